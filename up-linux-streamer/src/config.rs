@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
+use up_rust::UMessageType;
+use up_streamer::{
+    AccessDecision, AccessPolicy, AccessRule, ScriptLimits, UUriPattern, WasmFilterLimits,
+};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
@@ -13,6 +18,127 @@ pub struct Config {
 #[serde(deny_unknown_fields)]
 pub struct UpStreamerConfig {
     pub(crate) message_queue_size: u16,
+    #[serde(default)]
+    pub(crate) access_policy: Option<AccessPolicyConfig>,
+    #[serde(default)]
+    pub(crate) strict_source_authority: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AccessPolicyConfig {
+    pub(crate) default_decision: AccessDecisionConfig,
+    #[serde(default)]
+    pub(crate) rules: Vec<AccessRuleConfig>,
+}
+
+impl AccessPolicyConfig {
+    pub(crate) fn to_access_policy(&self) -> AccessPolicy {
+        self.rules.iter().fold(
+            AccessPolicy::new(self.default_decision.into()),
+            |access_policy, rule| access_policy.with_rule(rule.to_access_rule()),
+        )
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub enum AccessDecisionConfig {
+    Allow,
+    Deny,
+}
+
+impl From<AccessDecisionConfig> for AccessDecision {
+    fn from(decision: AccessDecisionConfig) -> Self {
+        match decision {
+            AccessDecisionConfig::Allow => AccessDecision::Allow,
+            AccessDecisionConfig::Deny => AccessDecision::Deny,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRuleConfig {
+    pub(crate) decision: AccessDecisionConfig,
+    #[serde(default)]
+    pub(crate) source: UUriPatternConfig,
+    #[serde(default)]
+    pub(crate) sink: UUriPatternConfig,
+    #[serde(default)]
+    pub(crate) message_types: Vec<MessageTypeConfig>,
+}
+
+impl AccessRuleConfig {
+    fn to_access_rule(&self) -> AccessRule {
+        AccessRule {
+            decision: self.decision.into(),
+            source: self.source.to_uuri_pattern(),
+            sink: self.sink.to_uuri_pattern(),
+            message_types: self
+                .message_types
+                .iter()
+                .map(|message_type| (*message_type).into())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UUriPatternConfig {
+    #[serde(default = "any_authority")]
+    pub(crate) authority: String,
+    #[serde(default)]
+    pub(crate) ue_id: Option<u32>,
+    #[serde(default)]
+    pub(crate) ue_version_major: Option<u32>,
+    #[serde(default)]
+    pub(crate) resource_id: Option<u32>,
+}
+
+fn any_authority() -> String {
+    "*".to_string()
+}
+
+impl Default for UUriPatternConfig {
+    fn default() -> Self {
+        Self {
+            authority: any_authority(),
+            ue_id: None,
+            ue_version_major: None,
+            resource_id: None,
+        }
+    }
+}
+
+impl UUriPatternConfig {
+    fn to_uuri_pattern(&self) -> UUriPattern {
+        UUriPattern {
+            authority: self.authority.clone(),
+            ue_id: self.ue_id,
+            ue_version_major: self.ue_version_major,
+            resource_id: self.resource_id,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub enum MessageTypeConfig {
+    Publish,
+    Notification,
+    Request,
+    Response,
+}
+
+impl From<MessageTypeConfig> for UMessageType {
+    fn from(message_type: MessageTypeConfig) -> Self {
+        match message_type {
+            MessageTypeConfig::Publish => UMessageType::UMESSAGE_TYPE_PUBLISH,
+            MessageTypeConfig::Notification => UMessageType::UMESSAGE_TYPE_NOTIFICATION,
+            MessageTypeConfig::Request => UMessageType::UMESSAGE_TYPE_REQUEST,
+            MessageTypeConfig::Response => UMessageType::UMESSAGE_TYPE_RESPONSE,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -29,6 +155,67 @@ pub struct SomeipConfig {
     pub(crate) config_file: PathBuf,
     pub(crate) default_someip_application_id_for_someip_subscriptions: u16,
     pub(crate) enabled: bool,
+    #[serde(default)]
+    pub(crate) mechatronics_to_host_script: Option<ScriptConfig>,
+    #[serde(default)]
+    pub(crate) host_to_mechatronics_script: Option<ScriptConfig>,
+    #[serde(default)]
+    pub(crate) mechatronics_to_host_wasm_filter: Option<WasmFilterConfig>,
+    #[serde(default)]
+    pub(crate) host_to_mechatronics_wasm_filter: Option<WasmFilterConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptConfig {
+    pub(crate) path: PathBuf,
+    #[serde(default)]
+    pub(crate) max_operations: Option<u64>,
+    #[serde(default)]
+    pub(crate) max_duration_ms: Option<u64>,
+    #[serde(default)]
+    pub(crate) max_messages: Option<usize>,
+}
+
+impl ScriptConfig {
+    pub(crate) fn to_script_limits(&self) -> ScriptLimits {
+        let default_limits = ScriptLimits::default();
+        ScriptLimits {
+            max_operations: self.max_operations.unwrap_or(default_limits.max_operations),
+            max_duration: self
+                .max_duration_ms
+                .map_or(default_limits.max_duration, Duration::from_millis),
+            max_messages: self.max_messages.unwrap_or(default_limits.max_messages),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WasmFilterConfig {
+    pub(crate) path: PathBuf,
+    #[serde(default)]
+    pub(crate) fuel: Option<u64>,
+    #[serde(default)]
+    pub(crate) max_memory_size: Option<usize>,
+    #[serde(default = "default_reload_interval_ms")]
+    pub(crate) reload_interval_ms: u64,
+}
+
+fn default_reload_interval_ms() -> u64 {
+    5000
+}
+
+impl WasmFilterConfig {
+    pub(crate) fn to_wasm_filter_limits(&self) -> WasmFilterLimits {
+        let default_limits = WasmFilterLimits::default();
+        WasmFilterLimits {
+            fuel: self.fuel.unwrap_or(default_limits.fuel),
+            max_memory_size: self
+                .max_memory_size
+                .unwrap_or(default_limits.max_memory_size),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
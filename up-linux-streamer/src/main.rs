@@ -1,14 +1,16 @@
 mod config;
 
-use crate::config::{Config, HostTransport};
+use crate::config::{Config, HostTransport, ScriptConfig, WasmFilterConfig};
 use clap::Parser;
 use log::trace;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use std::{env, thread};
 use up_rust::{UCode, UStatus, UTransport};
-use up_streamer::{Endpoint, UStreamer};
+use up_streamer::{Endpoint, ForwardingRuleOptions, MessageScript, UStreamer, WasmFilter};
 use up_transport_vsomeip::UPTransportVsomeip;
 use up_transport_zenoh::UPClientZenoh;
 use zenoh::config::Config as ZenohConfig;
@@ -47,6 +49,10 @@ async fn main() -> Result<(), UStatus> {
         "up-linux-streamer",
         config.up_streamer_config.message_queue_size,
     );
+    if let Some(access_policy) = &config.up_streamer_config.access_policy {
+        streamer.set_access_policy(access_policy.to_access_policy());
+    }
+    streamer.set_strict_source_authority(config.up_streamer_config.strict_source_authority);
 
     let zenoh_config = ZenohConfig::default();
     let host_transport: Arc<dyn UTransport> = Arc::new(match config.host_config.transport {
@@ -64,15 +70,7 @@ async fn main() -> Result<(), UStatus> {
     );
 
     if config.someip_config.enabled {
-        let someip_config_file_abs_path = if config.someip_config.config_file.is_relative() {
-            env::current_exe()
-                .unwrap()
-                .parent()
-                .unwrap()
-                .join(&config.someip_config.config_file)
-        } else {
-            config.someip_config.config_file
-        };
+        let someip_config_file_abs_path = abs_path(&config.someip_config.config_file);
         trace!("someip_config_file_abs_path: {someip_config_file_abs_path:?}");
         if !someip_config_file_abs_path.exists() {
             panic!(
@@ -100,7 +98,14 @@ async fn main() -> Result<(), UStatus> {
             someip_transport.clone(),
         );
         let forwarding_res = streamer
-            .add_forwarding_rule(mechatronics_endpoint.clone(), host_endpoint.clone())
+            .add_forwarding_rule_with_options(
+                mechatronics_endpoint.clone(),
+                host_endpoint.clone(),
+                forwarding_rule_options(
+                    &config.someip_config.mechatronics_to_host_script,
+                    &config.someip_config.mechatronics_to_host_wasm_filter,
+                ),
+            )
             .await;
 
         if let Err(err) = forwarding_res {
@@ -108,7 +113,14 @@ async fn main() -> Result<(), UStatus> {
         }
 
         let forwarding_res = streamer
-            .add_forwarding_rule(host_endpoint.clone(), mechatronics_endpoint.clone())
+            .add_forwarding_rule_with_options(
+                host_endpoint.clone(),
+                mechatronics_endpoint.clone(),
+                forwarding_rule_options(
+                    &config.someip_config.host_to_mechatronics_script,
+                    &config.someip_config.host_to_mechatronics_wasm_filter,
+                ),
+            )
             .await;
 
         if let Err(err) = forwarding_res {
@@ -120,3 +132,44 @@ async fn main() -> Result<(), UStatus> {
 
     Ok(())
 }
+
+// relative paths in the config are relative to the executable
+fn abs_path(path: &Path) -> PathBuf {
+    if path.is_relative() {
+        env::current_exe().unwrap().parent().unwrap().join(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+fn forwarding_rule_options(
+    script_config: &Option<ScriptConfig>,
+    wasm_filter_config: &Option<WasmFilterConfig>,
+) -> ForwardingRuleOptions {
+    let mut options = ForwardingRuleOptions::new();
+    if let Some(wasm_filter_config) = wasm_filter_config {
+        let wasm_filter_abs_path = abs_path(&wasm_filter_config.path);
+        trace!("wasm_filter_abs_path: {wasm_filter_abs_path:?}");
+        let mut wasm_filter = WasmFilter::from_file(
+            &wasm_filter_abs_path,
+            wasm_filter_config.to_wasm_filter_limits(),
+        )
+        .unwrap_or_else(|err| panic!("Invalid WASM filter: {wasm_filter_abs_path:?}: {err:?}"));
+        if wasm_filter_config.reload_interval_ms > 0 {
+            wasm_filter = wasm_filter
+                .with_hot_reload(Duration::from_millis(wasm_filter_config.reload_interval_ms));
+        }
+        options = options.with_wasm_filter(wasm_filter);
+    }
+    if let Some(script_config) = script_config {
+        let script_abs_path = abs_path(&script_config.path);
+        trace!("script_abs_path: {script_abs_path:?}");
+        let source = fs::read_to_string(&script_abs_path).unwrap_or_else(|err| {
+            panic!("Unable to read the specified script: {script_abs_path:?}: {err:?}")
+        });
+        let script = MessageScript::new(&source, script_config.to_script_limits())
+            .unwrap_or_else(|err| panic!("Invalid script: {script_abs_path:?}: {err:?}"));
+        options = options.with_script(script);
+    }
+    options
+}
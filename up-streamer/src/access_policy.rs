@@ -0,0 +1,296 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use crate::authority_pattern::authority_matches;
+use up_rust::{UMessage, UMessageType, UUri};
+
+/// Matches [`UUri`][up_rust::UUri]s for an [`AccessRule`]
+///
+/// `authority` may be an authority pattern, e.g. `zone-*`, the other fields match anything if
+/// `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UUriPattern {
+    pub authority: String,
+    pub ue_id: Option<u32>,
+    pub ue_version_major: Option<u32>,
+    pub resource_id: Option<u32>,
+}
+
+impl Default for UUriPattern {
+    fn default() -> Self {
+        Self::any()
+    }
+}
+
+impl UUriPattern {
+    /// A pattern matching any [`UUri`][up_rust::UUri], as well as none at all
+    pub fn any() -> Self {
+        Self {
+            authority: "*".to_string(),
+            ue_id: None,
+            ue_version_major: None,
+            resource_id: None,
+        }
+    }
+
+    /// A pattern matching any [`UUri`][up_rust::UUri] with an authority matching `authority`
+    pub fn authority(authority: &str) -> Self {
+        Self {
+            authority: authority.to_string(),
+            ..Self::any()
+        }
+    }
+
+    /// Restricts the pattern to the uE `ue_id`
+    pub fn with_ue_id(mut self, ue_id: u32) -> Self {
+        self.ue_id = Some(ue_id);
+        self
+    }
+
+    /// Restricts the pattern to the resource `resource_id`
+    pub fn with_resource_id(mut self, resource_id: u32) -> Self {
+        self.resource_id = Some(resource_id);
+        self
+    }
+
+    fn is_any(&self) -> bool {
+        *self == Self::any()
+    }
+
+    fn matches(&self, uri: Option<&UUri>) -> bool {
+        let Some(uri) = uri else {
+            return self.is_any();
+        };
+        authority_matches(&self.authority, &uri.authority_name)
+            && self.ue_id.map_or(true, |ue_id| ue_id == uri.ue_id)
+            && self.ue_version_major.map_or(true, |ue_version_major| {
+                ue_version_major == uri.ue_version_major
+            })
+            && self
+                .resource_id
+                .map_or(true, |resource_id| resource_id == uri.resource_id)
+    }
+}
+
+/// Whether an [`AccessRule`] lets matching messages through
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessDecision {
+    Allow,
+    Deny,
+}
+
+/// A single rule of an [`AccessPolicy`], matching messages on their source and sink
+/// [`UUri`][up_rust::UUri] and their [`UMessageType`][up_rust::UMessageType]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessRule {
+    pub decision: AccessDecision,
+    pub source: UUriPattern,
+    pub sink: UUriPattern,
+    /// The message types the rule applies to, all of them if empty
+    pub message_types: Vec<UMessageType>,
+}
+
+impl AccessRule {
+    pub fn allow(source: UUriPattern, sink: UUriPattern) -> Self {
+        Self {
+            decision: AccessDecision::Allow,
+            source,
+            sink,
+            message_types: Vec::new(),
+        }
+    }
+
+    pub fn deny(source: UUriPattern, sink: UUriPattern) -> Self {
+        Self {
+            decision: AccessDecision::Deny,
+            source,
+            sink,
+            message_types: Vec::new(),
+        }
+    }
+
+    /// Restricts the rule to messages of the given types
+    pub fn for_message_types(mut self, message_types: &[UMessageType]) -> Self {
+        self.message_types = message_types.to_vec();
+        self
+    }
+
+    fn matches(&self, msg: &UMessage) -> bool {
+        let Some(attributes) = msg.attributes.as_ref() else {
+            return self.source.is_any() && self.sink.is_any() && self.message_types.is_empty();
+        };
+        (self.message_types.is_empty()
+            || self
+                .message_types
+                .contains(&attributes.type_.enum_value_or_default()))
+            && self.source.matches(attributes.source.as_ref())
+            && self.sink.matches(attributes.sink.as_ref())
+    }
+}
+
+/// Decides which messages a [`UStreamer`][crate::UStreamer] forwards at all, set with
+/// [`UStreamer::set_access_policy`][crate::UStreamer::set_access_policy]
+///
+/// Rules are checked in the order they were added and the first matching one decides. Messages
+/// matched by no rule get the policy's default decision.
+///
+/// # Examples
+///
+/// ```
+/// use up_rust::UMessageType;
+/// use up_streamer::{AccessDecision, AccessPolicy, AccessRule, UUriPattern};
+///
+/// // only the uEs 0x1101 and 0x1102 on the host may send requests into the mechatronics network
+/// let access_policy = AccessPolicy::new(AccessDecision::Allow)
+///     .with_rule(
+///         AccessRule::allow(
+///             UUriPattern::authority("linux").with_ue_id(0x1101),
+///             UUriPattern::authority("me_authority"),
+///         )
+///         .for_message_types(&[UMessageType::UMESSAGE_TYPE_REQUEST]),
+///     )
+///     .with_rule(
+///         AccessRule::allow(
+///             UUriPattern::authority("linux").with_ue_id(0x1102),
+///             UUriPattern::authority("me_authority"),
+///         )
+///         .for_message_types(&[UMessageType::UMESSAGE_TYPE_REQUEST]),
+///     )
+///     .with_rule(
+///         AccessRule::deny(UUriPattern::any(), UUriPattern::authority("me_authority"))
+///             .for_message_types(&[UMessageType::UMESSAGE_TYPE_REQUEST]),
+///     );
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessPolicy {
+    rules: Vec<AccessRule>,
+    default_decision: AccessDecision,
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        Self::new(AccessDecision::Allow)
+    }
+}
+
+impl AccessPolicy {
+    pub fn new(default_decision: AccessDecision) -> Self {
+        Self {
+            rules: Vec::new(),
+            default_decision,
+        }
+    }
+
+    pub fn with_rule(mut self, rule: AccessRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Returns whether `msg` may be forwarded
+    pub fn decide(&self, msg: &UMessage) -> AccessDecision {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(msg))
+            .map_or(self.default_decision, |rule| rule.decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessDecision, AccessPolicy, AccessRule, UUriPattern};
+    use up_rust::{UAttributes, UMessage, UMessageType, UUri};
+
+    fn message(source: (&str, u32), sink: (&str, u32), message_type: UMessageType) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: message_type.into(),
+                source: Some(UUri {
+                    authority_name: source.0.to_string(),
+                    ue_id: source.1,
+                    ..Default::default()
+                })
+                .into(),
+                sink: Some(UUri {
+                    authority_name: sink.0.to_string(),
+                    ue_id: sink.1,
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_first_matching_rule_decides() {
+        let access_policy = AccessPolicy::new(AccessDecision::Allow)
+            .with_rule(
+                AccessRule::allow(
+                    UUriPattern::authority("linux").with_ue_id(0x1101),
+                    UUriPattern::authority("me_*"),
+                )
+                .for_message_types(&[UMessageType::UMESSAGE_TYPE_REQUEST]),
+            )
+            .with_rule(
+                AccessRule::deny(UUriPattern::any(), UUriPattern::authority("me_*"))
+                    .for_message_types(&[UMessageType::UMESSAGE_TYPE_REQUEST]),
+            );
+
+        let request = UMessageType::UMESSAGE_TYPE_REQUEST;
+        assert_eq!(
+            access_policy.decide(&message(("linux", 0x1101), ("me_authority", 0x4), request)),
+            AccessDecision::Allow
+        );
+        assert_eq!(
+            access_policy.decide(&message(("linux", 0x1102), ("me_authority", 0x4), request)),
+            AccessDecision::Deny
+        );
+        // only requests are restricted
+        assert_eq!(
+            access_policy.decide(&message(
+                ("linux", 0x1102),
+                ("me_authority", 0x4),
+                UMessageType::UMESSAGE_TYPE_NOTIFICATION
+            )),
+            AccessDecision::Allow
+        );
+    }
+
+    #[test]
+    fn test_default_decision_applies_when_no_rule_matches() {
+        let access_policy = AccessPolicy::new(AccessDecision::Deny).with_rule(AccessRule::allow(
+            UUriPattern::authority("linux"),
+            UUriPattern::any(),
+        ));
+
+        let notification = UMessageType::UMESSAGE_TYPE_NOTIFICATION;
+        assert_eq!(
+            access_policy.decide(&message(
+                ("linux", 0x1),
+                ("me_authority", 0x2),
+                notification
+            )),
+            AccessDecision::Allow
+        );
+        assert_eq!(
+            access_policy.decide(&message(
+                ("cloud", 0x1),
+                ("me_authority", 0x2),
+                notification
+            )),
+            AccessDecision::Deny
+        );
+    }
+}
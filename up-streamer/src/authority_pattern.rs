@@ -0,0 +1,135 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use std::cmp::Reverse;
+
+// authority patterns are glob-style: `*` matches any run of characters (including none) and
+// `?` matches exactly one character, everything else must match literally
+const ANY_SEQUENCE: char = '*';
+const ANY_CHARACTER: char = '?';
+
+/// Returns whether `authority` is a pattern rather than a single, literal `authority_name`
+pub(crate) fn is_authority_pattern(authority: &str) -> bool {
+    authority.contains(ANY_SEQUENCE) || authority.contains(ANY_CHARACTER)
+}
+
+/// Returns whether `authority_name` is matched by the glob-style `pattern`
+pub(crate) fn authority_matches(pattern: &str, authority_name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let authority_name: Vec<char> = authority_name.chars().collect();
+
+    let (mut p, mut a) = (0, 0);
+    // position in the pattern of the last `*` seen and the position in the authority_name it
+    // was tried against, so that we can backtrack and let it absorb one more character
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while a < authority_name.len() {
+        match pattern.get(p) {
+            Some(&ANY_SEQUENCE) => {
+                backtrack = Some((p, a));
+                p += 1;
+            }
+            Some(&c) if c == ANY_CHARACTER || c == authority_name[a] => {
+                p += 1;
+                a += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_a)) => {
+                    p = star_p + 1;
+                    a = star_a + 1;
+                    backtrack = Some((star_p, star_a + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == ANY_SEQUENCE)
+}
+
+/// Returns the one of `patterns` which matches `authority_name` most specifically, if any does
+///
+/// A literal `authority_name` is more specific than any pattern, and a pattern is the more
+/// specific the fewer characters its `*`s may stand in for. Ties go to the pattern first in
+/// lexicographic order, so that there's always a single one.
+pub(crate) fn most_specific_match<'a>(
+    patterns: impl IntoIterator<Item = &'a str>,
+    authority_name: &str,
+) -> Option<&'a str> {
+    patterns
+        .into_iter()
+        .filter(|pattern| authority_matches(pattern, authority_name))
+        .max_by_key(|pattern| {
+            (
+                !is_authority_pattern(pattern),
+                pattern.chars().filter(|&c| c != ANY_SEQUENCE).count(),
+                Reverse(*pattern),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{authority_matches, is_authority_pattern, most_specific_match};
+
+    #[test]
+    fn test_literal_authority_only_matches_itself() {
+        assert!(!is_authority_pattern("zone-1"));
+        assert!(authority_matches("zone-1", "zone-1"));
+        assert!(!authority_matches("zone-1", "zone-10"));
+        assert!(!authority_matches("zone-1", "zone-"));
+    }
+
+    #[test]
+    fn test_prefix_and_suffix_patterns() {
+        assert!(is_authority_pattern("zone-*"));
+        assert!(authority_matches("zone-*", "zone-"));
+        assert!(authority_matches("zone-*", "zone-front-left"));
+        assert!(!authority_matches("zone-*", "zonal-1"));
+
+        assert!(authority_matches(
+            "*.vehicle.example",
+            "ecu1.vehicle.example"
+        ));
+        assert!(authority_matches(
+            "*.vehicle.example",
+            "a.b.vehicle.example"
+        ));
+        assert!(!authority_matches("*.vehicle.example", "vehicle.example"));
+    }
+
+    #[test]
+    fn test_single_character_and_backtracking_patterns() {
+        assert!(is_authority_pattern("zone-?"));
+        assert!(authority_matches("zone-?", "zone-3"));
+        assert!(!authority_matches("zone-?", "zone-31"));
+
+        assert!(authority_matches("*-zone-*-ecu", "rear-zone-zone-2-ecu"));
+        assert!(!authority_matches("*-zone-*-ecu", "rear-zone-2-ecu-1"));
+        assert!(authority_matches("*", ""));
+        assert!(authority_matches("*", "anything"));
+    }
+
+    #[test]
+    fn test_most_specific_match() {
+        let patterns = ["*", "zone-*", "zone-?", "zone-1", "*-1"];
+        assert_eq!(most_specific_match(patterns, "zone-1"), Some("zone-1"));
+        assert_eq!(most_specific_match(patterns, "zone-2"), Some("zone-?"));
+        assert_eq!(most_specific_match(patterns, "zone-12"), Some("zone-*"));
+        assert_eq!(most_specific_match(patterns, "rear-1"), Some("*-1"));
+        assert_eq!(most_specific_match(patterns, "cloud"), Some("*"));
+        assert_eq!(most_specific_match(["zone-*"], "cloud"), None);
+        // equally specific patterns are decided between all the same
+        assert_eq!(most_specific_match(["*-2", "z*"], "zone-2"), Some("*-2"));
+    }
+}
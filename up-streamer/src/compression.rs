@@ -0,0 +1,177 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+// a compressed message carries the COMPRESSED_PAYLOAD_FORMAT as payload_format, so that nothing
+// mistakes it for its original payload, and a payload made of a header, all big-endian, followed
+// by the zstd compressed original payload:
+//
+// | original payload_format: i32 | original payload length: u32 |
+//
+// the peer streamer restores the original message before forwarding it on
+
+use protobuf::EnumOrUnknown;
+use up_rust::UMessage;
+
+// well outside of the range used by UPayloadFormat, next to the FRAGMENT_PAYLOAD_FORMAT
+pub(crate) const COMPRESSED_PAYLOAD_FORMAT: i32 = 0x7F02;
+const COMPRESSION_HEADER_SIZE: usize = 4 + 4;
+
+/// Compresses the payloads a forwarding rule forwards, used with
+/// [`ForwardingRuleOptions::with_compression`][crate::ForwardingRuleOptions::with_compression]
+///
+/// Meant for rules crossing a constrained link to a peer [`UStreamer`][crate::UStreamer], which
+/// decompresses them again before forwarding them on.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::CompressionConfig;
+///
+/// // squeeze hard, but leave small messages alone
+/// let compression_config = CompressionConfig {
+///     level: 19,
+///     min_size: 1024,
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// The zstd compression level, from `1` (fastest) to `22` (smallest), `0` picks zstd's
+    /// default
+    pub level: i32,
+    /// Payloads smaller than this many bytes are forwarded as they are
+    pub min_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            level: 3,
+            min_size: 256,
+        }
+    }
+}
+
+pub(crate) fn is_compressed(msg: &UMessage) -> bool {
+    msg.attributes
+        .as_ref()
+        .is_some_and(|attributes| attributes.payload_format.value() == COMPRESSED_PAYLOAD_FORMAT)
+}
+
+/// Returns `msg` with its payload compressed, or `None` if it is below `min_size` or doesn't
+/// shrink
+pub(crate) fn compress(msg: &UMessage, config: &CompressionConfig) -> Option<UMessage> {
+    let attributes = msg.attributes.as_ref()?;
+    let payload = msg.payload.as_ref()?;
+    if payload.len() < config.min_size {
+        return None;
+    }
+    let original_len = u32::try_from(payload.len()).ok()?;
+    let compressed = zstd::bulk::compress(payload, config.level).ok()?;
+    if COMPRESSION_HEADER_SIZE + compressed.len() >= payload.len() {
+        return None;
+    }
+
+    let mut compressed_payload = Vec::with_capacity(COMPRESSION_HEADER_SIZE + compressed.len());
+    compressed_payload.extend_from_slice(&attributes.payload_format.value().to_be_bytes());
+    compressed_payload.extend_from_slice(&original_len.to_be_bytes());
+    compressed_payload.extend_from_slice(&compressed);
+
+    let mut compressed_msg = msg.clone();
+    compressed_msg
+        .attributes
+        .mut_or_insert_default()
+        .payload_format = EnumOrUnknown::from_i32(COMPRESSED_PAYLOAD_FORMAT);
+    compressed_msg.payload = Some(compressed_payload.into());
+    Some(compressed_msg)
+}
+
+/// Restores the original of a message compressed by [`compress`], as long as its payload is no
+/// larger than `max_size`, which guards against payloads decompressing to far more than they let on
+pub(crate) fn decompress(mut msg: UMessage, max_size: usize) -> Result<UMessage, &'static str> {
+    let payload = msg.payload.take().unwrap_or_default();
+    let header = payload
+        .get(..COMPRESSION_HEADER_SIZE)
+        .ok_or("malformed compression header")?;
+    let payload_format = i32::from_be_bytes(header[0..4].try_into().unwrap());
+    let original_len = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    if original_len > max_size {
+        return Err("decompressed payload would be too large");
+    }
+
+    let decompressed = zstd::bulk::decompress(&payload[COMPRESSION_HEADER_SIZE..], original_len)
+        .map_err(|_| "corrupt compressed payload")?;
+    if decompressed.len() != original_len {
+        return Err("decompressed payload has the wrong length");
+    }
+
+    msg.attributes.mut_or_insert_default().payload_format = EnumOrUnknown::from_i32(payload_format);
+    msg.payload = Some(decompressed.into());
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress, is_compressed, CompressionConfig};
+    use up_rust::{UAttributes, UMessage, UPayloadFormat};
+
+    fn message_with_payload(payload: Vec<u8>) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                payload_format: UPayloadFormat::UPAYLOAD_FORMAT_JSON.into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(payload.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compressed_message_round_trips() {
+        let msg = message_with_payload(br#"{"speed": 42}"#.repeat(100));
+        let compressed = compress(&msg, &CompressionConfig::default()).unwrap();
+        assert!(is_compressed(&compressed));
+        assert!(compressed.payload.as_ref().unwrap().len() < msg.payload.as_ref().unwrap().len());
+
+        assert_eq!(decompress(compressed, 1300).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_payloads_decompressing_beyond_the_limit_are_refused() {
+        let msg = message_with_payload(br#"{"speed": 42}"#.repeat(100));
+        let compressed = compress(&msg, &CompressionConfig::default()).unwrap();
+
+        assert!(decompress(compressed, 1299).is_err());
+    }
+
+    #[test]
+    fn test_small_or_incompressible_payloads_are_left_alone() {
+        let config = CompressionConfig {
+            min_size: 64,
+            ..Default::default()
+        };
+        assert!(compress(&message_with_payload(vec![0u8; 63]), &config).is_none());
+
+        // xorshift noise, which zstd can't do anything with
+        let mut state = 0x2545_F491_u32;
+        let incompressible: Vec<u8> = (0..1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert!(compress(&message_with_payload(incompressible), &config).is_none());
+    }
+}
@@ -0,0 +1,317 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use up_rust::{UCode, UMessage, UMessageType, UStatus, UUri};
+
+/// Conflates the publish and notification messages a forwarding rule forwards, used with
+/// [`ForwardingRuleOptions::with_conflation`][crate::ForwardingRuleOptions::with_conflation]
+///
+/// Messages are keyed by their source and sink, and each key is forwarded at most
+/// `messages_per_second` times. A message arriving sooner is held back in place of any message
+/// held back before it, so that what's eventually forwarded is always the newest value. Unlike
+/// dropping from a full queue, this keeps a slow link carrying the freshest state of every signal.
+/// A rate of `0` would hold back every message for good, so a rule with one can't be added.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::ConflationConfig;
+///
+/// // a 100 Hz wheel speed signal arrives 10 times a second
+/// let conflation_config = ConflationConfig {
+///     messages_per_second: 10,
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConflationConfig {
+    /// Messages forwarded per second for each source and sink
+    pub messages_per_second: u32,
+}
+
+impl Default for ConflationConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 10,
+        }
+    }
+}
+
+/// What became of a message offered to a [`Conflator`]
+#[derive(Debug, PartialEq)]
+pub(crate) enum Conflation {
+    /// The message may be forwarded right away
+    Forward(UMessage),
+    /// The message is held back until its key is due again
+    Held,
+    /// The message is held back in place of an older one, which won't be forwarded
+    Superseded,
+}
+
+// the parts of a UUri which tell one source or sink from another
+pub(crate) type UriKey = (String, u32, u32, u32);
+
+pub(crate) fn uri_key(uri: Option<&UUri>) -> UriKey {
+    uri.map_or_else(UriKey::default, |uri| {
+        (
+            uri.authority_name.clone(),
+            uri.ue_id,
+            uri.ue_version_major,
+            uri.resource_id,
+        )
+    })
+}
+
+// slots aren't swept for keys which went quiet before there are at least this many
+const MIN_SLOTS_TO_SWEEP: usize = 64;
+
+type ConflationKey = (UriKey, UriKey);
+
+struct ConflationSlot {
+    last_forwarded: Instant,
+    pending: Option<UMessage>,
+}
+
+#[derive(Default)]
+struct ConflationState {
+    slots: HashMap<ConflationKey, ConflationSlot>,
+    // the keys holding back a message, by when it's due
+    due: BTreeSet<(Instant, ConflationKey)>,
+    // how many slots there may be before those of keys which went quiet are swept
+    sweep_at: usize,
+}
+
+// the latest message held back for each source and sink of a single forwarding rule
+pub(crate) struct Conflator {
+    interval: Duration,
+    state: Mutex<ConflationState>,
+}
+
+impl Conflator {
+    /// Fails with [`UCode::INVALID_ARGUMENT`] if `messages_per_second` is `0`
+    pub(crate) fn new(config: &ConflationConfig) -> Result<Self, UStatus> {
+        if config.messages_per_second == 0 {
+            return Err(UStatus::fail_with_code(
+                UCode::INVALID_ARGUMENT,
+                "Conflation to 0 messages per second",
+            ));
+        }
+        Ok(Self {
+            interval: Duration::from_secs(1) / config.messages_per_second,
+            state: Mutex::new(ConflationState {
+                sweep_at: MIN_SLOTS_TO_SWEEP,
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// Lets `msg` through if its key is due, otherwise holds it back; messages other than
+    /// publishes and notifications always go through
+    pub(crate) fn offer(&self, msg: UMessage) -> Conflation {
+        self.offer_at(msg, Instant::now())
+    }
+
+    fn offer_at(&self, msg: UMessage, now: Instant) -> Conflation {
+        let Some(attributes) = msg.attributes.as_ref() else {
+            return Conflation::Forward(msg);
+        };
+        if !matches!(
+            attributes.type_.enum_value_or_default(),
+            UMessageType::UMESSAGE_TYPE_PUBLISH | UMessageType::UMESSAGE_TYPE_NOTIFICATION
+        ) {
+            return Conflation::Forward(msg);
+        }
+        let key = (
+            uri_key(attributes.source.as_ref()),
+            uri_key(attributes.sink.as_ref()),
+        );
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let Some(slot) = state.slots.get_mut(&key) else {
+            state.slots.insert(
+                key,
+                ConflationSlot {
+                    last_forwarded: now,
+                    pending: None,
+                },
+            );
+            self.sweep(state, now);
+            return Conflation::Forward(msg);
+        };
+        if slot.pending.is_none() && now.duration_since(slot.last_forwarded) >= self.interval {
+            slot.last_forwarded = now;
+            return Conflation::Forward(msg);
+        }
+        match slot.pending.replace(msg) {
+            Some(_) => Conflation::Superseded,
+            None => {
+                state.due.insert((slot.last_forwarded + self.interval, key));
+                Conflation::Held
+            }
+        }
+    }
+
+    // forgets the keys which went quiet, so that their next message goes through right away and
+    // keys which come and go don't pile up, looking only once their slots have doubled
+    fn sweep(&self, state: &mut ConflationState, now: Instant) {
+        if state.slots.len() < state.sweep_at {
+            return;
+        }
+        state.slots.retain(|_, slot| {
+            slot.pending.is_some() || now.duration_since(slot.last_forwarded) < self.interval
+        });
+        state.sweep_at = (state.slots.len() * 2).max(MIN_SLOTS_TO_SWEEP);
+    }
+
+    /// When the next message held back is due, if there is any
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        let state = self.state.lock().unwrap();
+        state.due.first().map(|(due, _)| *due)
+    }
+
+    /// Takes the held back messages which are due
+    pub(crate) fn take_due(&self) -> Vec<UMessage> {
+        self.take_due_at(Instant::now())
+    }
+
+    fn take_due_at(&self, now: Instant) -> Vec<UMessage> {
+        let mut due = Vec::new();
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        while state.due.first().is_some_and(|(due_at, _)| *due_at <= now) {
+            let Some((_, key)) = state.due.pop_first() else {
+                break;
+            };
+            let Some(slot) = state.slots.get_mut(&key) else {
+                continue;
+            };
+            if let Some(pending) = slot.pending.take() {
+                due.push(pending);
+                slot.last_forwarded = now;
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conflation, ConflationConfig, Conflator};
+    use std::time::{Duration, Instant};
+    use up_rust::{UAttributes, UMessage, UMessageType, UUri};
+
+    fn signal(resource_id: u32, value: u8) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_PUBLISH.into(),
+                source: Some(UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: 0x1234,
+                    ue_version_major: 1,
+                    resource_id,
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(vec![value].into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_newest_value_per_key_is_forwarded_once_due() {
+        let conflator = Conflator::new(&ConflationConfig {
+            messages_per_second: 10,
+        })
+        .unwrap();
+        let start = Instant::now();
+
+        assert_eq!(
+            conflator.offer_at(signal(0x8001, 1), start),
+            Conflation::Forward(signal(0x8001, 1))
+        );
+        assert_eq!(
+            conflator.offer_at(signal(0x8001, 2), start + Duration::from_millis(10)),
+            Conflation::Held
+        );
+        assert_eq!(
+            conflator.offer_at(signal(0x8001, 3), start + Duration::from_millis(20)),
+            Conflation::Superseded
+        );
+        // another key isn't held back by the first one
+        assert_eq!(
+            conflator.offer_at(signal(0x8002, 1), start + Duration::from_millis(20)),
+            Conflation::Forward(signal(0x8002, 1))
+        );
+
+        assert_eq!(
+            conflator.next_due(),
+            Some(start + Duration::from_millis(100))
+        );
+        assert!(conflator
+            .take_due_at(start + Duration::from_millis(90))
+            .is_empty());
+        assert_eq!(
+            conflator.take_due_at(start + Duration::from_millis(100)),
+            vec![signal(0x8001, 3)]
+        );
+        assert_eq!(conflator.next_due(), None);
+    }
+
+    #[test]
+    fn test_requests_pass_and_quiet_keys_are_forgotten() {
+        let conflator = Conflator::new(&ConflationConfig::default()).unwrap();
+        let start = Instant::now();
+
+        let mut request = signal(0x8001, 1);
+        request.attributes.mut_or_insert_default().type_ =
+            UMessageType::UMESSAGE_TYPE_REQUEST.into();
+        assert!(matches!(
+            conflator.offer_at(request.clone(), start),
+            Conflation::Forward(_)
+        ));
+        assert!(matches!(
+            conflator.offer_at(request, start),
+            Conflation::Forward(_)
+        ));
+
+        // keys which went quiet are forgotten once enough of them have piled up
+        for resource_id in 0..64 {
+            assert!(matches!(
+                conflator.offer_at(signal(resource_id, 1), start),
+                Conflation::Forward(_)
+            ));
+        }
+        assert_eq!(conflator.state.lock().unwrap().slots.len(), 64);
+        for resource_id in 64..128 {
+            assert!(matches!(
+                conflator.offer_at(signal(resource_id, 1), start + Duration::from_secs(1)),
+                Conflation::Forward(_)
+            ));
+        }
+        assert_eq!(conflator.state.lock().unwrap().slots.len(), 64);
+    }
+
+    #[test]
+    fn test_a_rate_of_zero_is_rejected() {
+        assert!(Conflator::new(&ConflationConfig {
+            messages_per_second: 0,
+        })
+        .is_err());
+    }
+}
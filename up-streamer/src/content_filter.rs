@@ -0,0 +1,658 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+// the expression language, from loosest to tightest binding:
+//
+// expression := and ( "||" and )*
+// and        := unary ( "&&" unary )*
+// unary      := "!" unary | "(" expression ")" | comparison
+// comparison := path ( "==" | "!=" | "<" | "<=" | ">" | ">=" | "~" ) literal
+// path       := identifier ( "." identifier )*
+// literal    := "string" | 'string' | number | true | false
+//
+// "!" and parentheses may only nest MAX_NESTING_DEPTH deep, so that neither parsing nor evaluating
+// an expression can run out of stack
+
+use crate::authority_pattern::authority_matches;
+use crate::descriptor_registry::DescriptorRegistry;
+use protobuf::reflect::{ReflectValueRef, RuntimeFieldType};
+use protobuf::MessageDyn;
+use up_rust::{UCode, UMessage, UPayloadFormat, UStatus};
+
+const MAX_NESTING_DEPTH: usize = 32;
+
+/// A condition on the payload of a message, used with
+/// [`ForwardingRuleOptions::with_content_filter`][crate::ForwardingRuleOptions::with_content_filter]
+/// and
+/// [`ForwardingRuleOptions::with_content_routing`][crate::ForwardingRuleOptions::with_content_routing]
+///
+/// Compares fields, addressed by their dot separated path, to literals, e.g.
+/// `name ~ "Al*" && (speed >= 10.5 || !(gear == "PARK"))`. `~` matches a string against a
+/// glob-style pattern, in which `*` stands for any run of characters and `?` for any single one.
+///
+/// JSON payloads are looked into as they are. Protobuf payloads need their message type in the
+/// [`DescriptorRegistry`][crate::DescriptorRegistry] given with
+/// [`ContentFilter::with_descriptors`], and unless they are wrapped in an `Any` also
+/// [`ContentFilter::with_message_type`]. Enum fields compare by the name of their value.
+///
+/// A comparison with a field which isn't there, or holds a value of another type, is false, as
+/// is the whole filter for payloads which can't be looked into. `!` and parentheses may be nested
+/// up to 32 deep.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::{ContentFilter, DescriptorRegistry};
+///
+/// let content_filter = ContentFilter::new(r#"name ~ "Al*""#)
+///     .unwrap()
+///     .with_descriptors(DescriptorRegistry::new())
+///     .with_message_type("example.hello_world.v1.HelloRequest");
+/// ```
+#[derive(Clone)]
+pub struct ContentFilter {
+    expression: Expression,
+    descriptors: DescriptorRegistry,
+    message_type: Option<String>,
+}
+
+impl ContentFilter {
+    /// Parses `expression`, failing with [`UCode::INVALID_ARGUMENT`] if it isn't valid
+    pub fn new(expression: &str) -> Result<Self, UStatus> {
+        let expression = Parser::new(expression)
+            .and_then(|parser| parser.parse())
+            .map_err(|reason| {
+                UStatus::fail_with_code(
+                    UCode::INVALID_ARGUMENT,
+                    format!("Invalid content filter: {expression:?}, {reason}"),
+                )
+            })?;
+        Ok(Self {
+            expression,
+            descriptors: DescriptorRegistry::default(),
+            message_type: None,
+        })
+    }
+
+    /// Looks up the types of protobuf payloads in `descriptors`
+    pub fn with_descriptors(mut self, descriptors: DescriptorRegistry) -> Self {
+        self.descriptors = descriptors;
+        self
+    }
+
+    /// Takes protobuf payloads to be `message_type`, those wrapped in an `Any` of another type
+    /// don't match
+    pub fn with_message_type(mut self, message_type: &str) -> Self {
+        self.message_type = Some(message_type.to_string());
+        self
+    }
+
+    /// Whether the payload of `msg` meets the filter's condition, decoding it into
+    /// `decoded_payloads` unless another filter already did
+    pub(crate) fn matches(&self, msg: &UMessage, decoded_payloads: &mut DecodedPayloads) -> bool {
+        decoded_payloads
+            .decode(msg, self)
+            .is_some_and(|payload| self.expression.evaluate(payload))
+    }
+}
+
+// the payload of a single message as decoded for the content filters which look into it, so that
+// it's only decoded once however many of them do
+#[derive(Default)]
+pub(crate) struct DecodedPayloads {
+    json: Option<Option<Payload>>,
+    // keyed on the descriptors and message type protobuf payloads were decoded with
+    protobuf: Vec<((usize, Option<String>), Option<Payload>)>,
+}
+
+impl DecodedPayloads {
+    fn decode(&mut self, msg: &UMessage, content_filter: &ContentFilter) -> Option<&Payload> {
+        let attributes = msg.attributes.as_ref()?;
+        let payload = msg.payload.as_ref()?;
+        match attributes.payload_format.enum_value().ok()? {
+            UPayloadFormat::UPAYLOAD_FORMAT_JSON => self
+                .json
+                .get_or_insert_with(|| serde_json::from_slice(payload).ok().map(Payload::Json))
+                .as_ref(),
+            payload_format => {
+                let descriptors_id = content_filter.descriptors.id();
+                let message_type = content_filter.message_type.as_deref();
+                let index = match self.protobuf.iter().position(|((id, decoded_type), _)| {
+                    *id == descriptors_id && decoded_type.as_deref() == message_type
+                }) {
+                    Some(index) => index,
+                    None => {
+                        let decoded = content_filter
+                            .descriptors
+                            .parse(payload_format, payload, message_type)
+                            .map(Payload::Protobuf);
+                        self.protobuf
+                            .push(((descriptors_id, message_type.map(str::to_string)), decoded));
+                        self.protobuf.len() - 1
+                    }
+                };
+                self.protobuf[index].1.as_ref()
+            }
+        }
+    }
+}
+
+// a payload in a form whose fields can be looked up
+enum Payload {
+    Json(serde_json::Value),
+    Protobuf(Box<dyn MessageDyn>),
+}
+
+impl Payload {
+    fn field(&self, path: &[String]) -> Option<Value> {
+        match self {
+            Payload::Json(json) => json_field(json, path),
+            Payload::Protobuf(msg) => protobuf_field(msg.as_ref(), path),
+        }
+    }
+}
+
+fn json_field(json: &serde_json::Value, path: &[String]) -> Option<Value> {
+    let json = path
+        .iter()
+        .try_fold(json, |json, name| json.as_object()?.get(name))?;
+    match json {
+        serde_json::Value::String(string) => Some(Value::String(string.clone())),
+        serde_json::Value::Number(number) => number.as_f64().map(Value::Number),
+        serde_json::Value::Bool(boolean) => Some(Value::Bool(*boolean)),
+        _ => None,
+    }
+}
+
+fn protobuf_field(msg: &dyn MessageDyn, path: &[String]) -> Option<Value> {
+    let (name, rest) = path.split_first()?;
+    let field = msg.descriptor_dyn().field_by_name(name)?;
+    if !matches!(field.runtime_field_type(), RuntimeFieldType::Singular(_)) {
+        return None;
+    }
+    if !rest.is_empty() {
+        return match field.get_singular(msg)? {
+            ReflectValueRef::Message(nested) => protobuf_field(&*nested, rest),
+            _ => None,
+        };
+    }
+    match field.get_singular_field_or_default(msg) {
+        ReflectValueRef::U32(number) => Some(Value::Number(number.into())),
+        ReflectValueRef::U64(number) => Some(Value::Number(number as f64)),
+        ReflectValueRef::I32(number) => Some(Value::Number(number.into())),
+        ReflectValueRef::I64(number) => Some(Value::Number(number as f64)),
+        ReflectValueRef::F32(number) => Some(Value::Number(number.into())),
+        ReflectValueRef::F64(number) => Some(Value::Number(number)),
+        ReflectValueRef::Bool(boolean) => Some(Value::Bool(boolean)),
+        ReflectValueRef::String(string) => Some(Value::String(string.to_string())),
+        ReflectValueRef::Enum(descriptor, number) => descriptor
+            .value_by_number(number)
+            .map(|value| Value::String(value.name().to_string())),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Matches,
+}
+
+impl Operator {
+    fn apply(self, field: &Value, literal: &Value) -> bool {
+        match (self, field, literal) {
+            (Operator::Equal, field, literal) => field == literal,
+            (Operator::NotEqual, Value::String(_), Value::String(_))
+            | (Operator::NotEqual, Value::Number(_), Value::Number(_))
+            | (Operator::NotEqual, Value::Bool(_), Value::Bool(_)) => field != literal,
+            (Operator::Matches, Value::String(field), Value::String(pattern)) => {
+                authority_matches(pattern, field)
+            }
+            (operator, Value::Number(field), Value::Number(literal)) => {
+                operator.orders(field.partial_cmp(literal))
+            }
+            (operator, Value::String(field), Value::String(literal)) => {
+                operator.orders(Some(field.cmp(literal)))
+            }
+            _ => false,
+        }
+    }
+
+    fn orders(self, ordering: Option<std::cmp::Ordering>) -> bool {
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self {
+            Operator::Less => ordering.is_lt(),
+            Operator::LessOrEqual => ordering.is_le(),
+            Operator::Greater => ordering.is_gt(),
+            Operator::GreaterOrEqual => ordering.is_ge(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Comparison {
+        path: Vec<String>,
+        operator: Operator,
+        literal: Value,
+    },
+    Not(Box<Expression>),
+    And(Vec<Expression>),
+    Or(Vec<Expression>),
+}
+
+impl Expression {
+    fn evaluate(&self, payload: &Payload) -> bool {
+        match self {
+            Expression::Comparison {
+                path,
+                operator,
+                literal,
+            } => payload
+                .field(path)
+                .is_some_and(|field| operator.apply(&field, literal)),
+            Expression::Not(expression) => !expression.evaluate(payload),
+            Expression::And(expressions) => expressions
+                .iter()
+                .all(|expression| expression.evaluate(payload)),
+            Expression::Or(expressions) => expressions
+                .iter()
+                .any(|expression| expression.evaluate(payload)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Path(Vec<String>),
+    Literal(Value),
+    Operator(Operator),
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Operator(Operator::Equal), 2),
+            ('!', Some('=')) => (Token::Operator(Operator::NotEqual), 2),
+            ('<', Some('=')) => (Token::Operator(Operator::LessOrEqual), 2),
+            ('>', Some('=')) => (Token::Operator(Operator::GreaterOrEqual), 2),
+            ('<', _) => (Token::Operator(Operator::Less), 1),
+            ('>', _) => (Token::Operator(Operator::Greater), 1),
+            ('~', _) => (Token::Operator(Operator::Matches), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::OpenParen, 1),
+            (')', _) => (Token::CloseParen, 1),
+            ('"' | '\'', _) => {
+                let mut string = String::new();
+                let mut end = i + 1;
+                loop {
+                    match chars.get(end) {
+                        None => return Err(format!("unterminated string at {i}")),
+                        Some(&quote) if quote == c => break,
+                        Some('\\') => {
+                            let escaped = chars
+                                .get(end + 1)
+                                .ok_or_else(|| format!("unterminated string at {i}"))?;
+                            string.push(*escaped);
+                            end += 2;
+                        }
+                        Some(&other) => {
+                            string.push(other);
+                            end += 1;
+                        }
+                    }
+                }
+                (Token::Literal(Value::String(string)), end + 1 - i)
+            }
+            (c, _) if c.is_ascii_digit() || c == '-' => {
+                let len = chars[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+                    .count()
+                    + 1;
+                let number: String = chars[i..i + len].iter().collect();
+                let number = number
+                    .parse()
+                    .map_err(|_| format!("invalid number: {number} at {i}"))?;
+                (Token::Literal(Value::Number(number)), len)
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '.'))
+                    .count();
+                let word: String = chars[i..i + len].iter().collect();
+                let token = match word.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    _ => {
+                        let path: Vec<String> = word.split('.').map(str::to_string).collect();
+                        if path.iter().any(String::is_empty) {
+                            return Err(format!("invalid field path: {word} at {i}"));
+                        }
+                        Token::Path(path)
+                    }
+                };
+                (token, len)
+            }
+            (c, _) => return Err(format!("unexpected character: {c:?} at {i}")),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, String> {
+        Ok(Self {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        })
+    }
+
+    fn parse(mut self) -> Result<Expression, String> {
+        let expression = self.parse_or()?;
+        match self.tokens.get(self.position) {
+            None => Ok(expression),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, String> {
+        let mut expressions = vec![self.parse_and()?];
+        while self.next_is(&Token::Or) {
+            expressions.push(self.parse_and()?);
+        }
+        Ok(match expressions.len() {
+            1 => expressions.pop().unwrap(),
+            _ => Expression::Or(expressions),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, String> {
+        let mut expressions = vec![self.parse_unary()?];
+        while self.next_is(&Token::And) {
+            expressions.push(self.parse_unary()?);
+        }
+        Ok(match expressions.len() {
+            1 => expressions.pop().unwrap(),
+            _ => Expression::And(expressions),
+        })
+    }
+
+    // parses what a `!` or parentheses hold, as long as they aren't nested too deep
+    fn parse_nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Expression, String>,
+    ) -> Result<Expression, String> {
+        if self.depth == MAX_NESTING_DEPTH {
+            return Err(format!("nested deeper than {MAX_NESTING_DEPTH}"));
+        }
+        self.depth += 1;
+        let expression = parse(self);
+        self.depth -= 1;
+        expression
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expression::Not(Box::new(
+                self.parse_nested(Self::parse_unary)?,
+            ))),
+            Some(Token::OpenParen) => {
+                let expression = self.parse_nested(Self::parse_or)?;
+                if !self.next_is(&Token::CloseParen) {
+                    return Err("missing closing parenthesis".to_string());
+                }
+                Ok(expression)
+            }
+            Some(Token::Path(path)) => {
+                let Some(Token::Operator(operator)) = self.next() else {
+                    return Err(format!("expected an operator after {}", path.join(".")));
+                };
+                let Some(Token::Literal(literal)) = self.next() else {
+                    return Err(format!("expected a literal after {operator:?}"));
+                };
+                Ok(Expression::Comparison {
+                    path,
+                    operator,
+                    literal,
+                })
+            }
+            Some(token) => Err(format!("unexpected {token:?}")),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentFilter, DecodedPayloads};
+    use crate::descriptor_registry::tests::{hello_file_descriptor, hello_request};
+    use crate::DescriptorRegistry;
+    use protobuf::well_known_types::any::Any;
+    use protobuf::Message;
+    use up_rust::{UAttributes, UMessage, UPayloadFormat};
+
+    fn message(payload_format: UPayloadFormat, payload: Vec<u8>) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                payload_format: payload_format.into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(payload.into()),
+            ..Default::default()
+        }
+    }
+
+    fn json(payload: &str) -> UMessage {
+        message(
+            UPayloadFormat::UPAYLOAD_FORMAT_JSON,
+            payload.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_json_fields_are_compared() {
+        let content_filter = ContentFilter::new(
+            r#"name ~ "Al*" && (vehicle.speed >= 10.5 || !(vehicle.gear == 'PARK'))"#,
+        )
+        .unwrap();
+
+        assert!(content_filter.matches(
+            &json(r#"{"name": "Alice", "vehicle": {"speed": 12, "gear": "DRIVE"}}"#),
+            &mut DecodedPayloads::default()
+        ));
+        assert!(content_filter.matches(
+            &json(r#"{"name": "Alfred", "vehicle": {"speed": 0, "gear": "NEUTRAL"}}"#),
+            &mut DecodedPayloads::default()
+        ));
+        assert!(!content_filter.matches(
+            &json(r#"{"name": "Alfred", "vehicle": {"speed": 0, "gear": "PARK"}}"#),
+            &mut DecodedPayloads::default()
+        ));
+        assert!(!content_filter.matches(
+            &json(r#"{"name": "Bob", "vehicle": {"speed": 12}}"#),
+            &mut DecodedPayloads::default()
+        ));
+        // a string where a number is expected doesn't compare
+        assert!(!content_filter.matches(
+            &json(r#"{"name": "Alice", "vehicle": {"speed": "12", "gear": "PARK"}}"#),
+            &mut DecodedPayloads::default()
+        ));
+        assert!(!content_filter.matches(&json("not json"), &mut DecodedPayloads::default()));
+    }
+
+    #[test]
+    fn test_invalid_expressions_are_refused() {
+        for expression in [
+            "",
+            "name",
+            "name ==",
+            "name == other",
+            "(name == 'a'",
+            "name == 'a' &&",
+            "name == 'a",
+            "name..first == 'a'",
+            "name = 'a'",
+            "speed > 1x",
+            format!("{}name == 'a'", "!".repeat(33)).as_str(),
+            format!("{}name == 'a'{}", "(".repeat(33), ")".repeat(33)).as_str(),
+        ] {
+            assert!(
+                ContentFilter::new(expression).is_err(),
+                "accepted {expression:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_protobuf_fields_are_looked_up_by_descriptor() {
+        let file = hello_file_descriptor();
+        let hello_request = hello_request("Alice", 1);
+        let mut any = Any::new();
+        any.type_url = "type.googleapis.com/example.hello.HelloRequest".to_string();
+        any.value = hello_request.clone();
+        let any = any.write_to_bytes().unwrap();
+
+        let content_filter = ContentFilter::new("name == 'Alice'")
+            .unwrap()
+            .with_descriptors(DescriptorRegistry::new().with_file_descriptor(&file));
+        assert!(content_filter.matches(
+            &message(
+                UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF_WRAPPED_IN_ANY,
+                any.clone()
+            ),
+            &mut DecodedPayloads::default()
+        ));
+        // a plain protobuf payload doesn't say what it is
+        assert!(!content_filter.matches(
+            &message(
+                UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF,
+                hello_request.clone()
+            ),
+            &mut DecodedPayloads::default()
+        ));
+
+        let content_filter = content_filter.with_message_type("example.hello.HelloRequest");
+        assert!(content_filter.matches(
+            &message(UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF, hello_request),
+            &mut DecodedPayloads::default()
+        ));
+        assert!(!ContentFilter::new("name == 'Alice'")
+            .unwrap()
+            .with_descriptors(DescriptorRegistry::new().with_file_descriptor(&file))
+            .with_message_type("example.hello.HelloResponse")
+            .matches(
+                &message(UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF_WRAPPED_IN_ANY, any),
+                &mut DecodedPayloads::default()
+            ));
+    }
+
+    #[test]
+    fn test_expressions_nest_up_to_the_limit_and_chain_without_one() {
+        let nested = format!("{}name == 'a'{}", "(!".repeat(16), ")".repeat(16));
+        assert!(ContentFilter::new(&nested)
+            .unwrap()
+            .matches(&json(r#"{"name": "a"}"#), &mut DecodedPayloads::default()));
+
+        let chained = vec!["speed == 1"; 10_000].join(" || ") + " || speed == 2";
+        assert!(ContentFilter::new(&chained)
+            .unwrap()
+            .matches(&json(r#"{"speed": 2}"#), &mut DecodedPayloads::default()));
+    }
+
+    #[test]
+    fn test_payloads_are_decoded_once_for_all_filters() {
+        let file = hello_file_descriptor();
+        let descriptors = DescriptorRegistry::new().with_file_descriptor(&file);
+        let content_filters = [
+            ContentFilter::new("name == 'Bob'")
+                .unwrap()
+                .with_descriptors(descriptors.clone())
+                .with_message_type("example.hello.HelloRequest"),
+            ContentFilter::new("name == 'Alice'")
+                .unwrap()
+                .with_descriptors(descriptors)
+                .with_message_type("example.hello.HelloRequest"),
+        ];
+        let msg = message(
+            UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF,
+            hello_request("Alice", 1),
+        );
+
+        let mut decoded_payloads = DecodedPayloads::default();
+        assert_eq!(
+            content_filters
+                .iter()
+                .position(|content_filter| content_filter.matches(&msg, &mut decoded_payloads)),
+            Some(1)
+        );
+        assert_eq!(decoded_payloads.protobuf.len(), 1);
+    }
+}
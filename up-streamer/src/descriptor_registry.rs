@@ -0,0 +1,174 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use protobuf::descriptor::FileDescriptorSet;
+use protobuf::reflect::{FileDescriptor, MessageDescriptor};
+use protobuf::well_known_types::any::Any;
+use protobuf::{Message, MessageDyn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use up_rust::{UCode, UPayloadFormat, UStatus};
+
+/// The protobuf message types a [`UStreamer`][crate::UStreamer] may look into, by their fully
+/// qualified name, e.g. `example.hello_world.v1.HelloRequest`
+///
+/// Needed for [`ContentFilter`][crate::ContentFilter]s on protobuf payloads and
+/// [`PayloadTranslation`][crate::PayloadTranslation]s. Descriptors come
+/// either from generated code or from a `FileDescriptorSet`, as written by
+/// `protoc --include_imports --descriptor_set_out`.
+///
+/// # Examples
+///
+/// ```
+/// use protobuf::well_known_types::timestamp;
+/// use up_streamer::DescriptorRegistry;
+///
+/// let descriptor_registry =
+///     DescriptorRegistry::new().with_file_descriptor(timestamp::file_descriptor());
+/// ```
+#[derive(Clone, Default)]
+pub struct DescriptorRegistry {
+    messages: Arc<HashMap<String, MessageDescriptor>>,
+}
+
+impl DescriptorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the message types of `file_descriptor`, including nested ones
+    pub fn with_file_descriptor(mut self, file_descriptor: &FileDescriptor) -> Self {
+        let messages = Arc::make_mut(&mut self.messages);
+        let mut pending: Vec<MessageDescriptor> = file_descriptor.messages().collect();
+        while let Some(message) = pending.pop() {
+            pending.extend(message.nested_messages());
+            messages.insert(message.full_name().to_string(), message);
+        }
+        self
+    }
+
+    /// Adds the message types of every file in the serialized `FileDescriptorSet`, which has to
+    /// include the files they import
+    pub fn with_file_descriptor_set(self, file_descriptor_set: &[u8]) -> Result<Self, UStatus> {
+        let file_descriptor_set = FileDescriptorSet::parse_from_bytes(file_descriptor_set)
+            .map_err(|err| {
+                UStatus::fail_with_code(
+                    UCode::INVALID_ARGUMENT,
+                    format!("Unable to parse FileDescriptorSet: {err}"),
+                )
+            })?;
+        let file_descriptors = FileDescriptor::new_dynamic_fds(file_descriptor_set.file, &[])
+            .map_err(|err| {
+                UStatus::fail_with_code(
+                    UCode::INVALID_ARGUMENT,
+                    format!("Unable to build descriptors from FileDescriptorSet: {err}"),
+                )
+            })?;
+        Ok(file_descriptors
+            .iter()
+            .fold(self, |registry, file_descriptor| {
+                registry.with_file_descriptor(file_descriptor)
+            }))
+    }
+
+    /// Tells this registry, and its clones, apart from other ones
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.messages) as usize
+    }
+
+    /// The message type `full_name`, if it's known
+    pub(crate) fn message(&self, full_name: &str) -> Option<&MessageDescriptor> {
+        self.messages.get(full_name)
+    }
+
+    /// Parses a protobuf `payload`, plain ones as `message_type` and those wrapped in an `Any` as
+    /// the type it names, which has to be `message_type` if that's given
+    pub(crate) fn parse(
+        &self,
+        payload_format: UPayloadFormat,
+        payload: &[u8],
+        message_type: Option<&str>,
+    ) -> Option<Box<dyn MessageDyn>> {
+        match payload_format {
+            UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF => {
+                let descriptor = self.message(message_type?)?;
+                descriptor.parse_from_bytes(payload).ok()
+            }
+            // uProtocol takes an unspecified format to be protobuf wrapped in an Any
+            UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF_WRAPPED_IN_ANY
+            | UPayloadFormat::UPAYLOAD_FORMAT_UNSPECIFIED => {
+                let any = Any::parse_from_bytes(payload).ok()?;
+                let any_type = any.type_url.rsplit('/').next()?;
+                if message_type.is_some_and(|message_type| message_type != any_type) {
+                    return None;
+                }
+                let descriptor = self.message(any_type)?;
+                descriptor.parse_from_bytes(&any.value).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use protobuf::descriptor::field_descriptor_proto::{Label, Type};
+    use protobuf::descriptor::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto};
+    use protobuf::reflect::{FileDescriptor, ReflectValueBox};
+
+    /// `example.hello.HelloRequest`, with a string `name` and an int32 `count`
+    pub(crate) fn hello_file_descriptor() -> FileDescriptor {
+        let mut file = FileDescriptorProto::new();
+        file.set_name("hello.proto".to_string());
+        file.set_package("example.hello".to_string());
+        file.set_syntax("proto3".to_string());
+        let mut hello_request = DescriptorProto::new();
+        hello_request.set_name("HelloRequest".to_string());
+        for (number, name, type_) in [
+            (1, "name", Type::TYPE_STRING),
+            (2, "count", Type::TYPE_INT32),
+        ] {
+            let mut field = FieldDescriptorProto::new();
+            field.set_name(name.to_string());
+            field.set_json_name(name.to_string());
+            field.set_number(number);
+            field.set_type(type_);
+            field.set_label(Label::LABEL_OPTIONAL);
+            hello_request.field.push(field);
+        }
+        file.message_type.push(hello_request);
+        FileDescriptor::new_dynamic(file, &[]).unwrap()
+    }
+
+    /// A serialized `example.hello.HelloRequest`
+    pub(crate) fn hello_request(name: &str, count: i32) -> Vec<u8> {
+        let file = hello_file_descriptor();
+        let descriptor = file
+            .messages()
+            .find(|descriptor| descriptor.name() == "HelloRequest")
+            .unwrap();
+        let mut hello_request = descriptor.new_instance();
+        descriptor
+            .field_by_name("name")
+            .unwrap()
+            .set_singular_field(
+                &mut *hello_request,
+                ReflectValueBox::String(name.to_string()),
+            );
+        descriptor
+            .field_by_name("count")
+            .unwrap()
+            .set_singular_field(&mut *hello_request, ReflectValueBox::I32(count));
+        hello_request.write_to_bytes_dyn().unwrap()
+    }
+}
@@ -11,24 +11,57 @@
  * SPDX-License-Identifier: Apache-2.0
  ********************************************************************************/
 
+use crate::authority_pattern::authority_matches;
+use crate::replay_protection::{ReplayGuard, ReplayProtectionConfig};
+use crate::retained_values::{RetainedValues, RetainedValuesConfig};
 use async_std::sync::Arc;
 use log::*;
-use up_rust::UTransport;
+use up_rust::{UCode, UStatus, UTransport};
 
 const ENDPOINT_TAG: &str = "Endpoint:";
 const ENDPOINT_FN_NEW_TAG: &str = "new():";
+const ENDPOINT_FN_NEW_WITH_AUTHORITIES_TAG: &str = "new_with_authorities():";
+
+/// What happens to a message whose payload exceeds the `max_payload_size` of the out
+/// [`Endpoint`] it is to be sent on, see [`Endpoint::with_max_payload_size`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// The message is dropped and counted. If it's a request, its sender is sent a response with
+    /// [`UCode::RESOURCE_EXHAUSTED`][up_rust::UCode::RESOURCE_EXHAUSTED].
+    #[default]
+    Reject,
+    /// The message is split into fragments which fit, to be reassembled by the [`UStreamer`][crate::UStreamer]
+    /// on the other end of the out [`UTransport`][up_rust::UTransport]. Messages which can't be
+    /// fragmented, e.g. since `max_payload_size` leaves no room besides the fragment header, are
+    /// rejected as with [`OversizePolicy::Reject`].
+    Fragment,
+}
 
 ///
 /// [`Endpoint`] is defined as a combination of `authority_name` and
 /// [`Arc<Mutex<Box<dyn UTransport>>>`][up_rust::UTransport] as endpoints are at the authority level.
 ///
+/// The `authority_name` may also be a glob-style pattern, where `*` matches any run of characters
+/// and `?` matches a single character, e.g. `zone-*` or `*.vehicle.example`. Messages are then
+/// matched against the pattern at forwarding time, so that a single [`Endpoint`] can stand in for
+/// a variable number of authorities.
+///
+/// Where a single [`UTransport`][up_rust::UTransport] hosts several authorities, e.g. a SOME/IP
+/// segment or a Zenoh domain, an [`Endpoint`] can carry all of them with
+/// [`Endpoint::new_with_authorities`]. Listeners are then registered and messages routed for each
+/// of its authorities.
+///
+/// An [`Endpoint`] may also declare the largest payload its [`UTransport`][up_rust::UTransport]
+/// can carry with [`Endpoint::with_max_payload_size`], which is then enforced before sending on
+/// it, rather than leaving the [`UTransport`][up_rust::UTransport] to fail on it.
+///
 /// # Examples
 ///
 /// ```
 /// use std::sync::Arc;
 /// use async_std::sync::Mutex;
 /// use up_rust::UTransport;
-/// use up_streamer::Endpoint;
+/// use up_streamer::{Endpoint, OversizePolicy};
 ///
 /// # pub mod up_client_foo {
 /// #     use std::sync::Arc;
@@ -84,16 +117,33 @@ const ENDPOINT_FN_NEW_TAG: &str = "new():";
 ///
 /// let authority_foo = "foo_authority";
 ///
-/// let local_endpoint = Endpoint::new("local_endpoint", authority_foo, local_transport);
+/// let local_endpoint = Endpoint::new("local_endpoint", authority_foo, local_transport.clone());
+///
+/// let zone_controllers_endpoint = Endpoint::new("zone_controllers_endpoint", "zone-*", local_transport.clone());
+///
+/// let segment_endpoint = Endpoint::new_with_authorities(
+///     "segment_endpoint",
+///     &["body_authority", "chassis_authority"],
+///     local_transport.clone(),
+/// )
+/// .expect("an Endpoint needs an authority");
+///
+/// let someip_endpoint = Endpoint::new("someip_endpoint", "me_authority", local_transport)
+///     .with_max_payload_size(1400, OversizePolicy::Reject);
 /// ```
 #[derive(Clone)]
 pub struct Endpoint {
     pub(crate) name: String,
-    pub(crate) authority: String,
+    pub(crate) authorities: Vec<String>,
     pub(crate) transport: Arc<dyn UTransport>,
+    pub(crate) max_payload_size: Option<usize>,
+    pub(crate) oversize_policy: OversizePolicy,
+    pub(crate) replay_guard: Option<Arc<ReplayGuard>>,
+    pub(crate) retained_values: Option<Arc<RetainedValues>>,
 }
 
 impl Endpoint {
+    /// Creates an [`Endpoint`] for a single `authority`, which may be a pattern
     pub fn new(name: &str, authority: &str, transport: Arc<dyn UTransport>) -> Self {
         // Try to initiate logging.
         // Required in case of dynamic lib, otherwise no logs.
@@ -105,8 +155,118 @@ impl Endpoint {
         );
         Self {
             name: name.to_string(),
-            authority: authority.to_string(),
+            authorities: vec![authority.to_string()],
             transport,
+            max_payload_size: None,
+            oversize_policy: OversizePolicy::default(),
+            replay_guard: None,
+            retained_values: None,
+        }
+    }
+
+    /// Creates an [`Endpoint`] for a set of `authorities` served by the same `transport`, each of
+    /// which may be a pattern
+    ///
+    /// # Errors
+    ///
+    /// Without any `authorities` the [`Endpoint`] couldn't be forwarded to or from, so this fails
+    /// with [`UCode::INVALID_ARGUMENT`][up_rust::UCode::INVALID_ARGUMENT].
+    pub fn new_with_authorities(
+        name: &str,
+        authorities: &[&str],
+        transport: Arc<dyn UTransport>,
+    ) -> Result<Self, UStatus> {
+        let _ = env_logger::try_init();
+        debug!(
+            "{}:{} Creating Endpoint from: ({:?})",
+            &ENDPOINT_TAG, &ENDPOINT_FN_NEW_WITH_AUTHORITIES_TAG, &authorities,
+        );
+        if authorities.is_empty() {
+            let err = UStatus::fail_with_code(
+                UCode::INVALID_ARGUMENT,
+                format!("Endpoint: {name} has no authorities"),
+            );
+            warn!(
+                "{}:{} Creating Endpoint failed: {:?}",
+                &ENDPOINT_TAG, &ENDPOINT_FN_NEW_WITH_AUTHORITIES_TAG, err
+            );
+            return Err(err);
+        }
+        let mut unique_authorities: Vec<String> = Vec::with_capacity(authorities.len());
+        for authority in authorities {
+            if !unique_authorities.iter().any(|a| a == authority) {
+                unique_authorities.push(authority.to_string());
+            }
         }
+        Ok(Self {
+            name: name.to_string(),
+            authorities: unique_authorities,
+            transport,
+            max_payload_size: None,
+            oversize_policy: OversizePolicy::default(),
+            replay_guard: None,
+            retained_values: None,
+        })
+    }
+
+    /// Limits the payload of messages sent on this [`Endpoint`] to `max_payload_size` bytes,
+    /// larger ones are handled according to `oversize_policy`
+    pub fn with_max_payload_size(
+        mut self,
+        max_payload_size: usize,
+        oversize_policy: OversizePolicy,
+    ) -> Self {
+        self.max_payload_size = Some(max_payload_size);
+        self.oversize_policy = oversize_policy;
+        self
+    }
+
+    /// Rejects messages arriving on this [`Endpoint`] which are too old or were seen before, see
+    /// [`ReplayProtectionConfig`][crate::ReplayProtectionConfig]
+    ///
+    /// The ids seen are shared by all clones of this [`Endpoint`], so it may be the in
+    /// [`Endpoint`] of several forwarding rules. Each of those rules remembers the ids it was
+    /// handed on its own, so that every rule forwards a message once, rather than only the first
+    /// one to hear it.
+    pub fn with_replay_protection(mut self, config: ReplayProtectionConfig) -> Self {
+        self.replay_guard = Some(Arc::new(ReplayGuard::new(config)));
+        self
+    }
+
+    /// Retains the last publish or notification message per topic arriving on this
+    /// [`Endpoint`], see [`RetainedValuesConfig`][crate::RetainedValuesConfig]
+    ///
+    /// Values are retained whether or not a forwarding rule forwards them yet. When a forwarding
+    /// rule from this [`Endpoint`] is added, or an authority it forwards to subscribes through
+    /// uSubscription, the retained values which may go there are forwarded right away, so that
+    /// consumers joining late needn't wait for the next periodic publish, even if the subscribe
+    /// request is carried by several rules. Replayed values go through the checks of the rule
+    /// forwarding them and are left out once their TTL has passed. They're sent with a fresh id
+    /// and what is left of their TTL, so that [`Endpoint::with_replay_protection`] on the other
+    /// end doesn't reject them as replays. Fragmented messages aren't retained. The values are
+    /// shared by all clones of this [`Endpoint`] and outlive the rules they were forwarded by.
+    pub fn with_retained_values(mut self, config: RetainedValuesConfig) -> Self {
+        self.retained_values = Some(Arc::new(RetainedValues::new(config)));
+        self
+    }
+
+    // whether any of our authorities would match any of the other's authorities
+    pub(crate) fn shares_authority_with(&self, other: &Endpoint) -> bool {
+        self.authorities.iter().any(|authority| {
+            other.authorities.iter().any(|other_authority| {
+                authority_matches(authority, other_authority)
+                    || authority_matches(other_authority, authority)
+            })
+        })
+    }
+
+    // whether each of the other's authorities is matched by one of ours, so that we may stand in
+    // for it
+    pub(crate) fn serves_authorities_of(&self, other: &Endpoint) -> bool {
+        other.authorities.iter().all(|other_authority| {
+            self.authorities
+                .iter()
+                .any(|authority| authority_matches(authority, other_authority))
+        })
     }
 }
@@ -0,0 +1,286 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+// the queue in front of a TransportForwarder, split into one bounded lane per forwarding rule
+// feeding it, which are served weighted round-robin so that a busy rule can't starve the others
+//
+// every message put into a lane is accompanied by a token on the shared `ready` channel, so the
+// consumer can wait on that one channel and then pick the lane whose turn it is
+//
+// a token may also come without a message, to wake the consumer up for something other than a
+// message, e.g. one held back by conflation
+
+use async_std::channel::{self, Receiver, SendError, Sender};
+use async_std::future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use up_rust::UMessage;
+
+struct Lane {
+    key: String,
+    weight: u32,
+    served: u32,
+    users: usize,
+    sender: Sender<Arc<UMessage>>,
+    receiver: Receiver<Arc<UMessage>>,
+}
+
+#[derive(Default)]
+struct Lanes {
+    lanes: Vec<Lane>,
+    cursor: usize,
+}
+
+impl Lanes {
+    fn next(&mut self) -> Option<Arc<UMessage>> {
+        let lanes_num = self.lanes.len();
+        if lanes_num == 0 {
+            return None;
+        }
+        // one more than a full pass, so that the current lane gets a fresh turn if it's the only
+        // one with anything queued
+        for _ in 0..=lanes_num {
+            let cursor = self.cursor % lanes_num;
+            let lane = &mut self.lanes[cursor];
+            if lane.served < lane.weight {
+                if let Ok(msg) = lane.receiver.try_recv() {
+                    lane.served += 1;
+                    return Some(msg);
+                }
+            }
+            lane.served = 0;
+            self.cursor = (cursor + 1) % lanes_num;
+        }
+        None
+    }
+}
+
+pub(crate) fn fair_queue(lane_capacity: usize) -> (FairQueueProducer, FairQueueConsumer) {
+    let lanes = Arc::new(Mutex::new(Lanes::default()));
+    let (ready_tx, ready_rx) = channel::unbounded();
+    (
+        FairQueueProducer {
+            lane_capacity,
+            lanes: lanes.clone(),
+            ready_tx,
+        },
+        FairQueueConsumer { lanes, ready_rx },
+    )
+}
+
+pub(crate) struct FairQueueProducer {
+    lane_capacity: usize,
+    lanes: Arc<Mutex<Lanes>>,
+    ready_tx: Sender<()>,
+}
+
+impl FairQueueProducer {
+    /// Returns a sender for the lane `key`, adding it with `weight` if it isn't there yet
+    pub(crate) fn add_lane(&self, key: &str, weight: u32) -> LaneSender {
+        let mut lanes = self.lanes.lock().unwrap();
+        let lane = match lanes.lanes.iter_mut().position(|lane| lane.key == key) {
+            Some(index) => &mut lanes.lanes[index],
+            None => {
+                let (sender, receiver) = channel::bounded(self.lane_capacity);
+                lanes.lanes.push(Lane {
+                    key: key.to_string(),
+                    weight: weight.max(1),
+                    served: 0,
+                    users: 0,
+                    sender,
+                    receiver,
+                });
+                lanes.lanes.last_mut().unwrap()
+            }
+        };
+        lane.users += 1;
+        LaneSender {
+            sender: lane.sender.clone(),
+            ready_tx: self.ready_tx.clone(),
+        }
+    }
+
+    /// The number of messages the lanes can hold between them
+    pub(crate) fn capacity(&self) -> usize {
+        self.lanes.lock().unwrap().lanes.len() * self.lane_capacity
+    }
+
+    /// Drops the lane `key` once its last user is gone, returning what was still queued in it
+    pub(crate) fn remove_lane(&self, key: &str) -> Vec<Arc<UMessage>> {
+        let mut lanes = self.lanes.lock().unwrap();
+        let Some(index) = lanes.lanes.iter().position(|lane| lane.key == key) else {
+            return Vec::new();
+        };
+        lanes.lanes[index].users -= 1;
+        if lanes.lanes[index].users > 0 {
+            return Vec::new();
+        }
+
+        let lane = lanes.lanes.remove(index);
+        if lanes.cursor > index {
+            lanes.cursor -= 1;
+        }
+        lane.sender.close();
+        std::iter::from_fn(|| lane.receiver.try_recv().ok()).collect()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LaneSender {
+    sender: Sender<Arc<UMessage>>,
+    ready_tx: Sender<()>,
+}
+
+impl LaneSender {
+    /// Waits for room in the lane, then queues `msg`
+    pub(crate) async fn send(&self, msg: Arc<UMessage>) -> Result<(), SendError<Arc<UMessage>>> {
+        self.sender.send(msg).await?;
+        let _ = self.ready_tx.send(()).await;
+        Ok(())
+    }
+
+    /// The number of messages queued in the lane
+    pub(crate) fn len(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// Wakes the consumer up without a message
+    pub(crate) fn wake(&self) {
+        let _ = self.ready_tx.try_send(());
+    }
+}
+
+pub(crate) struct FairQueueConsumer {
+    lanes: Arc<Mutex<Lanes>>,
+    ready_rx: Receiver<()>,
+}
+
+impl FairQueueConsumer {
+    /// Waits for the next message in line, `None` once every producer and lane sender is gone
+    pub(crate) async fn recv(&self) -> Option<Arc<UMessage>> {
+        loop {
+            if let Some(msg) = self.recv_until(None).await? {
+                return Some(msg);
+            }
+        }
+    }
+
+    /// Waits for the next message in line until `deadline`, `Some(None)` if there was none by then
+    /// or the consumer was woken up without one, `None` once every producer and lane sender is
+    /// gone
+    ///
+    /// Messages already queued are returned even if `deadline` has passed.
+    pub(crate) async fn recv_until(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Option<Option<Arc<UMessage>>> {
+        let ready = match deadline {
+            None => self.ready_rx.recv().await,
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match future::timeout(timeout, self.ready_rx.recv()).await {
+                    Ok(ready) => ready,
+                    Err(_) => return Some(None),
+                }
+            }
+        };
+        ready.ok()?;
+        // a token without a message belongs to a lane which has since been removed, or woke us up
+        Some(self.lanes.lock().unwrap().next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fair_queue;
+    use async_std::task;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use up_rust::UMessage;
+
+    fn message_with_payload(payload: &str) -> Arc<UMessage> {
+        Arc::new(UMessage {
+            payload: Some(payload.as_bytes().to_vec().into()),
+            ..Default::default()
+        })
+    }
+
+    fn payload_of(msg: &UMessage) -> String {
+        String::from_utf8(msg.payload.clone().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_lanes_are_served_by_weight() {
+        task::block_on(async {
+            let (producer, consumer) = fair_queue(10);
+            let busy = producer.add_lane("busy", 1);
+            let quiet = producer.add_lane("quiet", 2);
+            assert_eq!(producer.capacity(), 20);
+
+            for _ in 0..6 {
+                busy.send(message_with_payload("busy")).await.unwrap();
+            }
+            for _ in 0..3 {
+                quiet.send(message_with_payload("quiet")).await.unwrap();
+            }
+            assert_eq!((busy.len(), quiet.len()), (6, 3));
+
+            let mut served = Vec::new();
+            for _ in 0..9 {
+                served.push(payload_of(&consumer.recv().await.unwrap()));
+            }
+            assert_eq!(
+                served,
+                ["busy", "quiet", "quiet", "busy", "quiet", "busy", "busy", "busy", "busy"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_removed_lane_returns_what_was_queued_and_consumer_ends() {
+        task::block_on(async {
+            let (producer, consumer) = fair_queue(10);
+            let lane = producer.add_lane("rule", 1);
+            let same_lane = producer.add_lane("rule", 1);
+
+            lane.send(message_with_payload("a")).await.unwrap();
+            assert!(producer.remove_lane("rule").is_empty());
+            let dropped = producer.remove_lane("rule");
+            assert_eq!(dropped.len(), 1);
+            assert!(same_lane.send(message_with_payload("b")).await.is_err());
+
+            drop((producer, lane, same_lane));
+            assert!(consumer.recv().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_consumer_waits_until_its_deadline_or_being_woken_up() {
+        task::block_on(async {
+            let (producer, consumer) = fair_queue(10);
+            let lane = producer.add_lane("rule", 1);
+
+            let deadline = Instant::now() + Duration::from_millis(20);
+            assert!(consumer.recv_until(Some(deadline)).await.unwrap().is_none());
+            assert!(Instant::now() >= deadline);
+
+            lane.wake();
+            assert!(consumer.recv_until(None).await.unwrap().is_none());
+
+            // what's queued comes first, however late it is
+            lane.send(message_with_payload("a")).await.unwrap();
+            let received = consumer.recv_until(Some(Instant::now())).await.unwrap();
+            assert_eq!(payload_of(&received.unwrap()), "a");
+        });
+    }
+}
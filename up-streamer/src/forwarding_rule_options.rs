@@ -0,0 +1,336 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use crate::compression::CompressionConfig;
+use crate::conflation::ConflationConfig;
+use crate::content_filter::ContentFilter;
+use crate::endpoint::Endpoint;
+use crate::message_script::MessageScript;
+use crate::payload_protection::{PayloadProtection, ProtectionRequirement};
+use crate::payload_translation::PayloadTranslation;
+use crate::rate_limit::RateLimit;
+use crate::response_cache::ResponseCacheConfig;
+use crate::transport_health::HealthProbe;
+use crate::wasm_filter::WasmFilter;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Configures when a failover rule switches between its out [`Endpoint`][crate::Endpoint]s
+#[derive(Clone)]
+pub struct FailoverConfig {
+    /// Number of `send` failures in a row after which an out [`Endpoint`][crate::Endpoint] is
+    /// considered down and the next one in line is used
+    pub consecutive_failures_to_fail_over: u32,
+    /// How long to wait after the last failure before trying a down
+    /// [`Endpoint`][crate::Endpoint] again, which is how the rule fails back without a probe
+    pub retry_interval: Duration,
+    /// Optional active check, a failing probe takes an out [`Endpoint`][crate::Endpoint] out of
+    /// rotation until it succeeds again
+    pub health_probe: Option<Arc<dyn HealthProbe>>,
+    /// How often `health_probe` is run against each out [`Endpoint`][crate::Endpoint]
+    pub health_probe_interval: Duration,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures_to_fail_over: 3,
+            retry_interval: Duration::from_secs(5),
+            health_probe: None,
+            health_probe_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// How a load balanced rule spreads messages across its out [`Endpoint`][crate::Endpoint]s
+///
+/// Out [`Endpoint`][crate::Endpoint]s whose [`UTransport`][up_rust::UTransport] is down, see
+/// [`TransportHealthConfig`][crate::TransportHealthConfig], are left out for as long as any other
+/// one isn't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadBalancingStrategy {
+    /// Each out [`Endpoint`][crate::Endpoint] in turn
+    RoundRobin,
+    /// The out [`Endpoint`][crate::Endpoint] with the fewest messages of the rule queued for
+    /// sending
+    LeastQueueDepth,
+    /// An out [`Endpoint`][crate::Endpoint] chosen by hashing the source
+    /// [`UUri`][up_rust::UUri], so that messages from one source keep their order
+    SourceHash,
+}
+
+// how a rule picks which of its out endpoints a message is sent on
+#[derive(Clone, Default)]
+pub(crate) enum OutSelection {
+    #[default]
+    Single,
+    Failover(FailoverConfig),
+    LoadBalancing(LoadBalancingStrategy),
+    // the filter for each of the additional out endpoints, in the same order
+    ContentRouting(Vec<ContentFilter>),
+}
+
+/// Optional behavior of a forwarding rule, used with
+/// [`UStreamer::add_forwarding_rule_with_options`][crate::UStreamer::add_forwarding_rule_with_options]
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use up_streamer::{FailoverConfig, ForwardingRuleOptions};
+///
+/// let options = ForwardingRuleOptions::new().with_failover(
+///     vec![],
+///     FailoverConfig {
+///         consecutive_failures_to_fail_over: 2,
+///         retry_interval: Duration::from_secs(1),
+///         ..Default::default()
+///     },
+/// );
+/// ```
+#[derive(Clone)]
+pub struct ForwardingRuleOptions {
+    pub(crate) additional_out_endpoints: Vec<Endpoint>,
+    pub(crate) out_selection: OutSelection,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) scheduling_weight: u32,
+    pub(crate) compression: Option<CompressionConfig>,
+    pub(crate) protection: Option<PayloadProtection>,
+    pub(crate) protection_requirement: Option<ProtectionRequirement>,
+    pub(crate) deadline_exceeded_responses: bool,
+    pub(crate) response_cache: Option<ResponseCacheConfig>,
+    pub(crate) conflation: Option<ConflationConfig>,
+    pub(crate) content_filter: Option<ContentFilter>,
+    pub(crate) payload_translation: Option<PayloadTranslation>,
+    pub(crate) script: Option<MessageScript>,
+    pub(crate) wasm_filter: Option<WasmFilter>,
+}
+
+impl Default for ForwardingRuleOptions {
+    fn default() -> Self {
+        Self {
+            additional_out_endpoints: Vec::new(),
+            out_selection: OutSelection::default(),
+            rate_limit: None,
+            scheduling_weight: 1,
+            compression: None,
+            protection: None,
+            protection_requirement: None,
+            deadline_exceeded_responses: false,
+            response_cache: None,
+            conflation: None,
+            content_filter: None,
+            payload_translation: None,
+            script: None,
+            wasm_filter: None,
+        }
+    }
+}
+
+impl ForwardingRuleOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the rule's out [`Endpoint`][crate::Endpoint] the primary of an active/standby group
+    ///
+    /// Messages are sent on the primary while it is healthy, otherwise on the first healthy
+    /// `standby_endpoints` in the order given. Once a more preferred
+    /// [`Endpoint`][crate::Endpoint] recovers, the rule fails back onto it.
+    ///
+    /// Every standby must serve each of the primary's authorities, or adding the rule fails.
+    ///
+    /// Replaces any load balancing set up with [`ForwardingRuleOptions::with_load_balancing`].
+    pub fn with_failover(
+        mut self,
+        standby_endpoints: Vec<Endpoint>,
+        failover_config: FailoverConfig,
+    ) -> Self {
+        self.additional_out_endpoints = standby_endpoints;
+        self.out_selection = OutSelection::Failover(failover_config);
+        self
+    }
+
+    /// Spreads the rule's messages across its out [`Endpoint`][crate::Endpoint] and
+    /// `additional_out_endpoints` according to `strategy`
+    ///
+    /// Each out [`Endpoint`][crate::Endpoint] with its own [`UTransport`][up_rust::UTransport]
+    /// gets its own queue and worker, so parallel transports raise the throughput of the rule.
+    ///
+    /// Replaces any failover set up with [`ForwardingRuleOptions::with_failover`].
+    pub fn with_load_balancing(
+        mut self,
+        additional_out_endpoints: Vec<Endpoint>,
+        strategy: LoadBalancingStrategy,
+    ) -> Self {
+        self.additional_out_endpoints = additional_out_endpoints;
+        self.out_selection = OutSelection::LoadBalancing(strategy);
+        self
+    }
+
+    /// Sends each message on the first of `routes` whose [`ContentFilter`][crate::ContentFilter]
+    /// its payload matches, and those matching none on the rule's out
+    /// [`Endpoint`][crate::Endpoint]
+    ///
+    /// Replaces any failover or load balancing set up before.
+    pub fn with_content_routing(mut self, routes: Vec<(ContentFilter, Endpoint)>) -> Self {
+        let (content_filters, out_endpoints) = routes.into_iter().unzip();
+        self.additional_out_endpoints = out_endpoints;
+        self.out_selection = OutSelection::ContentRouting(content_filters);
+        self
+    }
+
+    /// Forwards only messages whose payload matches `content_filter`, see
+    /// [`ContentFilter`][crate::ContentFilter]
+    ///
+    /// Meant for keeping data from leaving the vehicle which needn't, a request which is filtered
+    /// out is answered with [`UCode::PERMISSION_DENIED`][up_rust::UCode::PERMISSION_DENIED].
+    pub fn with_content_filter(mut self, content_filter: ContentFilter) -> Self {
+        self.content_filter = Some(content_filter);
+        self
+    }
+
+    /// Converts the payloads the rule forwards between protobuf and JSON, see
+    /// [`PayloadTranslation`][crate::PayloadTranslation]
+    ///
+    /// Payloads are translated after any [`ContentFilter`][crate::ContentFilter] looked at them
+    /// and before any compression or protection. Messages whose payload doesn't parse as its
+    /// message type are dropped, a request among them is answered with
+    /// [`UCode::INVALID_ARGUMENT`][up_rust::UCode::INVALID_ARGUMENT].
+    pub fn with_payload_translation(mut self, payload_translation: PayloadTranslation) -> Self {
+        self.payload_translation = Some(payload_translation);
+        self
+    }
+
+    /// Runs `script` on each message the rule forwards, which may rewrite it, drop it or forward
+    /// several messages in its place, see [`MessageScript`][crate::MessageScript]
+    ///
+    /// The script sees messages as they arrived, before any
+    /// [`ContentFilter`][crate::ContentFilter] or conflation. Messages for which it fails are
+    /// dropped, a request among them is answered with
+    /// [`UCode::INTERNAL`][up_rust::UCode::INTERNAL]. The messages it returns go through the
+    /// access policy and strict source authority check again, and are dropped if addressed to an
+    /// authority the rule doesn't forward to.
+    pub fn with_script(mut self, script: MessageScript) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    /// Runs the WebAssembly module of `wasm_filter` on each message the rule forwards, which may
+    /// rewrite, drop or reject it, see [`WasmFilter`][crate::WasmFilter]
+    ///
+    /// The filter sees messages as they arrived, before any
+    /// [`MessageScript`][crate::MessageScript]. A request it rejects is answered with
+    /// [`UCode::PERMISSION_DENIED`][up_rust::UCode::PERMISSION_DENIED], one for which it fails
+    /// with [`UCode::INTERNAL`][up_rust::UCode::INTERNAL]. A message it rewrites is checked again
+    /// as with [`ForwardingRuleOptions::with_script`].
+    pub fn with_wasm_filter(mut self, wasm_filter: WasmFilter) -> Self {
+        self.wasm_filter = Some(wasm_filter);
+        self
+    }
+
+    /// Limits the messages and payload bytes the rule forwards, see
+    /// [`RateLimit`][crate::RateLimit]
+    ///
+    /// The limit applies to the rule as a whole, however many out
+    /// [`Endpoint`][crate::Endpoint]s it has.
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Forwards only the latest of the publish and notification messages coming in faster than
+    /// the rule should carry them, see [`ConflationConfig`][crate::ConflationConfig]
+    ///
+    /// Conflation comes before the rule's [`RateLimit`][crate::RateLimit], if any.
+    pub fn with_conflation(mut self, conflation_config: ConflationConfig) -> Self {
+        self.conflation = Some(conflation_config);
+        self
+    }
+
+    /// Sets the rule's share when several rules forward onto the same out
+    /// [`UTransport`][up_rust::UTransport], defaults to `1`
+    ///
+    /// Each rule has its own queue in front of every out [`UTransport`][up_rust::UTransport], and
+    /// those queues are served in turn, `scheduling_weight` messages at a time. Equal weights
+    /// make for plain round-robin, so that every rule makes progress however busy the others are.
+    pub fn with_scheduling_weight(mut self, scheduling_weight: u32) -> Self {
+        self.scheduling_weight = scheduling_weight.max(1);
+        self
+    }
+
+    /// Compresses the payloads the rule forwards, see [`CompressionConfig`][crate::CompressionConfig]
+    ///
+    /// Only for rules whose out [`Endpoint`][crate::Endpoint]s lead to a peer
+    /// [`UStreamer`][crate::UStreamer], as compressed messages are marked with a payload format
+    /// of their own which only a [`UStreamer`][crate::UStreamer] understands. The peer
+    /// decompresses them before forwarding them on, so its receivers get the original payload,
+    /// up to the `max_message_size` of its [`ReassemblyConfig`][crate::ReassemblyConfig].
+    pub fn with_compression(mut self, compression_config: CompressionConfig) -> Self {
+        self.compression = Some(compression_config);
+        self
+    }
+
+    /// Signs and/or encrypts the messages the rule forwards, see
+    /// [`PayloadProtection`][crate::PayloadProtection]
+    ///
+    /// Only for rules whose out [`Endpoint`][crate::Endpoint]s lead to a peer
+    /// [`UStreamer`][crate::UStreamer] holding the matching
+    /// [`ProtectionKeys`][crate::ProtectionKeys]. Payloads are compressed before they are
+    /// encrypted, as encrypted payloads don't compress.
+    pub fn with_protection(mut self, protection: PayloadProtection) -> Self {
+        self.protection = Some(protection);
+        self
+    }
+
+    /// Drops messages arriving on the rule's in [`Endpoint`][crate::Endpoint] which were not
+    /// protected by a peer [`UStreamer`][crate::UStreamer] as `protection_requirement` demands,
+    /// for rules whose in [`Endpoint`][crate::Endpoint] is the untrusted side
+    ///
+    /// Protected messages are checked and decrypted whether or not this is set, but without it
+    /// they only go through the checks they claim to have been protected with.
+    pub fn with_protection_required(
+        mut self,
+        protection_requirement: ProtectionRequirement,
+    ) -> Self {
+        self.protection_requirement = Some(protection_requirement);
+        self
+    }
+
+    /// Answers requests the rule forwarded with a
+    /// [`UCode::DEADLINE_EXCEEDED`][up_rust::UCode::DEADLINE_EXCEEDED] response once their TTL
+    /// runs out without a response having come back through the [`UStreamer`][crate::UStreamer]
+    ///
+    /// The response is sent on the in [`Endpoint`][crate::Endpoint] the request arrived on, so
+    /// that its sender learns where the request died. Requests without a TTL use the
+    /// [`RequestTrackingConfig`][crate::RequestTrackingConfig]'s `default_ttl`, and those given
+    /// up on to stay within its `max_outstanding_requests` are answered right away.
+    pub fn with_deadline_exceeded_responses(mut self) -> Self {
+        self.deadline_exceeded_responses = true;
+        self
+    }
+
+    /// Keeps the responses to requests the rule forwarded, to answer retransmissions of a request
+    /// with instead of forwarding them again
+    ///
+    /// Meant for requests which aren't idempotent, so that a sender retrying after a lost response
+    /// doesn't have them carried out twice. While the first transmission still awaits its response,
+    /// retransmissions are dropped. Responses are matched to requests by their `reqid`, so that
+    /// this needs the rule carrying the responses back to go through the same
+    /// [`UStreamer`][crate::UStreamer]. A cached response is forwarded by that rule again, and
+    /// only to a retransmission from the same source to the same sink.
+    pub fn with_response_cache(mut self, response_cache_config: ResponseCacheConfig) -> Self {
+        self.response_cache = Some(response_cache_config);
+        self
+    }
+}
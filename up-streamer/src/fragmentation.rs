@@ -0,0 +1,483 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+// a message too large for an out endpoint is split into fragments, each a copy of the original
+// message with its own id, a slice of the payload and the FRAGMENT_PAYLOAD_FORMAT as payload_format
+//
+// the payload of a fragment starts with a header, all big-endian:
+//
+// | original payload_format: i32 | original id: msb u64, lsb u64 | index: u16 | count: u16 |
+// | total payload length: u32 |
+//
+// the peer streamer reassembles the original message, id and payload_format included, once all
+// fragments have arrived
+
+use protobuf::EnumOrUnknown;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use up_rust::{UMessage, UUIDBuilder, UUID};
+
+// well outside of the range used by UPayloadFormat
+pub(crate) const FRAGMENT_PAYLOAD_FORMAT: i32 = 0x7F01;
+const FRAGMENT_HEADER_SIZE: usize = 4 + 16 + 2 + 2 + 4;
+
+/// Limits the resources spent on reassembling fragmented messages, set with
+/// [`UStreamer::set_reassembly_config`][crate::UStreamer::set_reassembly_config]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReassemblyConfig {
+    /// How long after its first fragment arrived an incomplete message is given up on
+    pub timeout: Duration,
+    /// Payload bytes which may be held across all incomplete messages, the oldest incomplete
+    /// messages are given up on to stay within it
+    ///
+    /// Every forwarding rule which hears a fragmented message reassembles it on its own, so a
+    /// message heard by several rules is held once for each of them.
+    pub max_pending_bytes: usize,
+    /// Largest payload a reassembled or decompressed message may have
+    pub max_message_size: usize,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_pending_bytes: 16 * 1024 * 1024,
+            max_message_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+struct FragmentHeader {
+    payload_format: i32,
+    msb: u64,
+    lsb: u64,
+    index: u16,
+    count: u16,
+    total_len: u32,
+}
+
+impl FragmentHeader {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.payload_format.to_be_bytes());
+        buf.extend_from_slice(&self.msb.to_be_bytes());
+        buf.extend_from_slice(&self.lsb.to_be_bytes());
+        buf.extend_from_slice(&self.index.to_be_bytes());
+        buf.extend_from_slice(&self.count.to_be_bytes());
+        buf.extend_from_slice(&self.total_len.to_be_bytes());
+    }
+
+    fn read(buf: &[u8]) -> Option<Self> {
+        let header = buf.get(..FRAGMENT_HEADER_SIZE)?;
+        Some(Self {
+            payload_format: i32::from_be_bytes(header[0..4].try_into().ok()?),
+            msb: u64::from_be_bytes(header[4..12].try_into().ok()?),
+            lsb: u64::from_be_bytes(header[12..20].try_into().ok()?),
+            index: u16::from_be_bytes(header[20..22].try_into().ok()?),
+            count: u16::from_be_bytes(header[22..24].try_into().ok()?),
+            total_len: u32::from_be_bytes(header[24..28].try_into().ok()?),
+        })
+    }
+}
+
+pub(crate) fn is_fragment(msg: &UMessage) -> bool {
+    msg.attributes
+        .as_ref()
+        .is_some_and(|attributes| attributes.payload_format.value() == FRAGMENT_PAYLOAD_FORMAT)
+}
+
+/// Splits `msg` into fragments whose payloads, header included, are at most `max_payload_size`
+/// bytes, or `None` if that can't be done
+pub(crate) fn fragment(msg: &UMessage, max_payload_size: usize) -> Option<Vec<UMessage>> {
+    let attributes = msg.attributes.as_ref()?;
+    let payload = msg.payload.clone().unwrap_or_default();
+    let chunk_size = max_payload_size.checked_sub(FRAGMENT_HEADER_SIZE)?;
+    if chunk_size == 0 {
+        return None;
+    }
+    let count = u16::try_from(payload.len().div_ceil(chunk_size)).ok()?;
+    let total_len = u32::try_from(payload.len()).ok()?;
+
+    let fragments = payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment_payload = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            FragmentHeader {
+                payload_format: attributes.payload_format.value(),
+                msb: attributes.id.msb,
+                lsb: attributes.id.lsb,
+                index: index as u16,
+                count,
+                total_len,
+            }
+            .write(&mut fragment_payload);
+            fragment_payload.extend_from_slice(chunk);
+
+            let mut fragment = msg.clone();
+            let fragment_attributes = fragment.attributes.mut_or_insert_default();
+            fragment_attributes.id = Some(UUIDBuilder::build()).into();
+            fragment_attributes.payload_format = EnumOrUnknown::from_i32(FRAGMENT_PAYLOAD_FORMAT);
+            fragment.payload = Some(fragment_payload.into());
+            fragment
+        })
+        .collect();
+    Some(fragments)
+}
+
+/// What became of a fragment handed to [`Reassembler::accept`]
+pub(crate) enum Reassembly {
+    /// The fragment completed its message
+    Complete(UMessage),
+    /// More fragments are needed
+    Pending,
+    /// The fragment or its message was given up on
+    Dropped(&'static str),
+}
+
+struct PartialMessage {
+    first_seen: Instant,
+    // the first fragment to arrive, which the reassembled message is based on
+    template: UMessage,
+    payload_format: i32,
+    total_len: usize,
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    pending_bytes: usize,
+}
+
+// the rule reassembling a message and the original id of the message
+type PartialMessageKey = (u64, u64, u64);
+
+#[derive(Default)]
+struct ReassemblerState {
+    partial_messages: HashMap<PartialMessageKey, PartialMessage>,
+    pending_bytes: usize,
+}
+
+impl ReassemblerState {
+    fn remove(&mut self, key: &PartialMessageKey) -> Option<PartialMessage> {
+        let partial_message = self.partial_messages.remove(key)?;
+        self.pending_bytes -= partial_message.pending_bytes;
+        Some(partial_message)
+    }
+
+    // gives up on the incomplete messages which timed out, returning how many there were
+    fn expire(&mut self, now: Instant, timeout: Duration) -> usize {
+        let expired: Vec<PartialMessageKey> = self
+            .partial_messages
+            .iter()
+            .filter(|(_, partial_message)| {
+                now.duration_since(partial_message.first_seen) >= timeout
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+
+    // gives up on the oldest incomplete messages other than `keep` until `additional_bytes` fit
+    fn evict_for(
+        &mut self,
+        additional_bytes: usize,
+        max_pending_bytes: usize,
+        keep: &PartialMessageKey,
+    ) -> usize {
+        let mut evicted = 0;
+        while self.pending_bytes + additional_bytes > max_pending_bytes {
+            let Some(oldest) = self
+                .partial_messages
+                .iter()
+                .filter(|(key, _)| *key != keep)
+                .min_by_key(|(_, partial_message)| partial_message.first_seen)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            self.remove(&oldest);
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
+// reassembles the fragmented messages arriving on any in endpoint
+//
+// every rule from an in endpoint hears the same fragments, so each rule reassembles on its own,
+// lest only the rule which happens to hear the last fragment get the message
+#[derive(Default)]
+pub(crate) struct Reassembler {
+    config: RwLock<ReassemblyConfig>,
+    state: Mutex<ReassemblerState>,
+}
+
+impl Reassembler {
+    pub(crate) fn set_config(&self, config: ReassemblyConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    pub(crate) fn max_message_size(&self) -> usize {
+        self.config.read().unwrap().max_message_size
+    }
+
+    /// Takes in a fragment for the rule `rule_id`, returning the original message once it is
+    /// complete, along with the number of incomplete messages given up on in the meantime
+    pub(crate) fn accept(&self, rule_id: u64, fragment: UMessage) -> (Reassembly, usize) {
+        self.accept_at(rule_id, fragment, Instant::now())
+    }
+
+    fn accept_at(&self, rule_id: u64, mut fragment: UMessage, now: Instant) -> (Reassembly, usize) {
+        let config = self.config.read().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+        let mut given_up = state.expire(now, config.timeout);
+
+        let payload = fragment.payload.take().unwrap_or_default();
+        let Some(header) = FragmentHeader::read(&payload) else {
+            return (Reassembly::Dropped("malformed fragment header"), given_up);
+        };
+        if header.count == 0 || header.index >= header.count {
+            return (Reassembly::Dropped("malformed fragment index"), given_up);
+        }
+        if header.total_len as usize > config.max_message_size {
+            return (
+                Reassembly::Dropped("reassembled message would exceed max_message_size"),
+                given_up,
+            );
+        }
+        let chunk = payload[FRAGMENT_HEADER_SIZE..].to_vec();
+
+        let key = (rule_id, header.msb, header.lsb);
+        if state
+            .partial_messages
+            .get(&key)
+            .is_some_and(|partial_message| {
+                partial_message.fragments.len() != header.count as usize
+                    || partial_message.total_len != header.total_len as usize
+            })
+        {
+            state.remove(&key);
+            return (Reassembly::Dropped("inconsistent fragments"), given_up + 1);
+        }
+        let (received_bytes, duplicate) =
+            state
+                .partial_messages
+                .get(&key)
+                .map_or((0, false), |partial_message| {
+                    (
+                        partial_message.pending_bytes,
+                        partial_message.fragments[header.index as usize].is_some(),
+                    )
+                });
+        if duplicate {
+            // e.g. when the transport delivered it twice
+            return (Reassembly::Pending, given_up);
+        }
+        // checked on every fragment, so that a peer can't hold more than its message's worth
+        if received_bytes + chunk.len() > header.total_len as usize {
+            let removed = state.remove(&key).is_some();
+            return (
+                Reassembly::Dropped("fragments exceed the total payload length"),
+                given_up + removed as usize,
+            );
+        }
+        given_up += state.evict_for(chunk.len(), config.max_pending_bytes, &key);
+        if state.pending_bytes + chunk.len() > config.max_pending_bytes {
+            let removed = state.remove(&key).is_some();
+            return (
+                Reassembly::Dropped("reassembly would exceed max_pending_bytes"),
+                given_up + removed as usize,
+            );
+        }
+
+        let partial_message = state
+            .partial_messages
+            .entry(key)
+            .or_insert_with(|| PartialMessage {
+                first_seen: now,
+                template: fragment,
+                payload_format: header.payload_format,
+                total_len: header.total_len as usize,
+                fragments: vec![None; header.count as usize],
+                received: 0,
+                pending_bytes: 0,
+            });
+        let chunk_len = chunk.len();
+        partial_message.fragments[header.index as usize] = Some(chunk);
+        partial_message.received += 1;
+        partial_message.pending_bytes += chunk_len;
+        let complete = partial_message.received == partial_message.fragments.len();
+        state.pending_bytes += chunk_len;
+
+        if !complete {
+            return (Reassembly::Pending, given_up);
+        }
+
+        let partial_message = state.remove(&key).unwrap();
+        let mut payload = Vec::with_capacity(partial_message.total_len);
+        for chunk in partial_message.fragments.into_iter().flatten() {
+            payload.extend_from_slice(&chunk);
+        }
+        if payload.len() != partial_message.total_len {
+            return (Reassembly::Dropped("inconsistent fragments"), given_up);
+        }
+
+        let mut msg = partial_message.template;
+        let attributes = msg.attributes.mut_or_insert_default();
+        attributes.id = Some(UUID {
+            msb: header.msb,
+            lsb: header.lsb,
+            ..Default::default()
+        })
+        .into();
+        attributes.payload_format = EnumOrUnknown::from_i32(partial_message.payload_format);
+        msg.payload = Some(payload.into());
+        (Reassembly::Complete(msg), given_up)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fragment, is_fragment, Reassembler, Reassembly, ReassemblyConfig};
+    use std::time::{Duration, Instant};
+    use up_rust::{UAttributes, UMessage, UPayloadFormat, UUIDBuilder};
+
+    fn message_with_payload(payload_len: usize) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                id: Some(UUIDBuilder::build()).into(),
+                payload_format: UPayloadFormat::UPAYLOAD_FORMAT_RAW.into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(
+                (0..payload_len)
+                    .map(|i| i as u8)
+                    .collect::<Vec<u8>>()
+                    .into(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fragments_reassemble_in_any_order() {
+        let msg = message_with_payload(1000);
+        let mut fragments = fragment(&msg, 128).unwrap();
+        assert_eq!(fragments.len(), 10);
+        assert!(fragments.iter().all(
+            |fragment| is_fragment(fragment) && fragment.payload.as_ref().unwrap().len() <= 128
+        ));
+        fragments.reverse();
+
+        let reassembler = Reassembler::default();
+        let last = fragments.pop().unwrap();
+        for fragment in fragments {
+            assert!(matches!(
+                reassembler.accept(0, fragment).0,
+                Reassembly::Pending
+            ));
+        }
+        let Reassembly::Complete(reassembled) = reassembler.accept(0, last).0 else {
+            panic!("expected the message to be complete");
+        };
+        assert_eq!(reassembled, msg);
+    }
+
+    #[test]
+    fn test_every_rule_reassembles_on_its_own() {
+        let msg = message_with_payload(200);
+        let fragments = fragment(&msg, 128).unwrap();
+        let reassembler = Reassembler::default();
+
+        for rule_id in [0, 1] {
+            assert!(matches!(
+                reassembler.accept(rule_id, fragments[0].clone()),
+                (Reassembly::Pending, 0)
+            ));
+        }
+        for rule_id in [0, 1] {
+            let (Reassembly::Complete(reassembled), 0) =
+                reassembler.accept(rule_id, fragments[1].clone())
+            else {
+                panic!("expected the message to be complete for rule {rule_id}");
+            };
+            assert_eq!(reassembled, msg);
+        }
+        assert!(reassembler
+            .state
+            .lock()
+            .unwrap()
+            .partial_messages
+            .is_empty());
+    }
+
+    #[test]
+    fn test_incomplete_messages_time_out_and_are_evicted() {
+        let reassembler = Reassembler::default();
+        reassembler.set_config(ReassemblyConfig {
+            timeout: Duration::from_secs(1),
+            max_pending_bytes: 150,
+            ..Default::default()
+        });
+        let start = Instant::now();
+
+        let first = fragment(&message_with_payload(200), 128).unwrap();
+        let second = fragment(&message_with_payload(200), 128).unwrap();
+        assert!(matches!(
+            reassembler.accept_at(0, first[0].clone(), start),
+            (Reassembly::Pending, 0)
+        ));
+        // only one incomplete message fits, so the first is given up on
+        assert!(matches!(
+            reassembler.accept_at(0, second[0].clone(), start),
+            (Reassembly::Pending, 1)
+        ));
+        // and the second times out
+        assert!(matches!(
+            reassembler.accept_at(0, first[1].clone(), start + Duration::from_secs(1)),
+            (Reassembly::Pending, 1)
+        ));
+    }
+
+    #[test]
+    fn test_every_fragment_is_held_to_the_byte_bounds() {
+        let reassembler = Reassembler::default();
+        reassembler.set_config(ReassemblyConfig {
+            max_pending_bytes: 150,
+            ..Default::default()
+        });
+
+        // the message being reassembled can't grow past max_pending_bytes either
+        let fragments = fragment(&message_with_payload(400), 128).unwrap();
+        assert!(matches!(
+            reassembler.accept(0, fragments[0].clone()),
+            (Reassembly::Pending, 0)
+        ));
+        assert!(matches!(
+            reassembler.accept(0, fragments[1].clone()),
+            (Reassembly::Dropped(_), 1)
+        ));
+
+        // nor can its fragments add up to more than the payload length they claim
+        let mut overlong = fragment(&message_with_payload(200), 128).unwrap().remove(0);
+        let mut payload = overlong.payload.take().unwrap().to_vec();
+        payload[24..28].copy_from_slice(&50u32.to_be_bytes());
+        overlong.payload = Some(payload.into());
+        assert!(matches!(
+            reassembler.accept(0, overlong),
+            (Reassembly::Dropped(_), 0)
+        ));
+    }
+}
@@ -16,8 +16,74 @@
 //! `up-streamer` implements the `UStreamer` spec to allow bridging between different
 //! transports.
 
+mod access_policy;
+pub use access_policy::{AccessDecision, AccessPolicy, AccessRule, UUriPattern};
+
+mod authority_pattern;
+
+mod compression;
+pub use compression::CompressionConfig;
+
+mod conflation;
+pub use conflation::ConflationConfig;
+
+mod content_filter;
+pub use content_filter::ContentFilter;
+
+mod descriptor_registry;
+pub use descriptor_registry::DescriptorRegistry;
+
 mod endpoint;
-pub use endpoint::Endpoint;
+pub use endpoint::{Endpoint, OversizePolicy};
+
+mod fair_queue;
+
+mod forwarding_rule_options;
+pub use forwarding_rule_options::{FailoverConfig, ForwardingRuleOptions, LoadBalancingStrategy};
+
+mod fragmentation;
+pub use fragmentation::ReassemblyConfig;
+
+mod listener_reconciliation;
+pub use listener_reconciliation::ListenerReconciliationConfig;
+
+mod message_script;
+pub use message_script::{MessageScript, ScriptLimits};
+
+mod metrics;
+pub use metrics::StreamerMetrics;
+
+mod payload_protection;
+pub use payload_protection::{PayloadProtection, ProtectionKeys, ProtectionRequirement, Signing};
+
+mod payload_translation;
+pub use payload_translation::PayloadTranslation;
+
+mod rate_limit;
+pub use rate_limit::{RateLimit, RateLimitPolicy};
+
+mod replay_protection;
+pub use replay_protection::ReplayProtectionConfig;
+
+mod request_tracker;
+pub use request_tracker::{RequestTrackingConfig, RpcLatencyStats};
+
+mod response_cache;
+pub use response_cache::ResponseCacheConfig;
+
+mod retained_values;
+pub use retained_values::RetainedValuesConfig;
+
+mod source_quota;
+pub use source_quota::SourceQuotaConfig;
+
+mod transport_health;
+pub use transport_health::{
+    HealthProbe, TransportHealthConfig, TransportHealthEvent, TransportHealthState,
+};
 
 mod ustreamer;
 pub use ustreamer::UStreamer;
+
+mod wasm_filter;
+pub use wasm_filter::{WasmFilter, WasmFilterLimits};
@@ -0,0 +1,49 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use std::time::Duration;
+
+/// Configures when the [`UStreamer`][crate::UStreamer] re-registers the listeners of its
+/// forwarding rules, e.g. after an underlying transport lost its session and came back
+///
+/// Each listener is unregistered from its [`UTransport`][up_rust::UTransport] before it's
+/// registered again, so that a [`UTransport`][up_rust::UTransport] which still has it, and would
+/// take it twice, doesn't deliver every message twice. Messages arriving between the two aren't
+/// heard, so with a periodic `interval` a [`UTransport`][up_rust::UTransport] which never lost its
+/// session may lose a few messages on every round.
+#[derive(Clone, Debug)]
+pub struct ListenerReconciliationConfig {
+    /// If set, listeners are re-registered this often regardless of any reconnect signal
+    pub interval: Option<Duration>,
+    /// Whether to re-register the listeners once a [`UTransport`][up_rust::UTransport] recovers
+    /// from being [`TransportHealthState::Down`][crate::TransportHealthState::Down]
+    pub on_transport_recovery: bool,
+    /// Delay before the first retry of a failed re-registration, doubled on each further retry
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between retries
+    pub max_backoff: Duration,
+    /// Number of attempts at re-registering a listener before giving up until the next signal
+    pub max_attempts: u32,
+}
+
+impl Default for ListenerReconciliationConfig {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            on_transport_recovery: true,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            max_attempts: 10,
+        }
+    }
+}
@@ -0,0 +1,391 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use log::*;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use up_rust::{UAttributes, UCode, UMessage, UStatus};
+
+const MESSAGE_SCRIPT_TAG: &str = "MessageScript:";
+const MESSAGE_SCRIPT_FN_PRINT_TAG: &str = "print():";
+
+// the function a script has to define, which is called for each message
+const ENTRY_POINT: &str = "on_message";
+
+// bounds the strings, arrays, blobs and maps a script may build, so that it can't exhaust memory
+// within a single operation
+const MAX_VALUE_SIZE: usize = 1024 * 1024;
+
+// how many operations a script may run between looking at the clock
+const OPERATIONS_PER_DEADLINE_CHECK: u64 = 256;
+
+thread_local! {
+    // when the script running on this thread has to give up
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Bounds a single run of a [`MessageScript`]
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use up_streamer::ScriptLimits;
+///
+/// // a script which has to do some work, on a busy link
+/// let script_limits = ScriptLimits {
+///     max_operations: 1_000_000,
+///     max_duration: Duration::from_millis(50),
+///     max_messages: 4,
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptLimits {
+    /// Most operations, e.g. expressions, statements and function calls, a run may take
+    pub max_operations: u64,
+    /// Longest a run may take
+    pub max_duration: Duration,
+    /// Most messages a run may return to forward in place of the one it was given
+    pub max_messages: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 100_000,
+            max_duration: Duration::from_millis(10),
+            max_messages: 16,
+        }
+    }
+}
+
+/// A [Rhai](https://rhai.rs) script which rewrites or drops the messages a forwarding rule
+/// forwards, used with
+/// [`ForwardingRuleOptions::with_script`][crate::ForwardingRuleOptions::with_script]
+///
+/// The script has to define `on_message(msg)`, which is called with a map of the message's
+/// `attributes`, in the canonical proto3 JSON mapping with fields at their default value left out,
+/// and its `payload` as a blob. It returns the message to forward in the same shape, an array of
+/// messages to forward several, or `()` to drop it. A returned `payload` may also be a string.
+/// Every message returned is held to the access policy and authorities of the forwarding rule, as
+/// if it had arrived that way.
+///
+/// Scripts are sandboxed: they can't reach the file system, the network or other modules, `eval`
+/// isn't available and what they `print` ends up in the debug log. A run taking more than its
+/// [`ScriptLimits`] is aborted.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::{MessageScript, ScriptLimits};
+///
+/// // an ECU which sends its door status with a TTL that's far too short
+/// let message_script = MessageScript::new(
+///     r#"
+///         fn on_message(msg) {
+///             if msg.attributes.source.ueId == 0x4321 {
+///                 msg.attributes.ttl = 1000;
+///             }
+///             msg
+///         }
+///     "#,
+///     ScriptLimits::default(),
+/// )
+/// .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct MessageScript {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    max_duration: Duration,
+    max_messages: usize,
+}
+
+impl MessageScript {
+    /// Compiles `source`, failing with [`UCode::INVALID_ARGUMENT`] if it isn't valid or doesn't
+    /// define `on_message(msg)`
+    pub fn new(source: &str, limits: ScriptLimits) -> Result<Self, UStatus> {
+        let engine = sandboxed_engine(&limits);
+        let ast = engine.compile(source).map_err(|err| {
+            UStatus::fail_with_code(
+                UCode::INVALID_ARGUMENT,
+                format!("Invalid message script: {err}"),
+            )
+        })?;
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == ENTRY_POINT && function.params.len() == 1)
+        {
+            return Err(UStatus::fail_with_code(
+                UCode::INVALID_ARGUMENT,
+                format!("Message script doesn't define {ENTRY_POINT}(msg)"),
+            ));
+        }
+        Ok(Self {
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+            max_duration: limits.max_duration,
+            max_messages: limits.max_messages,
+        })
+    }
+
+    /// Runs the script on `msg`, returning the messages to forward in its place, or why it failed
+    pub(crate) fn run(&self, msg: &UMessage) -> Result<Vec<UMessage>, String> {
+        let mut message = Map::new();
+        message.insert(
+            "attributes".into(),
+            attributes_to_dynamic(msg.attributes.get_or_default())?,
+        );
+        message.insert(
+            "payload".into(),
+            Dynamic::from_blob(msg.payload.as_deref().unwrap_or_default().to_vec()),
+        );
+
+        let deadline = Instant::now() + self.max_duration;
+        DEADLINE.with(|running_deadline| running_deadline.set(Some(deadline)));
+        let result = self.engine.call_fn::<Dynamic>(
+            &mut Scope::new(),
+            &self.ast,
+            ENTRY_POINT,
+            (Dynamic::from_map(message),),
+        );
+        DEADLINE.with(|running_deadline| running_deadline.set(None));
+
+        let result = result.map_err(failure)?;
+        if result.is_unit() {
+            return Ok(Vec::new());
+        }
+        if result.is_array() {
+            let messages = result.cast::<Array>();
+            if messages.len() > self.max_messages {
+                return Err(format!(
+                    "returned {} messages, more than its max_messages",
+                    messages.len()
+                ));
+            }
+            return messages.iter().map(message_from_dynamic).collect();
+        }
+        Ok(vec![message_from_dynamic(&result)?])
+    }
+}
+
+// an engine which can do nothing but compute, within limits
+fn sandboxed_engine(limits: &ScriptLimits) -> Engine {
+    let mut engine = Engine::new();
+    // zero would mean no limit at all
+    engine.set_max_operations(limits.max_operations.max(1));
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(MAX_VALUE_SIZE);
+    engine.set_max_array_size(MAX_VALUE_SIZE);
+    engine.set_max_map_size(MAX_VALUE_SIZE);
+    // the default resolver would load modules from the file system
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.on_print(|text| {
+        debug!("{MESSAGE_SCRIPT_TAG}{MESSAGE_SCRIPT_FN_PRINT_TAG} {text}");
+    });
+    engine.on_debug(|text, _source, position| {
+        debug!("{MESSAGE_SCRIPT_TAG}{MESSAGE_SCRIPT_FN_PRINT_TAG} {position}: {text}");
+    });
+    engine.on_progress(|operations| {
+        if operations % OPERATIONS_PER_DEADLINE_CHECK != 0 {
+            return None;
+        }
+        let expired = DEADLINE.with(|deadline| {
+            deadline
+                .get()
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        });
+        expired.then_some(Dynamic::UNIT)
+    });
+    engine
+}
+
+fn failure(err: Box<EvalAltResult>) -> String {
+    match *err {
+        EvalAltResult::ErrorInFunctionCall(_, _, err, _) => failure(err),
+        EvalAltResult::ErrorTooManyOperations(_) => "exceeded its max_operations".to_string(),
+        EvalAltResult::ErrorTerminated(..) => "exceeded its max_duration".to_string(),
+        err => err.to_string(),
+    }
+}
+
+fn attributes_to_dynamic(attributes: &UAttributes) -> Result<Dynamic, String> {
+    let json = protobuf_json_mapping::print_to_string(attributes)
+        .map_err(|err| format!("unable to print attributes: {err}"))?;
+    let value: serde_json::Value = serde_json::from_str(&json)
+        .map_err(|err| format!("unable to read printed attributes: {err}"))?;
+    rhai::serde::to_dynamic(value).map_err(|err| format!("unable to pass attributes: {err}"))
+}
+
+fn message_from_dynamic(value: &Dynamic) -> Result<UMessage, String> {
+    let message = value
+        .clone()
+        .try_cast::<Map>()
+        .ok_or("script returned neither a message, an array of messages nor ()")?;
+
+    let attributes = match message.get("attributes") {
+        Some(attributes) => {
+            let value: serde_json::Value = rhai::serde::from_dynamic(attributes)
+                .map_err(|err| format!("script returned unreadable attributes: {err}"))?;
+            protobuf_json_mapping::parse_from_str::<UAttributes>(&value.to_string())
+                .map_err(|err| format!("script returned invalid attributes: {err}"))?
+        }
+        None => return Err("script returned a message without attributes".to_string()),
+    };
+    let payload = match message.get("payload") {
+        None => None,
+        Some(payload) if payload.is_unit() => None,
+        Some(payload) if payload.is_blob() => Some(payload.clone().cast::<Blob>()),
+        Some(payload) if payload.is_string() => Some(
+            payload
+                .clone()
+                .into_string()
+                .unwrap_or_default()
+                .into_bytes(),
+        ),
+        Some(payload) => {
+            return Err(format!(
+                "script returned a payload of type {}",
+                payload.type_name()
+            ))
+        }
+    };
+
+    Ok(UMessage {
+        attributes: Some(attributes).into(),
+        payload: payload
+            .filter(|payload| !payload.is_empty())
+            .map(Into::into),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessageScript, ScriptLimits};
+    use std::time::Duration;
+    use up_rust::{UAttributes, UMessage, UMessageType, UUri};
+
+    fn message(ttl: u32, payload: &str) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_PUBLISH.into(),
+                source: Some(UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: 0x4321,
+                    ue_version_major: 1,
+                    resource_id: 0x8001,
+                    ..Default::default()
+                })
+                .into(),
+                ttl: Some(ttl),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(payload.as_bytes().to_vec().into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_messages_are_rewritten_dropped_and_multiplied() {
+        let script = MessageScript::new(
+            r#"
+                fn on_message(msg) {
+                    let text = msg.payload.as_string();
+                    if text == "drop" {
+                        return ();
+                    }
+                    if text == "twice" {
+                        return [msg, msg];
+                    }
+                    msg.attributes.ttl = 1000;
+                    msg.payload = text + "!";
+                    msg
+                }
+            "#,
+            ScriptLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            script.run(&message(10, "hello")).unwrap(),
+            vec![message(1000, "hello!")]
+        );
+        assert!(script.run(&message(10, "drop")).unwrap().is_empty());
+        assert_eq!(
+            script.run(&message(10, "twice")).unwrap(),
+            vec![message(10, "twice"), message(10, "twice")]
+        );
+    }
+
+    #[test]
+    fn test_scripts_are_checked_and_limited() {
+        assert!(MessageScript::new("fn on_message(", ScriptLimits::default()).is_err());
+        assert!(MessageScript::new("fn transform(msg) { msg }", ScriptLimits::default()).is_err());
+
+        let spinning = "fn on_message(msg) { loop {} }";
+        let script = MessageScript::new(
+            spinning,
+            ScriptLimits {
+                max_operations: 1000,
+                max_duration: Duration::from_secs(10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            script.run(&message(10, "hello")),
+            Err("exceeded its max_operations".to_string())
+        );
+
+        let script = MessageScript::new(
+            spinning,
+            ScriptLimits {
+                max_operations: u64::MAX,
+                max_duration: Duration::from_millis(20),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            script.run(&message(10, "hello")),
+            Err("exceeded its max_duration".to_string())
+        );
+
+        let script = MessageScript::new(
+            r#"fn on_message(msg) { import "secrets" as secrets; msg }"#,
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        assert!(script.run(&message(10, "hello")).is_err());
+
+        let script = MessageScript::new(
+            "fn on_message(msg) { [msg, msg, msg] }",
+            ScriptLimits {
+                max_messages: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            script.run(&message(10, "hello")),
+            Err("returned 3 messages, more than its max_messages".to_string())
+        );
+    }
+}
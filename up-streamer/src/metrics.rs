@@ -0,0 +1,219 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A point-in-time snapshot of the counters kept by a [`UStreamer`][crate::UStreamer]
+///
+/// Obtained from [`UStreamer::metrics`][crate::UStreamer::metrics]. Counters only ever increase
+/// over the lifetime of the [`UStreamer`][crate::UStreamer].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamerMetrics {
+    /// Messages successfully sent on an out [`UTransport`][up_rust::UTransport]
+    pub messages_sent: u64,
+    /// Messages which an out [`UTransport`][up_rust::UTransport] failed to send
+    pub send_failures: u64,
+    /// Times a rule switched from its active out [`Endpoint`][crate::Endpoint] onto a standby
+    pub failovers: u64,
+    /// Times a rule switched back onto a more preferred out [`Endpoint`][crate::Endpoint]
+    pub failbacks: u64,
+    /// Messages dropped since their out [`UTransport`][up_rust::UTransport] was down
+    pub dropped_transport_down: u64,
+    /// Listeners registered again on an in [`UTransport`][up_rust::UTransport] which had lost them
+    pub listener_reregistrations: u64,
+    /// Listeners which couldn't be registered again within the configured number of attempts
+    pub listener_reregistration_failures: u64,
+    /// Messages dropped for exceeding the [`RateLimit`][crate::RateLimit] of their rule
+    pub rate_limited_dropped: u64,
+    /// Messages held back for exceeding the [`RateLimit`][crate::RateLimit] of their rule
+    pub rate_limited_delayed: u64,
+    /// Messages superseded by a newer one while held back by their rule's
+    /// [`ConflationConfig`][crate::ConflationConfig]
+    pub conflated_messages: u64,
+    /// Retained values forwarded on a newly added rule, see
+    /// [`Endpoint::with_retained_values`][crate::Endpoint::with_retained_values]
+    pub retained_values_replayed: u64,
+    /// Messages dropped since their payload didn't match their rule's
+    /// [`ContentFilter`][crate::ContentFilter]
+    pub content_filtered: u64,
+    /// Messages whose payload was converted by their rule's
+    /// [`PayloadTranslation`][crate::PayloadTranslation]
+    pub translated_messages: u64,
+    /// Messages dropped since their payload couldn't be converted
+    pub translation_failures: u64,
+    /// Messages dropped by their rule's [`MessageScript`][crate::MessageScript]
+    pub script_dropped: u64,
+    /// Messages dropped since their rule's [`MessageScript`][crate::MessageScript] failed or ran
+    /// out of its [`ScriptLimits`][crate::ScriptLimits]
+    pub script_failures: u64,
+    /// Messages dropped or rejected by their rule's [`WasmFilter`][crate::WasmFilter]
+    pub wasm_filtered: u64,
+    /// Messages dropped since their rule's [`WasmFilter`][crate::WasmFilter] or
+    /// [`MessageScript`][crate::MessageScript] addressed them to an authority the rule doesn't
+    /// forward to
+    pub rewritten_sink_mismatches: u64,
+    /// Messages dropped since their rule's [`WasmFilter`][crate::WasmFilter] failed or ran out of
+    /// its [`WasmFilterLimits`][crate::WasmFilterLimits]
+    pub wasm_filter_failures: u64,
+    /// Messages dropped since their source was over its [`SourceQuotaConfig`][crate::SourceQuotaConfig] quota
+    pub quota_dropped: u64,
+    /// Messages dropped since the [`AccessPolicy`][crate::AccessPolicy] denied forwarding them
+    pub access_denied: u64,
+    /// Messages dropped since their source authority didn't belong to the in
+    /// [`Endpoint`][crate::Endpoint] they arrived on
+    pub source_authority_mismatches: u64,
+    /// Messages dropped for exceeding the `max_payload_size` of their out
+    /// [`Endpoint`][crate::Endpoint]
+    pub oversize_rejected: u64,
+    /// Messages split into fragments for exceeding the `max_payload_size` of their out
+    /// [`Endpoint`][crate::Endpoint]
+    pub fragmented_messages: u64,
+    /// Fragmented messages reassembled from fragments arriving on an in
+    /// [`Endpoint`][crate::Endpoint]
+    pub reassembled_messages: u64,
+    /// Fragmented messages given up on, since fragments were malformed or missing or since they
+    /// exceeded the [`ReassemblyConfig`][crate::ReassemblyConfig]
+    pub reassembly_failures: u64,
+    /// Messages whose payload was compressed by a rule using
+    /// [`CompressionConfig`][crate::CompressionConfig]
+    pub compressed_messages: u64,
+    /// Compressed messages arriving on an in [`Endpoint`][crate::Endpoint] which were
+    /// decompressed
+    pub decompressed_messages: u64,
+    /// Compressed messages dropped since their payload couldn't be decompressed
+    pub decompression_failures: u64,
+    /// Messages signed and/or encrypted by a rule using
+    /// [`PayloadProtection`][crate::PayloadProtection]
+    pub protected_messages: u64,
+    /// Messages dropped since they couldn't be protected, failed their signature check or
+    /// decryption, or weren't protected though their rule requires it
+    pub protection_failures: u64,
+    /// Messages dropped by the [`ReplayProtectionConfig`][crate::ReplayProtectionConfig] of
+    /// their in [`Endpoint`][crate::Endpoint], since they were too old or seen before
+    pub replays_rejected: u64,
+    /// Forwarded requests whose TTL ran out before their response was forwarded, or which were
+    /// given up on to stay within the
+    /// [`RequestTrackingConfig`][crate::RequestTrackingConfig]'s `max_outstanding_requests`
+    pub expired_requests: u64,
+    /// Responses forwarded, or dropped, which answer no outstanding request
+    pub orphaned_responses: u64,
+    /// Responses forwarded, or dropped, which didn't take the way back their request came
+    pub misrouted_responses: u64,
+    /// [`UCode::DEADLINE_EXCEEDED`][up_rust::UCode::DEADLINE_EXCEEDED] responses sent for
+    /// expired requests, see
+    /// [`ForwardingRuleOptions::with_deadline_exceeded_responses`][crate::ForwardingRuleOptions::with_deadline_exceeded_responses]
+    pub deadline_exceeded_responses: u64,
+    /// Retransmitted requests answered from a rule's response cache, see
+    /// [`ForwardingRuleOptions::with_response_cache`][crate::ForwardingRuleOptions::with_response_cache]
+    pub cached_responses_sent: u64,
+    /// Retransmitted requests dropped as their first transmission was still awaiting its response
+    pub duplicate_requests_dropped: u64,
+}
+
+// the live counters behind StreamerMetrics, shared by everything doing the forwarding
+#[derive(Default)]
+pub(crate) struct StreamerCounters {
+    pub(crate) messages_sent: AtomicU64,
+    pub(crate) send_failures: AtomicU64,
+    pub(crate) failovers: AtomicU64,
+    pub(crate) failbacks: AtomicU64,
+    pub(crate) dropped_transport_down: AtomicU64,
+    pub(crate) listener_reregistrations: AtomicU64,
+    pub(crate) listener_reregistration_failures: AtomicU64,
+    pub(crate) rate_limited_dropped: AtomicU64,
+    pub(crate) rate_limited_delayed: AtomicU64,
+    pub(crate) conflated_messages: AtomicU64,
+    pub(crate) retained_values_replayed: AtomicU64,
+    pub(crate) content_filtered: AtomicU64,
+    pub(crate) translated_messages: AtomicU64,
+    pub(crate) translation_failures: AtomicU64,
+    pub(crate) script_dropped: AtomicU64,
+    pub(crate) script_failures: AtomicU64,
+    pub(crate) wasm_filtered: AtomicU64,
+    pub(crate) rewritten_sink_mismatches: AtomicU64,
+    pub(crate) wasm_filter_failures: AtomicU64,
+    pub(crate) quota_dropped: AtomicU64,
+    pub(crate) access_denied: AtomicU64,
+    pub(crate) source_authority_mismatches: AtomicU64,
+    pub(crate) oversize_rejected: AtomicU64,
+    pub(crate) fragmented_messages: AtomicU64,
+    pub(crate) reassembled_messages: AtomicU64,
+    pub(crate) reassembly_failures: AtomicU64,
+    pub(crate) compressed_messages: AtomicU64,
+    pub(crate) decompressed_messages: AtomicU64,
+    pub(crate) decompression_failures: AtomicU64,
+    pub(crate) protected_messages: AtomicU64,
+    pub(crate) protection_failures: AtomicU64,
+    pub(crate) replays_rejected: AtomicU64,
+    pub(crate) expired_requests: AtomicU64,
+    pub(crate) orphaned_responses: AtomicU64,
+    pub(crate) misrouted_responses: AtomicU64,
+    pub(crate) deadline_exceeded_responses: AtomicU64,
+    pub(crate) cached_responses_sent: AtomicU64,
+    pub(crate) duplicate_requests_dropped: AtomicU64,
+}
+
+impl StreamerCounters {
+    pub(crate) fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add(counter: &AtomicU64, amount: u64) {
+        counter.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> StreamerMetrics {
+        StreamerMetrics {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            send_failures: self.send_failures.load(Ordering::Relaxed),
+            failovers: self.failovers.load(Ordering::Relaxed),
+            failbacks: self.failbacks.load(Ordering::Relaxed),
+            dropped_transport_down: self.dropped_transport_down.load(Ordering::Relaxed),
+            listener_reregistrations: self.listener_reregistrations.load(Ordering::Relaxed),
+            listener_reregistration_failures: self
+                .listener_reregistration_failures
+                .load(Ordering::Relaxed),
+            rate_limited_dropped: self.rate_limited_dropped.load(Ordering::Relaxed),
+            rate_limited_delayed: self.rate_limited_delayed.load(Ordering::Relaxed),
+            conflated_messages: self.conflated_messages.load(Ordering::Relaxed),
+            retained_values_replayed: self.retained_values_replayed.load(Ordering::Relaxed),
+            content_filtered: self.content_filtered.load(Ordering::Relaxed),
+            translated_messages: self.translated_messages.load(Ordering::Relaxed),
+            translation_failures: self.translation_failures.load(Ordering::Relaxed),
+            script_dropped: self.script_dropped.load(Ordering::Relaxed),
+            script_failures: self.script_failures.load(Ordering::Relaxed),
+            wasm_filtered: self.wasm_filtered.load(Ordering::Relaxed),
+            rewritten_sink_mismatches: self.rewritten_sink_mismatches.load(Ordering::Relaxed),
+            wasm_filter_failures: self.wasm_filter_failures.load(Ordering::Relaxed),
+            quota_dropped: self.quota_dropped.load(Ordering::Relaxed),
+            access_denied: self.access_denied.load(Ordering::Relaxed),
+            source_authority_mismatches: self.source_authority_mismatches.load(Ordering::Relaxed),
+            oversize_rejected: self.oversize_rejected.load(Ordering::Relaxed),
+            fragmented_messages: self.fragmented_messages.load(Ordering::Relaxed),
+            reassembled_messages: self.reassembled_messages.load(Ordering::Relaxed),
+            reassembly_failures: self.reassembly_failures.load(Ordering::Relaxed),
+            compressed_messages: self.compressed_messages.load(Ordering::Relaxed),
+            decompressed_messages: self.decompressed_messages.load(Ordering::Relaxed),
+            decompression_failures: self.decompression_failures.load(Ordering::Relaxed),
+            protected_messages: self.protected_messages.load(Ordering::Relaxed),
+            protection_failures: self.protection_failures.load(Ordering::Relaxed),
+            replays_rejected: self.replays_rejected.load(Ordering::Relaxed),
+            expired_requests: self.expired_requests.load(Ordering::Relaxed),
+            orphaned_responses: self.orphaned_responses.load(Ordering::Relaxed),
+            misrouted_responses: self.misrouted_responses.load(Ordering::Relaxed),
+            deadline_exceeded_responses: self.deadline_exceeded_responses.load(Ordering::Relaxed),
+            cached_responses_sent: self.cached_responses_sent.load(Ordering::Relaxed),
+            duplicate_requests_dropped: self.duplicate_requests_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,556 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+// a protected message carries the PROTECTED_PAYLOAD_FORMAT as payload_format and a payload of,
+// all big-endian:
+//
+// | original payload_format: i32 | key_id: u32 | flags: u8 | nonce: 12 bytes, if encrypted |
+// | original payload, ChaCha20-Poly1305 encrypted if FLAG_ENCRYPTED |
+// | signature: 32 bytes HMAC-SHA256 or 64 bytes Ed25519, if signed |
+//
+// the attributes stay readable, as the transport in the middle routes by them, but all of them
+// but the payload_format are covered by the signature and, when encrypted, authenticated as
+// associated data, so they can't be tampered with either
+//
+// which checks a message went through is up to the receiving rule's ProtectionRequirement, not
+// to the flags, which are the sender's to set
+//
+// the ciphertext is signed, so the peer streamer checks the signature before decrypting
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Signer, Verifier};
+use hmac::{Hmac, Mac};
+use protobuf::EnumOrUnknown;
+use sha2::Sha256;
+use std::collections::HashMap;
+use up_rust::{UAttributes, UMessage, UUri};
+
+// well outside of the range used by UPayloadFormat, next to the COMPRESSED_PAYLOAD_FORMAT
+pub(crate) const PROTECTED_PAYLOAD_FORMAT: i32 = 0x7F03;
+const PROTECTION_HEADER_SIZE: usize = 4 + 4 + 1;
+const NONCE_SIZE: usize = 12;
+const HMAC_SIGNATURE_SIZE: usize = 32;
+const ED25519_SIGNATURE_SIZE: usize = 64;
+
+const FLAG_ENCRYPTED: u8 = 0x1;
+const FLAG_HMAC_SHA256: u8 = 0x2;
+const FLAG_ED25519: u8 = 0x4;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How a [`PayloadProtection`] signs messages
+#[derive(Clone)]
+pub enum Signing {
+    /// HMAC-SHA256 with a key shared with the peer [`UStreamer`][crate::UStreamer]
+    HmacSha256 { key: Vec<u8> },
+    /// Ed25519 with a secret key, whose public key the peer [`UStreamer`][crate::UStreamer]
+    /// verifies with
+    Ed25519 { secret_key: [u8; 32] },
+}
+
+/// Signs and/or encrypts the messages a forwarding rule forwards, used with
+/// [`ForwardingRuleOptions::with_protection`][crate::ForwardingRuleOptions::with_protection]
+///
+/// Meant for rules crossing an untrusted transport to a peer [`UStreamer`][crate::UStreamer],
+/// which holds the matching [`ProtectionKeys`] under the same `key_id`. The payload is encrypted,
+/// the attributes the message is routed by stay readable but are signed along with it.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::{PayloadProtection, Signing};
+///
+/// let protection = PayloadProtection {
+///     key_id: 1,
+///     signing: Some(Signing::HmacSha256 {
+///         key: b"a secret shared by both streamers".to_vec(),
+///     }),
+///     encryption_key: Some([0x42; 32]),
+/// };
+/// ```
+#[derive(Clone)]
+pub struct PayloadProtection {
+    /// Tells the peer [`UStreamer`][crate::UStreamer] which of its [`ProtectionKeys`] to use
+    pub key_id: u32,
+    pub signing: Option<Signing>,
+    /// A ChaCha20-Poly1305 key shared with the peer [`UStreamer`][crate::UStreamer]
+    pub encryption_key: Option<[u8; 32]>,
+}
+
+/// What a forwarding rule demands of the protection of the messages arriving on its in
+/// [`Endpoint`][crate::Endpoint], used with
+/// [`ForwardingRuleOptions::with_protection_required`][crate::ForwardingRuleOptions::with_protection_required]
+///
+/// Messages protected with less than this, e.g. not signed at all, are dropped, whatever the
+/// peer [`UStreamer`][crate::UStreamer] claims in them.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::ProtectionRequirement;
+///
+/// // only messages signed and encrypted with key 1 get through
+/// let protection_requirement = ProtectionRequirement {
+///     key_id: Some(1),
+///     encrypted: true,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtectionRequirement {
+    /// The `key_id` messages have to be protected with, any of the [`ProtectionKeys`] if `None`
+    pub key_id: Option<u32>,
+    /// Whether messages have to be signed, on by default
+    pub signed: bool,
+    /// Whether messages have to be encrypted
+    pub encrypted: bool,
+}
+
+impl Default for ProtectionRequirement {
+    fn default() -> Self {
+        Self {
+            key_id: None,
+            signed: true,
+            encrypted: false,
+        }
+    }
+}
+
+/// The keys a [`UStreamer`][crate::UStreamer] checks and decrypts protected messages with, set
+/// with [`UStreamer::set_protection_keys`][crate::UStreamer::set_protection_keys]
+///
+/// Keys are looked up by the `key_id` of the [`PayloadProtection`] the peer
+/// [`UStreamer`][crate::UStreamer] used.
+#[derive(Clone, Default)]
+pub struct ProtectionKeys {
+    hmac_keys: HashMap<u32, Vec<u8>>,
+    ed25519_public_keys: HashMap<u32, [u8; 32]>,
+    encryption_keys: HashMap<u32, [u8; 32]>,
+}
+
+impl ProtectionKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_hmac_key(mut self, key_id: u32, key: Vec<u8>) -> Self {
+        self.hmac_keys.insert(key_id, key);
+        self
+    }
+
+    pub fn with_ed25519_public_key(mut self, key_id: u32, public_key: [u8; 32]) -> Self {
+        self.ed25519_public_keys.insert(key_id, public_key);
+        self
+    }
+
+    pub fn with_encryption_key(mut self, key_id: u32, key: [u8; 32]) -> Self {
+        self.encryption_keys.insert(key_id, key);
+        self
+    }
+}
+
+pub(crate) fn is_protected(msg: &UMessage) -> bool {
+    msg.attributes
+        .as_ref()
+        .is_some_and(|attributes| attributes.payload_format.value() == PROTECTED_PAYLOAD_FORMAT)
+}
+
+fn write_optional_u32(data: &mut Vec<u8>, value: Option<u32>) {
+    match value {
+        Some(value) => {
+            data.push(1);
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        None => data.push(0),
+    }
+}
+
+fn write_uri(data: &mut Vec<u8>, uri: Option<&UUri>) {
+    let Some(uri) = uri else {
+        data.push(0);
+        return;
+    };
+    data.push(1);
+    data.extend_from_slice(&(uri.authority_name.len() as u32).to_be_bytes());
+    data.extend_from_slice(uri.authority_name.as_bytes());
+    data.extend_from_slice(&uri.ue_id.to_be_bytes());
+    data.extend_from_slice(&uri.ue_version_major.to_be_bytes());
+    data.extend_from_slice(&uri.resource_id.to_be_bytes());
+}
+
+// the attributes covered by the signature, all but the payload_format, which protection replaces
+fn authenticated_attributes(attributes: &UAttributes) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&attributes.id.msb.to_be_bytes());
+    data.extend_from_slice(&attributes.id.lsb.to_be_bytes());
+    data.extend_from_slice(&attributes.type_.value().to_be_bytes());
+    write_uri(&mut data, attributes.source.as_ref());
+    write_uri(&mut data, attributes.sink.as_ref());
+    data.extend_from_slice(&attributes.reqid.msb.to_be_bytes());
+    data.extend_from_slice(&attributes.reqid.lsb.to_be_bytes());
+    data.extend_from_slice(&attributes.priority.value().to_be_bytes());
+    write_optional_u32(&mut data, attributes.ttl);
+    write_optional_u32(&mut data, attributes.permission_level);
+    write_optional_u32(
+        &mut data,
+        attributes
+            .commstatus
+            .map(|commstatus| commstatus.value() as u32),
+    );
+    match &attributes.token {
+        Some(token) => {
+            data.push(1);
+            data.extend_from_slice(&(token.len() as u32).to_be_bytes());
+            data.extend_from_slice(token.as_bytes());
+        }
+        None => data.push(0),
+    }
+    data
+}
+
+fn hmac_sha256(key: &[u8]) -> HmacSha256 {
+    // HMAC takes keys of any length
+    <HmacSha256 as Mac>::new_from_slice(key).unwrap()
+}
+
+/// Returns `msg` with its payload protected as `protection` asks for
+pub(crate) fn protect(
+    msg: &UMessage,
+    protection: &PayloadProtection,
+) -> Result<UMessage, &'static str> {
+    let attributes = msg
+        .attributes
+        .as_ref()
+        .ok_or("message without attributes")?;
+    let payload = msg.payload.clone().unwrap_or_default();
+    let authenticated_attributes = authenticated_attributes(attributes);
+
+    let mut flags = 0;
+    if protection.encryption_key.is_some() {
+        flags |= FLAG_ENCRYPTED;
+    }
+    match protection.signing {
+        Some(Signing::HmacSha256 { .. }) => flags |= FLAG_HMAC_SHA256,
+        Some(Signing::Ed25519 { .. }) => flags |= FLAG_ED25519,
+        None => {}
+    }
+
+    let mut protected_payload = Vec::with_capacity(
+        PROTECTION_HEADER_SIZE + NONCE_SIZE + payload.len() + ED25519_SIGNATURE_SIZE,
+    );
+    protected_payload.extend_from_slice(&attributes.payload_format.value().to_be_bytes());
+    protected_payload.extend_from_slice(&protection.key_id.to_be_bytes());
+    protected_payload.push(flags);
+
+    match &protection.encryption_key {
+        Some(encryption_key) => {
+            let cipher = ChaCha20Poly1305::new(Key::from_slice(encryption_key));
+            let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let aad = [authenticated_attributes.as_slice(), &protected_payload].concat();
+            let ciphertext = cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: &payload,
+                        aad: &aad,
+                    },
+                )
+                .map_err(|_| "unable to encrypt payload")?;
+            protected_payload.extend_from_slice(&nonce);
+            protected_payload.extend_from_slice(&ciphertext);
+        }
+        None => protected_payload.extend_from_slice(&payload),
+    }
+
+    let signed = [authenticated_attributes.as_slice(), &protected_payload].concat();
+    match &protection.signing {
+        Some(Signing::HmacSha256 { key }) => {
+            let mut mac = hmac_sha256(key);
+            mac.update(&signed);
+            protected_payload.extend_from_slice(&mac.finalize().into_bytes());
+        }
+        Some(Signing::Ed25519 { secret_key }) => {
+            let signing_key = ed25519_dalek::SigningKey::from_bytes(secret_key);
+            protected_payload.extend_from_slice(&signing_key.sign(&signed).to_bytes());
+        }
+        None => {}
+    }
+
+    let mut protected_msg = msg.clone();
+    protected_msg
+        .attributes
+        .mut_or_insert_default()
+        .payload_format = EnumOrUnknown::from_i32(PROTECTED_PAYLOAD_FORMAT);
+    protected_msg.payload = Some(protected_payload.into());
+    Ok(protected_msg)
+}
+
+/// Checks and decrypts a message protected by [`protect`], restoring the original, provided it
+/// was protected as `requirement` demands
+pub(crate) fn unprotect(
+    mut msg: UMessage,
+    keys: &ProtectionKeys,
+    requirement: Option<&ProtectionRequirement>,
+) -> Result<UMessage, &'static str> {
+    let payload = msg.payload.take().unwrap_or_default();
+    let authenticated_attributes = authenticated_attributes(&msg.attributes);
+
+    let header = payload
+        .get(..PROTECTION_HEADER_SIZE)
+        .ok_or("malformed protection header")?;
+    let payload_format = i32::from_be_bytes(header[0..4].try_into().unwrap());
+    let key_id = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let flags = header[8];
+
+    let signature_size = match flags & (FLAG_HMAC_SHA256 | FLAG_ED25519) {
+        0 => 0,
+        FLAG_HMAC_SHA256 => HMAC_SIGNATURE_SIZE,
+        FLAG_ED25519 => ED25519_SIGNATURE_SIZE,
+        _ => return Err("malformed protection flags"),
+    };
+    if let Some(requirement) = requirement {
+        if requirement
+            .key_id
+            .is_some_and(|required_key_id| required_key_id != key_id)
+        {
+            return Err("protected with another key than required");
+        }
+        if requirement.signed && signature_size == 0 {
+            return Err("not signed as required");
+        }
+        if requirement.encrypted && flags & FLAG_ENCRYPTED == 0 {
+            return Err("not encrypted as required");
+        }
+    }
+    let signed_len = payload
+        .len()
+        .checked_sub(signature_size)
+        .filter(|signed_len| *signed_len >= PROTECTION_HEADER_SIZE)
+        .ok_or("malformed protected payload")?;
+    let (signed_payload, signature) = payload.split_at(signed_len);
+    let signed = [authenticated_attributes.as_slice(), signed_payload].concat();
+
+    if flags & FLAG_HMAC_SHA256 != 0 {
+        let key = keys.hmac_keys.get(&key_id).ok_or("unknown HMAC key")?;
+        let mut mac = hmac_sha256(key);
+        mac.update(&signed);
+        mac.verify_slice(signature)
+            .map_err(|_| "invalid HMAC signature")?;
+    }
+    if flags & FLAG_ED25519 != 0 {
+        let public_key = keys
+            .ed25519_public_keys
+            .get(&key_id)
+            .ok_or("unknown Ed25519 public key")?;
+        let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(public_key)
+            .map_err(|_| "invalid Ed25519 public key")?;
+        let signature = ed25519_dalek::Signature::from_bytes(signature.try_into().unwrap());
+        verifying_key
+            .verify(&signed, &signature)
+            .map_err(|_| "invalid Ed25519 signature")?;
+    }
+
+    let body = &signed_payload[PROTECTION_HEADER_SIZE..];
+    let original_payload = if flags & FLAG_ENCRYPTED != 0 {
+        let key = keys
+            .encryption_keys
+            .get(&key_id)
+            .ok_or("unknown encryption key")?;
+        if body.len() < NONCE_SIZE {
+            return Err("malformed protected payload");
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_SIZE);
+        let aad = [authenticated_attributes.as_slice(), header].concat();
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| "unable to decrypt payload")?
+    } else {
+        body.to_vec()
+    };
+
+    msg.attributes.mut_or_insert_default().payload_format = EnumOrUnknown::from_i32(payload_format);
+    msg.payload = Some(original_payload.into());
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_protected, protect, unprotect, PayloadProtection, ProtectionKeys, ProtectionRequirement,
+        Signing,
+    };
+    use up_rust::{UAttributes, UMessage, UPayloadFormat, UUIDBuilder, UUri};
+
+    fn message() -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                id: Some(UUIDBuilder::build()).into(),
+                sink: Some(UUri {
+                    authority_name: "remote".to_string(),
+                    ue_id: 0x1234,
+                    ..Default::default()
+                })
+                .into(),
+                payload_format: UPayloadFormat::UPAYLOAD_FORMAT_TEXT.into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(b"unlock the doors".to_vec().into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_signed_and_encrypted_message_round_trips_and_detects_tampering() {
+        let protection = PayloadProtection {
+            key_id: 7,
+            signing: Some(Signing::HmacSha256 {
+                key: b"shared secret".to_vec(),
+            }),
+            encryption_key: Some([0x42; 32]),
+        };
+        let keys = ProtectionKeys::new()
+            .with_hmac_key(7, b"shared secret".to_vec())
+            .with_encryption_key(7, [0x42; 32]);
+
+        let msg = message();
+        let protected = protect(&msg, &protection).unwrap();
+        assert!(is_protected(&protected));
+        let protected_payload = protected.payload.clone().unwrap();
+        assert!(!protected_payload
+            .windows(6)
+            .any(|window| window == b"unlock"));
+        assert_eq!(unprotect(protected.clone(), &keys, None).unwrap(), msg);
+
+        // redirecting the message breaks the signature
+        let mut redirected = protected.clone();
+        redirected
+            .attributes
+            .mut_or_insert_default()
+            .sink
+            .mut_or_insert_default()
+            .authority_name = "elsewhere".to_string();
+        assert!(unprotect(redirected, &keys, None).is_err());
+
+        // as does stretching its TTL
+        let mut stretched = protected.clone();
+        stretched.attributes.mut_or_insert_default().ttl = Some(60_000);
+        assert!(unprotect(stretched, &keys, None).is_err());
+
+        // as does not knowing the key
+        let other_keys = ProtectionKeys::new()
+            .with_hmac_key(7, b"another secret".to_vec())
+            .with_encryption_key(7, [0x42; 32]);
+        assert!(unprotect(protected, &other_keys, None).is_err());
+    }
+
+    #[test]
+    fn test_ed25519_signature_is_checked_with_public_key() {
+        let secret_key = [0x11; 32];
+        let public_key = ed25519_dalek::SigningKey::from_bytes(&secret_key)
+            .verifying_key()
+            .to_bytes();
+        let protection = PayloadProtection {
+            key_id: 1,
+            signing: Some(Signing::Ed25519 { secret_key }),
+            encryption_key: None,
+        };
+
+        let msg = message();
+        let protected = protect(&msg, &protection).unwrap();
+        assert_eq!(
+            unprotect(
+                protected.clone(),
+                &ProtectionKeys::new().with_ed25519_public_key(1, public_key),
+                None
+            )
+            .unwrap(),
+            msg
+        );
+
+        let other_public_key = ed25519_dalek::SigningKey::from_bytes(&[0x22; 32])
+            .verifying_key()
+            .to_bytes();
+        assert!(unprotect(
+            protected,
+            &ProtectionKeys::new().with_ed25519_public_key(1, other_public_key),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_messages_protected_with_less_than_required_are_rejected() {
+        let keys = ProtectionKeys::new().with_hmac_key(1, b"shared secret".to_vec());
+        let msg = message();
+
+        // a sender can't opt out of the checks by leaving out the flags
+        let unsigned = protect(
+            &msg,
+            &PayloadProtection {
+                key_id: 1,
+                signing: None,
+                encryption_key: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(unprotect(unsigned.clone(), &keys, None).unwrap(), msg);
+        assert!(unprotect(unsigned, &keys, Some(&ProtectionRequirement::default())).is_err());
+
+        let signed = protect(
+            &msg,
+            &PayloadProtection {
+                key_id: 1,
+                signing: Some(Signing::HmacSha256 {
+                    key: b"shared secret".to_vec(),
+                }),
+                encryption_key: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            unprotect(
+                signed.clone(),
+                &keys,
+                Some(&ProtectionRequirement::default())
+            )
+            .unwrap(),
+            msg
+        );
+        assert!(unprotect(
+            signed.clone(),
+            &keys,
+            Some(&ProtectionRequirement {
+                key_id: Some(2),
+                ..Default::default()
+            })
+        )
+        .is_err());
+        assert!(unprotect(
+            signed,
+            &keys,
+            Some(&ProtectionRequirement {
+                encrypted: true,
+                ..Default::default()
+            })
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,272 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use crate::descriptor_registry::DescriptorRegistry;
+use protobuf::well_known_types::any::Any;
+use protobuf::Message;
+use up_rust::{UMessage, UPayloadFormat};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    ProtobufToJson,
+    JsonToProtobuf,
+}
+
+/// Converts the payloads a forwarding rule forwards between protobuf and JSON, used with
+/// [`ForwardingRuleOptions::with_payload_translation`][crate::ForwardingRuleOptions::with_payload_translation]
+///
+/// Uses the canonical proto3 JSON mapping, with the message types looked up in a
+/// [`DescriptorRegistry`][crate::DescriptorRegistry]. The `payload_format` of translated messages
+/// is updated to match. Messages in any other format, including an unspecified one, without a
+/// payload or whose message type can't be told are forwarded as they are, only payloads which
+/// don't parse as their known message type fail.
+///
+/// # Examples
+///
+/// ```
+/// use protobuf::well_known_types::timestamp;
+/// use up_streamer::{DescriptorRegistry, PayloadTranslation};
+///
+/// let descriptors = DescriptorRegistry::new().with_file_descriptor(timestamp::file_descriptor());
+/// // for consumers on the other side which would rather have JSON
+/// let to_json = PayloadTranslation::protobuf_to_json(descriptors.clone());
+/// // and back for those which send JSON
+/// let to_protobuf = PayloadTranslation::json_to_protobuf(descriptors, "google.protobuf.Timestamp");
+/// ```
+#[derive(Clone)]
+pub struct PayloadTranslation {
+    direction: Direction,
+    descriptors: DescriptorRegistry,
+    message_type: Option<String>,
+}
+
+impl PayloadTranslation {
+    /// Translates protobuf payloads, also those wrapped in an `Any`, into JSON
+    ///
+    /// Plain protobuf payloads don't say what type they are, so those are only translated once
+    /// it's given with [`PayloadTranslation::with_message_type`].
+    pub fn protobuf_to_json(descriptors: DescriptorRegistry) -> Self {
+        Self {
+            direction: Direction::ProtobufToJson,
+            descriptors,
+            message_type: None,
+        }
+    }
+
+    /// Translates JSON payloads into plain protobuf payloads of `message_type`
+    pub fn json_to_protobuf(descriptors: DescriptorRegistry, message_type: &str) -> Self {
+        Self {
+            direction: Direction::JsonToProtobuf,
+            descriptors,
+            message_type: Some(message_type.to_string()),
+        }
+    }
+
+    /// Takes protobuf payloads to be `message_type`, those wrapped in an `Any` of another type
+    /// aren't translated
+    pub fn with_message_type(mut self, message_type: &str) -> Self {
+        self.message_type = Some(message_type.to_string());
+        self
+    }
+
+    /// Returns `msg` translated, or `None` if it isn't in the format translated from or its
+    /// message type can't be told
+    pub(crate) fn translate(&self, msg: &UMessage) -> Result<Option<UMessage>, &'static str> {
+        let Some(attributes) = msg.attributes.as_ref() else {
+            return Ok(None);
+        };
+        let payload_format = attributes
+            .payload_format
+            .enum_value_or(UPayloadFormat::UPAYLOAD_FORMAT_RAW);
+        let is_json = payload_format == UPayloadFormat::UPAYLOAD_FORMAT_JSON;
+        let is_protobuf = matches!(
+            payload_format,
+            UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF
+                | UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF_WRAPPED_IN_ANY
+        );
+        let payload = msg.payload.as_deref().unwrap_or_default();
+        if payload.is_empty() {
+            return Ok(None);
+        }
+
+        let (translated_format, translated_payload) = match self.direction {
+            Direction::ProtobufToJson if is_protobuf => {
+                if !self.is_of_known_type(payload_format, payload) {
+                    return Ok(None);
+                }
+                let parsed = self
+                    .descriptors
+                    .parse(payload_format, payload, self.message_type.as_deref())
+                    .ok_or("unable to parse protobuf payload of a known message type")?;
+                let json = protobuf_json_mapping::print_to_string(parsed.as_ref())
+                    .map_err(|_| "unable to print protobuf payload as JSON")?;
+                (UPayloadFormat::UPAYLOAD_FORMAT_JSON, json.into_bytes())
+            }
+            Direction::JsonToProtobuf if is_json => {
+                let descriptor = self
+                    .message_type
+                    .as_deref()
+                    .and_then(|message_type| self.descriptors.message(message_type))
+                    .ok_or("unknown message type")?;
+                let json = std::str::from_utf8(payload).map_err(|_| "JSON payload isn't UTF-8")?;
+                let parsed = protobuf_json_mapping::parse_dyn_from_str(descriptor, json)
+                    .map_err(|_| "unable to parse JSON payload as the message type")?;
+                let protobuf = parsed
+                    .write_to_bytes_dyn()
+                    .map_err(|_| "unable to serialize protobuf payload")?;
+                (UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF, protobuf)
+            }
+            _ => return Ok(None),
+        };
+
+        let mut translated_msg = msg.clone();
+        translated_msg
+            .attributes
+            .mut_or_insert_default()
+            .payload_format = translated_format.into();
+        translated_msg.payload = Some(translated_payload.into());
+        Ok(Some(translated_msg))
+    }
+
+    // whether we can tell the message type of a protobuf payload and have it in our registry
+    fn is_of_known_type(&self, payload_format: UPayloadFormat, payload: &[u8]) -> bool {
+        let message_type = if payload_format == UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF {
+            self.message_type.clone()
+        } else {
+            Any::parse_from_bytes(payload)
+                .ok()
+                .and_then(|any| any.type_url.rsplit('/').next().map(str::to_string))
+                .filter(|any_type| {
+                    self.message_type
+                        .as_ref()
+                        .map_or(true, |message_type| message_type == any_type)
+                })
+        };
+        message_type.is_some_and(|message_type| self.descriptors.message(&message_type).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PayloadTranslation;
+    use crate::descriptor_registry::tests::{hello_file_descriptor, hello_request};
+    use crate::DescriptorRegistry;
+    use up_rust::{UAttributes, UMessage, UPayloadFormat};
+
+    fn message(payload_format: UPayloadFormat, payload: Vec<u8>) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                payload_format: payload_format.into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(payload.into()),
+            ..Default::default()
+        }
+    }
+
+    fn json_of(msg: &UMessage) -> serde_json::Value {
+        serde_json::from_slice(msg.payload.as_ref().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_payloads_round_trip_between_protobuf_and_json() {
+        let descriptors = DescriptorRegistry::new().with_file_descriptor(&hello_file_descriptor());
+        let protobuf = message(
+            UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF,
+            hello_request("Alice", 3),
+        );
+
+        let to_json = PayloadTranslation::protobuf_to_json(descriptors.clone())
+            .with_message_type("example.hello.HelloRequest");
+        let json = to_json.translate(&protobuf).unwrap().unwrap();
+        assert_eq!(
+            json.attributes.payload_format.enum_value(),
+            Ok(UPayloadFormat::UPAYLOAD_FORMAT_JSON)
+        );
+        assert_eq!(
+            json_of(&json),
+            serde_json::json!({"name": "Alice", "count": 3})
+        );
+
+        let to_protobuf =
+            PayloadTranslation::json_to_protobuf(descriptors, "example.hello.HelloRequest");
+        assert_eq!(to_protobuf.translate(&json).unwrap(), Some(protobuf));
+    }
+
+    #[test]
+    fn test_other_formats_pass_and_untranslatable_payloads_fail() {
+        let to_json = PayloadTranslation::protobuf_to_json(
+            DescriptorRegistry::new().with_file_descriptor(&hello_file_descriptor()),
+        );
+        let text = message(UPayloadFormat::UPAYLOAD_FORMAT_TEXT, b"hello".to_vec());
+        assert_eq!(to_json.translate(&text).unwrap(), None);
+
+        let to_protobuf = PayloadTranslation::json_to_protobuf(
+            DescriptorRegistry::new().with_file_descriptor(&hello_file_descriptor()),
+            "example.hello.HelloRequest",
+        );
+        assert!(to_protobuf
+            .translate(&message(
+                UPayloadFormat::UPAYLOAD_FORMAT_JSON,
+                br#"{"unknown": 1}"#.to_vec()
+            ))
+            .is_err());
+
+        // a payload of a known message type which doesn't parse as it is broken
+        let to_json = to_json.with_message_type("example.hello.HelloRequest");
+        assert!(to_json
+            .translate(&message(
+                UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF,
+                vec![0xff, 0xff, 0xff]
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_payloads_of_untold_message_types_pass() {
+        let to_json = PayloadTranslation::protobuf_to_json(
+            DescriptorRegistry::new().with_file_descriptor(&hello_file_descriptor()),
+        );
+        let protobuf = message(
+            UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF,
+            hello_request("Alice", 3),
+        );
+
+        // without a message type, a plain protobuf payload can't be told apart
+        assert_eq!(to_json.translate(&protobuf).unwrap(), None);
+        assert_eq!(
+            to_json
+                .translate(&message(
+                    UPayloadFormat::UPAYLOAD_FORMAT_UNSPECIFIED,
+                    hello_request("Alice", 3)
+                ))
+                .unwrap(),
+            None
+        );
+        let to_json = to_json.with_message_type("example.hello.HelloRequest");
+        assert_eq!(
+            to_json
+                .translate(&message(
+                    UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF,
+                    Vec::new()
+                ))
+                .unwrap(),
+            None
+        );
+
+        let to_json = PayloadTranslation::protobuf_to_json(DescriptorRegistry::new())
+            .with_message_type("example.hello.HelloRequest");
+        assert_eq!(to_json.translate(&protobuf).unwrap(), None);
+    }
+}
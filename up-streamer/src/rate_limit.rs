@@ -0,0 +1,243 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use up_rust::{UCode, UStatus};
+
+/// What happens to a message which exceeds a [`RateLimit`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// The message is dropped
+    #[default]
+    Drop,
+    /// The message is held back until it fits within the [`RateLimit`], which in turn holds back
+    /// the in [`UTransport`][up_rust::UTransport]'s listener
+    Delay,
+}
+
+/// Limits the traffic of a forwarding rule, used with
+/// [`ForwardingRuleOptions::with_rate_limit`][crate::ForwardingRuleOptions::with_rate_limit]
+///
+/// Both limits are token buckets, refilled at the given rate up to their burst. A burst of `0`
+/// allows one second's worth of traffic. A rate of `0` would never forward anything, so a rule
+/// with one can't be added.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::{RateLimit, RateLimitPolicy};
+///
+/// // at most 1 Mbit/s, with bursts of up to 64 KiB, and never more than 500 messages per second
+/// let rate_limit = RateLimit {
+///     messages_per_second: Some(500),
+///     bytes_per_second: Some(125_000),
+///     byte_burst: 64 * 1024,
+///     policy: RateLimitPolicy::Delay,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Messages forwarded per second, unlimited if `None`
+    pub messages_per_second: Option<u32>,
+    /// Most messages which may be forwarded at once, `messages_per_second` if `0`
+    pub message_burst: u32,
+    /// Payload bytes forwarded per second, unlimited if `None`
+    pub bytes_per_second: Option<u64>,
+    /// Most payload bytes which may be forwarded at once, `bytes_per_second` if `0`
+    pub byte_burst: u64,
+    /// What happens to messages exceeding either limit
+    pub policy: RateLimitPolicy,
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: f64) -> Self {
+        let capacity = if burst > 0.0 { burst } else { rate };
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+    }
+
+    // how long until `amount` tokens can be taken, a full bucket admits anything so that a
+    // message larger than the burst still gets through eventually
+    fn wait_for(&self, amount: f64) -> Duration {
+        let needed = amount.min(self.capacity);
+        if self.tokens >= needed {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((needed - self.tokens) / self.rate)
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+}
+
+struct RateLimiterState {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    last_refill: Instant,
+}
+
+// enforces a RateLimit for a single forwarding rule
+pub(crate) struct RateLimiter {
+    policy: RateLimitPolicy,
+    state: Mutex<RateLimiterState>,
+}
+
+impl RateLimiter {
+    /// Fails with [`UCode::INVALID_ARGUMENT`] if either rate is `0`
+    pub(crate) fn new(rate_limit: &RateLimit) -> Result<Self, UStatus> {
+        if rate_limit.messages_per_second == Some(0) || rate_limit.bytes_per_second == Some(0) {
+            return Err(UStatus::fail_with_code(
+                UCode::INVALID_ARGUMENT,
+                "Rate limit of 0 per second",
+            ));
+        }
+        Ok(Self {
+            policy: rate_limit.policy,
+            state: Mutex::new(RateLimiterState {
+                messages: rate_limit
+                    .messages_per_second
+                    .map(|rate| TokenBucket::new(rate as f64, rate_limit.message_burst as f64)),
+                bytes: rate_limit
+                    .bytes_per_second
+                    .map(|rate| TokenBucket::new(rate as f64, rate_limit.byte_burst as f64)),
+                last_refill: Instant::now(),
+            }),
+        })
+    }
+
+    pub(crate) fn policy(&self) -> RateLimitPolicy {
+        self.policy
+    }
+
+    /// Admits a message of `size` payload bytes if it fits within the limits, otherwise returns
+    /// how long until it would
+    pub(crate) fn try_acquire(&self, size: usize) -> Result<(), Duration> {
+        self.try_acquire_at(size, Instant::now())
+    }
+
+    fn try_acquire_at(&self, size: usize, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        state.last_refill = now;
+
+        let size = size as f64;
+        let mut wait = Duration::ZERO;
+        if let Some(messages) = state.messages.as_mut() {
+            messages.refill(elapsed);
+            wait = wait.max(messages.wait_for(1.0));
+        }
+        if let Some(bytes) = state.bytes.as_mut() {
+            bytes.refill(elapsed);
+            wait = wait.max(bytes.wait_for(size));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        if let Some(messages) = state.messages.as_mut() {
+            messages.take(1.0);
+        }
+        if let Some(bytes) = state.bytes.as_mut() {
+            bytes.take(size);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RateLimiter};
+    use std::time::{Duration, Instant};
+    use up_rust::UCode;
+
+    #[test]
+    fn test_message_rate_with_burst() {
+        let rate_limiter = RateLimiter::new(&RateLimit {
+            messages_per_second: Some(10),
+            message_burst: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let start = Instant::now();
+
+        assert!(rate_limiter.try_acquire_at(0, start).is_ok());
+        assert!(rate_limiter.try_acquire_at(0, start).is_ok());
+        let wait = rate_limiter.try_acquire_at(0, start).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100));
+
+        // one message every 100ms
+        assert!(rate_limiter
+            .try_acquire_at(0, start + Duration::from_millis(100))
+            .is_ok());
+        assert!(rate_limiter
+            .try_acquire_at(0, start + Duration::from_millis(150))
+            .is_err());
+    }
+
+    #[test]
+    fn test_byte_rate_admits_oversized_message_from_full_bucket() {
+        let rate_limiter = RateLimiter::new(&RateLimit {
+            bytes_per_second: Some(1000),
+            byte_burst: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        let start = Instant::now();
+
+        assert!(rate_limiter.try_acquire_at(250, start).is_ok());
+        // the bucket is now in debt for the 150 bytes above the burst
+        let wait = rate_limiter.try_acquire_at(10, start).unwrap_err();
+        assert!(wait >= Duration::from_millis(150));
+        assert!(rate_limiter
+            .try_acquire_at(10, start + Duration::from_millis(200))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_rates_of_zero_are_rejected() {
+        for rate_limit in [
+            RateLimit {
+                messages_per_second: Some(0),
+                ..Default::default()
+            },
+            RateLimit {
+                bytes_per_second: Some(0),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(
+                RateLimiter::new(&rate_limit)
+                    .err()
+                    .map(|err| err.get_code()),
+                Some(UCode::INVALID_ARGUMENT)
+            );
+        }
+    }
+}
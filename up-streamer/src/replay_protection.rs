@@ -0,0 +1,214 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use up_rust::{UMessage, UUID};
+
+/// Rejects messages arriving on an in [`Endpoint`][crate::Endpoint] which are too old or were
+/// seen before, used with
+/// [`Endpoint::with_replay_protection`][crate::Endpoint::with_replay_protection]
+///
+/// How old a message is, is told by the timestamp in the first 48 bits of its `id`, the
+/// milliseconds since the UNIX epoch of uProtocol's UUIDs. Together with a signing
+/// [`PayloadProtection`][crate::PayloadProtection], which covers the `id`, captured messages can't
+/// be re-injected.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use up_streamer::ReplayProtectionConfig;
+///
+/// let replay_protection_config = ReplayProtectionConfig {
+///     window: Duration::from_secs(10),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayProtectionConfig {
+    /// How old a message may be, the ids of the messages within it are remembered
+    pub window: Duration,
+    /// How far ahead of our clock a message's timestamp may be, to allow for clocks drifting apart
+    pub max_clock_skew: Duration,
+}
+
+impl Default for ReplayProtectionConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(30),
+            max_clock_skew: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Why a message was refused by [`ReplayGuard::check`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ReplayRejection {
+    MissingId,
+    TooOld,
+    FromTheFuture,
+    AlreadySeen,
+}
+
+// the milliseconds since the UNIX epoch in the first 48 bits of a uProtocol UUID
+fn timestamp_millis(id: &UUID) -> u64 {
+    id.msb >> 16
+}
+
+// the ids seen on a single in endpoint, ordered by their timestamp so that those which left the
+// window are cheap to forget
+//
+// every rule from the in endpoint hears the same messages, so the ids are remembered per rule,
+// lest the first rule to hear a message have the others reject it
+pub(crate) struct ReplayGuard {
+    config: ReplayProtectionConfig,
+    seen: Mutex<BTreeSet<(u64, u64, u64, u64)>>,
+}
+
+impl ReplayGuard {
+    pub(crate) fn new(config: ReplayProtectionConfig) -> Self {
+        Self {
+            config,
+            seen: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Remembers the id of `msg` for the rule `rule_id`, unless it is a replay
+    pub(crate) fn check(&self, rule_id: u64, msg: &UMessage) -> Result<(), ReplayRejection> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.check_at(rule_id, msg, now)
+    }
+
+    fn check_at(
+        &self,
+        rule_id: u64,
+        msg: &UMessage,
+        now_millis: u64,
+    ) -> Result<(), ReplayRejection> {
+        let id = msg
+            .attributes
+            .as_ref()
+            .and_then(|attributes| attributes.id.as_ref())
+            .ok_or(ReplayRejection::MissingId)?;
+        let timestamp = timestamp_millis(id);
+        let window_start = now_millis.saturating_sub(self.config.window.as_millis() as u64);
+
+        if timestamp < window_start {
+            return Err(ReplayRejection::TooOld);
+        }
+        if timestamp > now_millis + self.config.max_clock_skew.as_millis() as u64 {
+            return Err(ReplayRejection::FromTheFuture);
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        *seen = seen.split_off(&(window_start, 0, 0, 0));
+        if !seen.insert((timestamp, rule_id, id.msb, id.lsb)) {
+            return Err(ReplayRejection::AlreadySeen);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplayGuard, ReplayProtectionConfig, ReplayRejection};
+    use std::time::Duration;
+    use up_rust::{UAttributes, UMessage, UUID};
+
+    fn message_at(timestamp_millis: u64, lsb: u64) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                id: Some(UUID {
+                    msb: (timestamp_millis << 16) | 0x8000,
+                    lsb,
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_old_future_and_repeated_ids_are_rejected() {
+        let replay_guard = ReplayGuard::new(ReplayProtectionConfig {
+            window: Duration::from_secs(10),
+            max_clock_skew: Duration::from_secs(1),
+        });
+        let now = 1_700_000_000_000;
+
+        assert_eq!(
+            replay_guard.check_at(0, &message_at(now - 5_000, 1), now),
+            Ok(())
+        );
+        assert_eq!(
+            replay_guard.check_at(0, &message_at(now - 5_000, 1), now),
+            Err(ReplayRejection::AlreadySeen)
+        );
+        assert_eq!(
+            replay_guard.check_at(0, &message_at(now - 5_000, 2), now),
+            Ok(())
+        );
+        assert_eq!(
+            replay_guard.check_at(0, &message_at(now - 10_001, 3), now),
+            Err(ReplayRejection::TooOld)
+        );
+        assert_eq!(
+            replay_guard.check_at(0, &message_at(now + 1_001, 4), now),
+            Err(ReplayRejection::FromTheFuture)
+        );
+        assert_eq!(
+            replay_guard.check_at(0, &UMessage::default(), now),
+            Err(ReplayRejection::MissingId)
+        );
+    }
+
+    #[test]
+    fn test_ids_are_remembered_per_rule() {
+        let replay_guard = ReplayGuard::new(ReplayProtectionConfig::default());
+        let now = 1_700_000_000_000;
+
+        assert_eq!(replay_guard.check_at(0, &message_at(now, 1), now), Ok(()));
+        assert_eq!(replay_guard.check_at(1, &message_at(now, 1), now), Ok(()));
+        assert_eq!(
+            replay_guard.check_at(1, &message_at(now, 1), now),
+            Err(ReplayRejection::AlreadySeen)
+        );
+    }
+
+    #[test]
+    fn test_ids_are_forgotten_once_out_of_the_window() {
+        let replay_guard = ReplayGuard::new(ReplayProtectionConfig {
+            window: Duration::from_secs(10),
+            ..Default::default()
+        });
+        let now = 1_700_000_000_000;
+
+        assert_eq!(replay_guard.check_at(0, &message_at(now, 1), now), Ok(()));
+        assert_eq!(replay_guard.check_at(0, &message_at(now, 2), now), Ok(()));
+        assert_eq!(replay_guard.seen.lock().unwrap().len(), 2);
+
+        assert_eq!(
+            replay_guard.check_at(0, &message_at(now + 10_001, 3), now + 10_001),
+            Ok(())
+        );
+        assert_eq!(replay_guard.seen.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,544 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use crate::response_cache::ResponseCache;
+use crate::ustreamer::ForwardingRoute;
+use async_std::channel::{self, Receiver, Sender};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+use up_rust::{UAttributes, UMessage, UTransport, UUri, UUID};
+
+/// Configures how a [`UStreamer`][crate::UStreamer] correlates the responses it forwards with the
+/// requests it forwarded, set with
+/// [`UStreamer::set_request_tracking_config`][crate::UStreamer::set_request_tracking_config]
+///
+/// A response is orphaned if no request it answers is outstanding, and misrouted if it doesn't
+/// arrive on an out [`Endpoint`][crate::Endpoint] its request was forwarded on, or isn't
+/// forwarded back onto the in [`Endpoint`][crate::Endpoint] its request arrived on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestTrackingConfig {
+    /// Whether orphaned and misrouted responses are dropped, rather than only logged and counted
+    pub drop_unmatched_responses: bool,
+    /// Most requests tracked at once, the oldest are given up on to stay within it, as if their
+    /// TTL had run out
+    pub max_outstanding_requests: usize,
+    /// How long requests without a TTL are tracked for
+    pub default_ttl: Duration,
+    /// Least time between two checks for requests having run out of time, which bounds how late a
+    /// [`UCode::DEADLINE_EXCEEDED`][up_rust::UCode::DEADLINE_EXCEEDED] response may be, see
+    /// [`ForwardingRuleOptions::with_deadline_exceeded_responses`][crate::ForwardingRuleOptions::with_deadline_exceeded_responses]
+    ///
+    /// Requests are only checked once the first of them is due to run out.
+    pub expiry_check_interval: Duration,
+}
+
+impl Default for RequestTrackingConfig {
+    fn default() -> Self {
+        Self {
+            drop_unmatched_responses: false,
+            max_outstanding_requests: 10_000,
+            default_ttl: Duration::from_secs(60),
+            expiry_check_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// The round-trip latency of the requests to a single service forwarded by a
+/// [`UStreamer`][crate::UStreamer], from forwarding the request to forwarding its response, see
+/// [`UStreamer::rpc_latency_stats`][crate::UStreamer::rpc_latency_stats]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcLatencyStats {
+    pub authority_name: String,
+    pub ue_id: u32,
+    pub ue_version_major: u32,
+    /// Responses the latency was measured for
+    pub responses: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+// how a tracked request was forwarded, and what's to become of its response
+#[derive(Default)]
+pub(crate) struct RequestRoute {
+    // the name of the in endpoint the request arrived on
+    pub(crate) in_endpoint: String,
+    // the names of the out endpoints the request may have been forwarded on
+    pub(crate) out_endpoints: Vec<String>,
+    // where to send a DEADLINE_EXCEEDED response once the request expires, if anywhere
+    pub(crate) deadline_reply_transport: Option<Weak<dyn UTransport>>,
+    // where to keep the response, if anywhere
+    pub(crate) response_cache: Option<Arc<ResponseCache>>,
+}
+
+// a request a UStreamer forwarded, which is still waiting for its response
+pub(crate) struct OutstandingRequest {
+    pub(crate) attributes: UAttributes,
+    pub(crate) route: RequestRoute,
+    pub(crate) forwarded_at: Instant,
+    pub(crate) expires_at: Instant,
+}
+
+/// What became of a response handed to [`RequestTracker::match_response`]
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ResponseMatch {
+    Matched,
+    Orphaned,
+    Misrouted,
+}
+
+type ServiceKey = (String, u32, u32);
+
+type RequestKey = (u64, u64);
+
+// the outstanding requests, along with when they were forwarded and expire in order, so that the
+// oldest and the expired ones are found without going through them all
+#[derive(Default)]
+struct OutstandingRequests {
+    requests: HashMap<RequestKey, OutstandingRequest>,
+    by_forwarding: BTreeSet<(Instant, RequestKey)>,
+    by_expiry: BTreeSet<(Instant, RequestKey)>,
+}
+
+impl OutstandingRequests {
+    fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    fn get(&self, key: &RequestKey) -> Option<&OutstandingRequest> {
+        self.requests.get(key)
+    }
+
+    fn contains_key(&self, key: &RequestKey) -> bool {
+        self.requests.contains_key(key)
+    }
+
+    fn insert(&mut self, key: RequestKey, outstanding_request: OutstandingRequest) {
+        self.remove(&key);
+        self.by_forwarding
+            .insert((outstanding_request.forwarded_at, key));
+        self.by_expiry.insert((outstanding_request.expires_at, key));
+        self.requests.insert(key, outstanding_request);
+    }
+
+    fn remove(&mut self, key: &RequestKey) -> Option<OutstandingRequest> {
+        let outstanding_request = self.requests.remove(key)?;
+        self.by_forwarding
+            .remove(&(outstanding_request.forwarded_at, *key));
+        self.by_expiry
+            .remove(&(outstanding_request.expires_at, *key));
+        Some(outstanding_request)
+    }
+
+    fn oldest(&self) -> Option<RequestKey> {
+        self.by_forwarding.first().map(|(_, key)| *key)
+    }
+
+    fn next_expiring(&self) -> Option<(Instant, RequestKey)> {
+        self.by_expiry.first().copied()
+    }
+}
+
+#[derive(Default)]
+struct LatencyStats {
+    responses: u64,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+}
+
+// correlates the requests and responses forwarded by a UStreamer, across all of its rules
+pub(crate) struct RequestTracker {
+    config: RwLock<RequestTrackingConfig>,
+    outstanding: Mutex<OutstandingRequests>,
+    latencies: Mutex<HashMap<ServiceKey, LatencyStats>>,
+    // tells whoever waits for the next request to expire that one is now due sooner
+    expiry_wakeup: Sender<()>,
+    expiry_wakeups: Receiver<()>,
+}
+
+impl Default for RequestTracker {
+    fn default() -> Self {
+        let (expiry_wakeup, expiry_wakeups) = channel::bounded(1);
+        Self {
+            config: RwLock::default(),
+            outstanding: Mutex::default(),
+            latencies: Mutex::default(),
+            expiry_wakeup,
+            expiry_wakeups,
+        }
+    }
+}
+
+impl RequestTracker {
+    pub(crate) fn config(&self) -> RequestTrackingConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    pub(crate) fn set_config(&self, config: RequestTrackingConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Starts tracking `request`, returning the requests given up on to make room for it
+    pub(crate) fn track_request(
+        &self,
+        request: &UMessage,
+        route: RequestRoute,
+    ) -> Vec<OutstandingRequest> {
+        self.track_request_at(request, route, Instant::now())
+    }
+
+    fn track_request_at(
+        &self,
+        request: &UMessage,
+        route: RequestRoute,
+        now: Instant,
+    ) -> Vec<OutstandingRequest> {
+        let Some(attributes) = request.attributes.as_ref() else {
+            return Vec::new();
+        };
+        let config = self.config();
+        let ttl = attributes
+            .ttl
+            .filter(|ttl| *ttl > 0)
+            .map_or(config.default_ttl, |ttl| Duration::from_millis(ttl as u64));
+
+        let mut outstanding = self.outstanding.lock().unwrap();
+        let mut given_up = Vec::new();
+        while outstanding.len() >= config.max_outstanding_requests.max(1) {
+            let Some(oldest) = outstanding.oldest() else {
+                break;
+            };
+            given_up.extend(outstanding.remove(&oldest));
+        }
+        let key = (attributes.id.msb, attributes.id.lsb);
+        outstanding.insert(
+            key,
+            OutstandingRequest {
+                attributes: attributes.clone(),
+                route,
+                forwarded_at: now,
+                expires_at: now + ttl,
+            },
+        );
+        if outstanding
+            .next_expiring()
+            .is_some_and(|(_, next_expiring)| next_expiring == key)
+        {
+            // a wakeup already pending does just as well
+            let _ = self.expiry_wakeup.try_send(());
+        }
+        given_up
+    }
+
+    /// When the next outstanding request runs out of time, if any is outstanding
+    pub(crate) fn next_expiry(&self) -> Option<Instant> {
+        self.outstanding
+            .lock()
+            .unwrap()
+            .next_expiring()
+            .map(|(expires_at, _)| expires_at)
+    }
+
+    /// Receives a wakeup whenever a request is tracked which runs out of time before all others
+    pub(crate) fn expiry_wakeups(&self) -> Receiver<()> {
+        self.expiry_wakeups.clone()
+    }
+
+    /// Whether the request `request_id` is waiting for its response
+    pub(crate) fn is_outstanding(&self, request_id: &UUID) -> bool {
+        self.outstanding
+            .lock()
+            .unwrap()
+            .contains_key(&(request_id.msb, request_id.lsb))
+    }
+
+    /// Stops tracking `request`, e.g. as it wasn't forwarded after all
+    pub(crate) fn forget_request(&self, request: &UMessage) {
+        if let Some(attributes) = request.attributes.as_ref() {
+            self.outstanding
+                .lock()
+                .unwrap()
+                .remove(&(attributes.id.msb, attributes.id.lsb));
+        }
+    }
+
+    /// Removes and returns the requests whose TTL has run out
+    pub(crate) fn take_expired(&self) -> Vec<OutstandingRequest> {
+        self.take_expired_at(Instant::now())
+    }
+
+    fn take_expired_at(&self, now: Instant) -> Vec<OutstandingRequest> {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        let mut expired = Vec::new();
+        while let Some((expires_at, key)) = outstanding.next_expiring() {
+            if expires_at > now {
+                break;
+            }
+            expired.extend(outstanding.remove(&key));
+        }
+        expired
+    }
+
+    /// Matches `response`, arriving on `in_endpoint` and to be forwarded onto one of
+    /// `out_endpoints` by `response_route`, against the request it answers
+    pub(crate) fn match_response(
+        &self,
+        response: &UMessage,
+        in_endpoint: &str,
+        out_endpoints: &[String],
+        response_route: Weak<ForwardingRoute>,
+    ) -> ResponseMatch {
+        self.match_response_at(
+            response,
+            in_endpoint,
+            out_endpoints,
+            response_route,
+            Instant::now(),
+        )
+    }
+
+    fn match_response_at(
+        &self,
+        response: &UMessage,
+        in_endpoint: &str,
+        out_endpoints: &[String],
+        response_route: Weak<ForwardingRoute>,
+        now: Instant,
+    ) -> ResponseMatch {
+        let Some(attributes) = response.attributes.as_ref() else {
+            return ResponseMatch::Orphaned;
+        };
+        let key = (attributes.reqid.msb, attributes.reqid.lsb);
+
+        let mut outstanding = self.outstanding.lock().unwrap();
+        let Some(outstanding_request) = outstanding.get(&key) else {
+            return ResponseMatch::Orphaned;
+        };
+        if outstanding_request.expires_at <= now {
+            outstanding.remove(&key);
+            return ResponseMatch::Orphaned;
+        }
+        if !outstanding_request
+            .route
+            .out_endpoints
+            .iter()
+            .any(|out_endpoint| out_endpoint == in_endpoint)
+            || !out_endpoints
+                .iter()
+                .any(|out_endpoint| *out_endpoint == outstanding_request.route.in_endpoint)
+        {
+            // the right response may still come along the right way
+            return ResponseMatch::Misrouted;
+        }
+        let outstanding_request = outstanding.remove(&key).unwrap();
+        drop(outstanding);
+
+        if let Some(response_cache) = &outstanding_request.route.response_cache {
+            response_cache.insert(&attributes.reqid, response, response_route);
+        }
+
+        let latency = now.duration_since(outstanding_request.forwarded_at);
+        let sink = outstanding_request
+            .attributes
+            .sink
+            .as_ref()
+            .cloned()
+            .unwrap_or_default();
+        self.record_latency(&sink, latency);
+        ResponseMatch::Matched
+    }
+
+    fn record_latency(&self, service: &UUri, latency: Duration) {
+        let key = (
+            service.authority_name.clone(),
+            service.ue_id,
+            service.ue_version_major,
+        );
+        let mut latencies = self.latencies.lock().unwrap();
+        let stats = latencies.entry(key).or_default();
+        stats.min = if stats.responses == 0 {
+            latency
+        } else {
+            stats.min.min(latency)
+        };
+        stats.max = stats.max.max(latency);
+        stats.total += latency;
+        stats.responses += 1;
+    }
+
+    pub(crate) fn latency_stats(&self) -> Vec<RpcLatencyStats> {
+        let latencies = self.latencies.lock().unwrap();
+        let mut latency_stats: Vec<RpcLatencyStats> = latencies
+            .iter()
+            .map(
+                |((authority_name, ue_id, ue_version_major), stats)| RpcLatencyStats {
+                    authority_name: authority_name.clone(),
+                    ue_id: *ue_id,
+                    ue_version_major: *ue_version_major,
+                    responses: stats.responses,
+                    min: stats.min,
+                    max: stats.max,
+                    mean: Duration::from_nanos(
+                        (stats.total.as_nanos() / u128::from(stats.responses)) as u64,
+                    ),
+                },
+            )
+            .collect();
+        latency_stats.sort_by(|a, b| {
+            (&a.authority_name, a.ue_id, a.ue_version_major).cmp(&(
+                &b.authority_name,
+                b.ue_id,
+                b.ue_version_major,
+            ))
+        });
+        latency_stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestRoute, RequestTracker, RequestTrackingConfig, ResponseMatch};
+    use std::sync::Weak;
+    use std::time::{Duration, Instant};
+    use up_rust::{UAttributes, UMessage, UMessageType, UUIDBuilder, UUri};
+
+    fn request(ttl: u32) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_REQUEST.into(),
+                id: Some(UUIDBuilder::build()).into(),
+                sink: Some(UUri {
+                    authority_name: "remote".to_string(),
+                    ue_id: 0x1234,
+                    ue_version_major: 1,
+                    ..Default::default()
+                })
+                .into(),
+                ttl: Some(ttl),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    fn response_to(request: &UMessage) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_RESPONSE.into(),
+                id: Some(UUIDBuilder::build()).into(),
+                reqid: request.attributes.id.clone(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    fn route() -> RequestRoute {
+        RequestRoute {
+            in_endpoint: "local".to_string(),
+            out_endpoints: vec!["remote".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_responses_are_matched_along_the_way_back() {
+        let request_tracker = RequestTracker::default();
+        let start = Instant::now();
+        let request = request(1000);
+        request_tracker.track_request_at(&request, route(), start);
+
+        let response = response_to(&request);
+        let local = ["local".to_string()];
+        assert_eq!(
+            request_tracker.match_response_at(
+                &response,
+                "elsewhere",
+                &local,
+                Weak::new(),
+                start + Duration::from_millis(10)
+            ),
+            ResponseMatch::Misrouted
+        );
+        assert_eq!(
+            request_tracker.match_response_at(
+                &response,
+                "remote",
+                &local,
+                Weak::new(),
+                start + Duration::from_millis(20)
+            ),
+            ResponseMatch::Matched
+        );
+        // a duplicate response has nothing left to answer
+        assert_eq!(
+            request_tracker.match_response_at(
+                &response,
+                "remote",
+                &local,
+                Weak::new(),
+                start + Duration::from_millis(30)
+            ),
+            ResponseMatch::Orphaned
+        );
+
+        let latency_stats = request_tracker.latency_stats();
+        assert_eq!(latency_stats.len(), 1);
+        assert_eq!(latency_stats[0].ue_id, 0x1234);
+        assert_eq!(latency_stats[0].responses, 1);
+        assert_eq!(latency_stats[0].mean, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_requests_expire_and_are_bounded() {
+        let request_tracker = RequestTracker::default();
+        request_tracker.set_config(RequestTrackingConfig {
+            max_outstanding_requests: 2,
+            ..Default::default()
+        });
+        let start = Instant::now();
+
+        let first = request(100);
+        assert!(request_tracker
+            .track_request_at(&first, route(), start)
+            .is_empty());
+        request_tracker.track_request_at(&request(1000), route(), start + Duration::from_millis(1));
+        let given_up = request_tracker.track_request_at(
+            &request(1000),
+            route(),
+            start + Duration::from_millis(2),
+        );
+        assert_eq!(given_up.len(), 1);
+        assert_eq!(given_up[0].attributes, *first.attributes.as_ref().unwrap());
+
+        assert!(request_tracker
+            .take_expired_at(start + Duration::from_millis(500))
+            .is_empty());
+        assert_eq!(
+            request_tracker
+                .take_expired_at(start + Duration::from_millis(1001))
+                .len(),
+            1
+        );
+        assert_eq!(
+            request_tracker
+                .take_expired_at(start + Duration::from_millis(1002))
+                .len(),
+            1
+        );
+    }
+}
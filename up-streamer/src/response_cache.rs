@@ -0,0 +1,337 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use crate::ustreamer::ForwardingRoute;
+use protobuf::Message;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, Weak};
+use std::time::{Duration, Instant};
+use up_rust::{UAttributes, UMessage, UUID};
+
+/// Bounds the responses a forwarding rule keeps to answer retransmitted requests with, used with
+/// [`ForwardingRuleOptions::with_response_cache`][crate::ForwardingRuleOptions::with_response_cache]
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use up_streamer::ResponseCacheConfig;
+///
+/// let response_cache_config = ResponseCacheConfig {
+///     ttl: Duration::from_secs(10),
+///     max_entries: 100,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResponseCacheConfig {
+    /// How long a response is kept after it was forwarded
+    pub ttl: Duration,
+    /// Most responses kept at once, the oldest are dropped to stay within it
+    pub max_entries: usize,
+    /// Most bytes of responses kept at once, the oldest are dropped to stay within it
+    pub max_bytes: usize,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(30),
+            max_entries: 1000,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+struct CachedResponse {
+    response: UMessage,
+    // the route the response was forwarded on, which answers from the cache go through as well
+    response_route: Weak<ForwardingRoute>,
+    cached_at: Instant,
+    size: usize,
+}
+
+#[derive(Default)]
+struct ResponseCacheState {
+    responses: HashMap<(u64, u64), CachedResponse>,
+    // request ids in the order their responses were cached, which is also the order they expire in
+    order: VecDeque<(u64, u64)>,
+    bytes: usize,
+}
+
+impl ResponseCacheState {
+    fn pop_oldest(&mut self) {
+        if let Some(request_id) = self.order.pop_front() {
+            if let Some(cached_response) = self.responses.remove(&request_id) {
+                self.bytes -= cached_response.size;
+            }
+        }
+    }
+
+    fn expire(&mut self, now: Instant, ttl: Duration) {
+        while let Some(request_id) = self.order.front() {
+            let expired = self
+                .responses
+                .get(request_id)
+                .map_or(true, |cached_response| {
+                    now.duration_since(cached_response.cached_at) >= ttl
+                });
+            if !expired {
+                break;
+            }
+            self.pop_oldest();
+        }
+    }
+}
+
+// the responses to the requests a single forwarding rule forwarded, keyed by request id
+pub(crate) struct ResponseCache {
+    config: ResponseCacheConfig,
+    state: Mutex<ResponseCacheState>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(ResponseCacheState::default()),
+        }
+    }
+
+    /// Returns the response cached for the request with `request_attributes`, along with the
+    /// route it was forwarded on, if any
+    ///
+    /// The response has to answer the same source and sink as the request, so that reusing a
+    /// request id doesn't get anyone else's response.
+    pub(crate) fn get(
+        &self,
+        request_attributes: &UAttributes,
+    ) -> Option<(UMessage, Weak<ForwardingRoute>)> {
+        self.get_at(request_attributes, Instant::now())
+    }
+
+    fn get_at(
+        &self,
+        request_attributes: &UAttributes,
+        now: Instant,
+    ) -> Option<(UMessage, Weak<ForwardingRoute>)> {
+        let mut state = self.state.lock().unwrap();
+        state.expire(now, self.config.ttl);
+        let request_id = &request_attributes.id;
+        state
+            .responses
+            .get(&(request_id.msb, request_id.lsb))
+            .filter(|cached_response| {
+                cached_response
+                    .response
+                    .attributes
+                    .as_ref()
+                    .is_some_and(|response_attributes| {
+                        response_attributes.sink == request_attributes.source
+                            && response_attributes.source == request_attributes.sink
+                    })
+            })
+            .map(|cached_response| {
+                (
+                    cached_response.response.clone(),
+                    cached_response.response_route.clone(),
+                )
+            })
+    }
+
+    /// Keeps `response` to the request `request_id`, forwarded on `response_route`, unless it
+    /// alone exceeds `max_bytes`
+    pub(crate) fn insert(
+        &self,
+        request_id: &UUID,
+        response: &UMessage,
+        response_route: Weak<ForwardingRoute>,
+    ) {
+        self.insert_at(request_id, response, response_route, Instant::now())
+    }
+
+    fn insert_at(
+        &self,
+        request_id: &UUID,
+        response: &UMessage,
+        response_route: Weak<ForwardingRoute>,
+        now: Instant,
+    ) {
+        let size = response.compute_size() as usize;
+        if size > self.config.max_bytes || self.config.max_entries == 0 {
+            return;
+        }
+        let key = (request_id.msb, request_id.lsb);
+
+        let mut state = self.state.lock().unwrap();
+        state.expire(now, self.config.ttl);
+        if state.responses.contains_key(&key) {
+            return;
+        }
+        while state.responses.len() >= self.config.max_entries
+            || state.bytes + size > self.config.max_bytes
+        {
+            state.pop_oldest();
+        }
+        state.responses.insert(
+            key,
+            CachedResponse {
+                response: response.clone(),
+                response_route,
+                cached_at: now,
+                size,
+            },
+        );
+        state.order.push_back(key);
+        state.bytes += size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResponseCache, ResponseCacheConfig};
+    use std::sync::Weak;
+    use std::time::{Duration, Instant};
+    use up_rust::{UAttributes, UMessage, UMessageType, UUIDBuilder, UUri};
+
+    fn uri(authority_name: &str) -> UUri {
+        UUri {
+            authority_name: authority_name.to_string(),
+            ue_id: 0x1234,
+            ue_version_major: 1,
+            ..Default::default()
+        }
+    }
+
+    fn request() -> UAttributes {
+        UAttributes {
+            type_: UMessageType::UMESSAGE_TYPE_REQUEST.into(),
+            id: Some(UUIDBuilder::build()).into(),
+            source: Some(uri("local")).into(),
+            sink: Some(uri("remote")).into(),
+            ..Default::default()
+        }
+    }
+
+    fn response(request: &UAttributes, payload_len: usize) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_RESPONSE.into(),
+                reqid: request.id.clone(),
+                source: request.sink.clone(),
+                sink: request.source.clone(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(vec![0u8; payload_len].into()),
+            ..Default::default()
+        }
+    }
+
+    fn insert_at(
+        response_cache: &ResponseCache,
+        request: &UAttributes,
+        response: &UMessage,
+        now: Instant,
+    ) {
+        response_cache.insert_at(&request.id, response, Weak::new(), now);
+    }
+
+    fn get_at(
+        response_cache: &ResponseCache,
+        request: &UAttributes,
+        now: Instant,
+    ) -> Option<UMessage> {
+        response_cache
+            .get_at(request, now)
+            .map(|(response, _)| response)
+    }
+
+    #[test]
+    fn test_responses_expire_after_ttl() {
+        let response_cache = ResponseCache::new(ResponseCacheConfig {
+            ttl: Duration::from_secs(1),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let request = request();
+
+        insert_at(&response_cache, &request, &response(&request, 10), start);
+        assert_eq!(
+            get_at(
+                &response_cache,
+                &request,
+                start + Duration::from_millis(999)
+            ),
+            Some(response(&request, 10))
+        );
+        assert_eq!(
+            get_at(&response_cache, &request, start + Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_responses_are_only_returned_to_their_requester() {
+        let response_cache = ResponseCache::new(ResponseCacheConfig::default());
+        let start = Instant::now();
+        let request = request();
+        insert_at(&response_cache, &request, &response(&request, 10), start);
+
+        let mut other_requester = request.clone();
+        other_requester.source = Some(uri("elsewhere")).into();
+        assert!(get_at(&response_cache, &other_requester, start).is_none());
+        let mut other_service = request.clone();
+        other_service.sink = Some(uri("elsewhere")).into();
+        assert!(get_at(&response_cache, &other_service, start).is_none());
+        assert!(get_at(&response_cache, &request, start).is_some());
+    }
+
+    #[test]
+    fn test_oldest_responses_make_room_within_bounds() {
+        let response_cache = ResponseCache::new(ResponseCacheConfig {
+            max_entries: 2,
+            max_bytes: 300,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let requests = [request(), request(), request()];
+
+        for request in &requests {
+            insert_at(&response_cache, request, &response(request, 10), start);
+        }
+        assert!(get_at(&response_cache, &requests[0], start).is_none());
+        assert!(get_at(&response_cache, &requests[1], start).is_some());
+
+        // too large to keep at all
+        let large_request = request();
+        insert_at(
+            &response_cache,
+            &large_request,
+            &response(&large_request, 400),
+            start,
+        );
+        assert!(get_at(&response_cache, &large_request, start).is_none());
+
+        // takes up most of the bytes, so only it stays
+        insert_at(
+            &response_cache,
+            &large_request,
+            &response(&large_request, 210),
+            start,
+        );
+        assert!(get_at(&response_cache, &large_request, start).is_some());
+        assert!(get_at(&response_cache, &requests[1], start).is_none());
+        assert!(get_at(&response_cache, &requests[2], start).is_none());
+    }
+}
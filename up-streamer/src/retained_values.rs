@@ -0,0 +1,379 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use crate::authority_pattern::authority_matches;
+use crate::conflation::{uri_key, UriKey};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use up_rust::{UMessage, UMessageType, UUIDBuilder};
+
+// uSubscription and its Subscribe method, a request to which means its source newly subscribes
+const USUBSCRIPTION_UE_ID: u32 = 0x0000;
+const USUBSCRIPTION_FN_SUBSCRIBE_RESOURCE_ID: u32 = 0x0001;
+
+/// The authority which newly subscribes, if `msg` is a request to uSubscription to subscribe to a
+/// topic
+pub(crate) fn subscriber_authority(msg: &UMessage) -> Option<&str> {
+    let attributes = msg.attributes.as_ref()?;
+    if attributes.type_.enum_value_or_default() != UMessageType::UMESSAGE_TYPE_REQUEST {
+        return None;
+    }
+    let sink = attributes.sink.as_ref()?;
+    if sink.ue_id & 0xFFFF != USUBSCRIPTION_UE_ID
+        || sink.resource_id != USUBSCRIPTION_FN_SUBSCRIBE_RESOURCE_ID
+    {
+        return None;
+    }
+    let source = attributes.source.as_ref()?;
+    (!source.authority_name.is_empty()).then_some(source.authority_name.as_str())
+}
+
+/// Bounds the last values an in [`Endpoint`][crate::Endpoint] retains for late joiners, used with
+/// [`Endpoint::with_retained_values`][crate::Endpoint::with_retained_values]
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use up_streamer::RetainedValuesConfig;
+///
+/// // slow signals stay interesting for a while, but not forever
+/// let retained_values_config = RetainedValuesConfig {
+///     max_age: Some(Duration::from_secs(15 * 60)),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetainedValuesConfig {
+    /// Most topics retained at once, the least recently updated are dropped to stay within it
+    pub max_topics: usize,
+    /// How long a value is retained after it arrived, for as long as it isn't replaced if `None`
+    pub max_age: Option<Duration>,
+}
+
+impl Default for RetainedValuesConfig {
+    fn default() -> Self {
+        Self {
+            max_topics: 1000,
+            max_age: None,
+        }
+    }
+}
+
+struct RetainedValue {
+    msg: UMessage,
+    retained_at: Instant,
+    sequence: u64,
+}
+
+#[derive(Default)]
+struct RetainedValuesState {
+    values: HashMap<(UriKey, UriKey), RetainedValue>,
+    // topics by when their value was last updated
+    updates: BTreeMap<u64, (UriKey, UriKey)>,
+    next_sequence: u64,
+}
+
+// the last publish or notification message per topic which arrived on a single in endpoint, that
+// is per source and sink
+pub(crate) struct RetainedValues {
+    config: RetainedValuesConfig,
+    state: Mutex<RetainedValuesState>,
+}
+
+impl RetainedValues {
+    pub(crate) fn new(config: RetainedValuesConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(RetainedValuesState::default()),
+        }
+    }
+
+    /// Keeps `msg` as the last value of its topic, if it's a publish or notification message
+    pub(crate) fn retain(&self, msg: &UMessage) {
+        self.retain_at(msg, Instant::now())
+    }
+
+    fn retain_at(&self, msg: &UMessage, now: Instant) {
+        let Some(attributes) = msg.attributes.as_ref() else {
+            return;
+        };
+        if self.config.max_topics == 0
+            || !matches!(
+                attributes.type_.enum_value_or_default(),
+                UMessageType::UMESSAGE_TYPE_PUBLISH | UMessageType::UMESSAGE_TYPE_NOTIFICATION
+            )
+        {
+            return;
+        }
+        let key = (
+            uri_key(attributes.source.as_ref()),
+            uri_key(attributes.sink.as_ref()),
+        );
+
+        let mut state = self.state.lock().unwrap();
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        if let Some(previous) = state.values.remove(&key) {
+            state.updates.remove(&previous.sequence);
+        }
+        while state.values.len() >= self.config.max_topics {
+            let Some((_, oldest)) = state.updates.pop_first() else {
+                break;
+            };
+            state.values.remove(&oldest);
+        }
+        state.updates.insert(sequence, key.clone());
+        state.values.insert(
+            key,
+            RetainedValue {
+                msg: msg.clone(),
+                retained_at: now,
+                sequence,
+            },
+        );
+    }
+
+    /// The retained values which may go to any of `out_authorities`, oldest first, each with how
+    /// long it has been retained
+    ///
+    /// Publish messages, which have no sink, may go anywhere. Values whose TTL has passed since
+    /// they arrived are left out.
+    pub(crate) fn replay_for(&self, out_authorities: &[String]) -> Vec<(UMessage, Duration)> {
+        self.replay_for_at(out_authorities, Instant::now())
+    }
+
+    fn replay_for_at(&self, out_authorities: &[String], now: Instant) -> Vec<(UMessage, Duration)> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(max_age) = self.config.max_age {
+            let RetainedValuesState {
+                values, updates, ..
+            } = &mut *state;
+            values.retain(|_, value| now.duration_since(value.retained_at) < max_age);
+            updates.retain(|_, key| values.contains_key(key));
+        }
+
+        state
+            .updates
+            .values()
+            .filter_map(|key| state.values.get(key))
+            .filter(|value| !has_expired(value, now))
+            .filter(|value| {
+                let sink_authority = value
+                    .msg
+                    .attributes
+                    .as_ref()
+                    .and_then(|attributes| attributes.sink.as_ref())
+                    .map_or("", |sink| sink.authority_name.as_str());
+                sink_authority.is_empty()
+                    || out_authorities
+                        .iter()
+                        .any(|out_authority| authority_matches(out_authority, sink_authority))
+            })
+            .map(|value| (value.msg.clone(), now.duration_since(value.retained_at)))
+            .collect()
+    }
+}
+
+/// Gives a replayed value, which was retained for `retained_for`, a fresh id and what is left of
+/// its TTL
+///
+/// The value is sent anew, so that the replay protection of the other end mustn't take it for a
+/// replay, but not past its TTL. It's done once the value has passed the checks of the rule
+/// replaying it, which may verify its original id.
+pub(crate) fn restamp(msg: &mut UMessage, retained_for: Duration) {
+    let attributes = msg.attributes.mut_or_insert_default();
+    attributes.id = Some(UUIDBuilder::build()).into();
+    if let Some(ttl) = attributes.ttl.filter(|ttl| *ttl > 0) {
+        let retained_for = u32::try_from(retained_for.as_millis()).unwrap_or(u32::MAX);
+        // values past their TTL aren't replayed, so there's at least a millisecond left
+        attributes.ttl = Some(ttl.saturating_sub(retained_for).max(1));
+    }
+}
+
+// a TTL of zero means the value doesn't expire
+fn has_expired(value: &RetainedValue, now: Instant) -> bool {
+    value
+        .msg
+        .attributes
+        .as_ref()
+        .and_then(|attributes| attributes.ttl)
+        .filter(|ttl| *ttl > 0)
+        .is_some_and(|ttl| {
+            now.duration_since(value.retained_at) >= Duration::from_millis(u64::from(ttl))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{restamp, subscriber_authority, RetainedValues, RetainedValuesConfig};
+    use std::time::{Duration, Instant};
+    use up_rust::{UAttributes, UMessage, UMessageType, UUIDBuilder, UUri};
+
+    fn messages(replayed: Vec<(UMessage, Duration)>) -> Vec<UMessage> {
+        replayed.into_iter().map(|(msg, _)| msg).collect()
+    }
+
+    fn value(resource_id: u32, sink_authority: Option<&str>, payload: u8) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: if sink_authority.is_some() {
+                    UMessageType::UMESSAGE_TYPE_NOTIFICATION
+                } else {
+                    UMessageType::UMESSAGE_TYPE_PUBLISH
+                }
+                .into(),
+                source: Some(UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: 0x1234,
+                    ue_version_major: 1,
+                    resource_id,
+                    ..Default::default()
+                })
+                .into(),
+                sink: sink_authority
+                    .map(|authority_name| UUri {
+                        authority_name: authority_name.to_string(),
+                        ue_id: 0x5678,
+                        ue_version_major: 1,
+                        ..Default::default()
+                    })
+                    .into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(vec![payload].into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_last_value_per_topic_is_replayed_where_it_may_go() {
+        let retained_values = RetainedValues::new(RetainedValuesConfig::default());
+        let now = Instant::now();
+
+        retained_values.retain_at(&value(0x8001, None, 1), now);
+        retained_values.retain_at(&value(0x8002, Some("cloud"), 1), now);
+        retained_values.retain_at(&value(0x8003, Some("dashboard"), 1), now);
+        retained_values.retain_at(&value(0x8001, None, 2), now);
+
+        assert_eq!(
+            messages(retained_values.replay_for_at(&["cloud*".to_string()], now)),
+            vec![value(0x8002, Some("cloud"), 1), value(0x8001, None, 2)]
+        );
+    }
+
+    #[test]
+    fn test_values_are_dropped_by_age_and_count() {
+        let retained_values = RetainedValues::new(RetainedValuesConfig {
+            max_topics: 2,
+            max_age: Some(Duration::from_secs(60)),
+        });
+        let start = Instant::now();
+
+        retained_values.retain_at(&value(0x8001, None, 1), start);
+        retained_values.retain_at(&value(0x8002, None, 1), start + Duration::from_secs(30));
+        retained_values.retain_at(&value(0x8001, None, 2), start + Duration::from_secs(40));
+        retained_values.retain_at(&value(0x8003, None, 1), start + Duration::from_secs(50));
+        assert_eq!(
+            messages(retained_values.replay_for_at(&[], start + Duration::from_secs(50))),
+            vec![value(0x8001, None, 2), value(0x8003, None, 1)]
+        );
+
+        assert_eq!(
+            messages(retained_values.replay_for_at(&[], start + Duration::from_secs(100))),
+            vec![value(0x8003, None, 1)]
+        );
+    }
+
+    #[test]
+    fn test_values_past_their_ttl_are_not_replayed() {
+        let retained_values = RetainedValues::new(RetainedValuesConfig::default());
+        let start = Instant::now();
+        let mut expiring = value(0x8001, None, 1);
+        expiring.attributes.as_mut().unwrap().ttl = Some(1000);
+        let mut lasting = value(0x8002, None, 1);
+        lasting.attributes.as_mut().unwrap().ttl = Some(0);
+
+        retained_values.retain_at(&expiring, start);
+        retained_values.retain_at(&lasting, start);
+        assert_eq!(
+            retained_values.replay_for_at(&[], start + Duration::from_millis(999)),
+            vec![
+                (expiring, Duration::from_millis(999)),
+                (lasting.clone(), Duration::from_millis(999))
+            ]
+        );
+        assert_eq!(
+            messages(retained_values.replay_for_at(&[], start + Duration::from_secs(1))),
+            vec![lasting]
+        );
+    }
+
+    #[test]
+    fn test_replayed_values_get_a_fresh_id_and_what_is_left_of_their_ttl() {
+        let mut expiring = value(0x8001, None, 1);
+        let attributes = expiring.attributes.as_mut().unwrap();
+        attributes.id = Some(UUIDBuilder::build()).into();
+        attributes.ttl = Some(1000);
+        let mut lasting = value(0x8002, None, 1);
+        lasting.attributes.as_mut().unwrap().ttl = Some(0);
+
+        let mut replayed = expiring.clone();
+        restamp(&mut replayed, Duration::from_millis(400));
+        let replayed_attributes = replayed.attributes.as_ref().unwrap();
+        assert!(replayed_attributes.id.is_some());
+        assert_ne!(replayed_attributes.id, expiring.attributes.id);
+        assert_eq!(replayed_attributes.ttl, Some(600));
+
+        restamp(&mut expiring, Duration::from_millis(999));
+        assert_eq!(expiring.attributes.ttl, Some(1));
+        restamp(&mut lasting, Duration::from_secs(60));
+        assert_eq!(lasting.attributes.ttl, Some(0));
+    }
+
+    #[test]
+    fn test_subscribe_requests_are_recognized() {
+        let subscribe_request = |resource_id| UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_REQUEST.into(),
+                source: Some(UUri {
+                    authority_name: "cloud".to_string(),
+                    ue_id: 0x5678,
+                    ue_version_major: 1,
+                    ..Default::default()
+                })
+                .into(),
+                sink: Some(UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: 0x0000,
+                    ue_version_major: 3,
+                    resource_id,
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            subscriber_authority(&subscribe_request(0x0001)),
+            Some("cloud")
+        );
+        assert_eq!(subscriber_authority(&subscribe_request(0x0002)), None);
+        assert_eq!(subscriber_authority(&value(0x0001, Some("local"), 1)), None);
+    }
+}
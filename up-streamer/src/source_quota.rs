@@ -0,0 +1,242 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use up_rust::UMessage;
+
+/// Limits how much of each out [`UTransport`][up_rust::UTransport]'s forwarding any single
+/// source may take up, across all forwarding rules
+///
+/// Sources are told apart by the authority and `ue_id` of the source [`UUri`][up_rust::UUri] of
+/// their messages. Messages over quota are dropped.
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::SourceQuotaConfig;
+///
+/// // no uE may fill more than a quarter of a queue or take more than half the bandwidth
+/// let source_quota_config = SourceQuotaConfig {
+///     max_queue_percent: Some(25),
+///     max_bandwidth_percent: Some(50),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceQuotaConfig {
+    /// Share of an out [`UTransport`][up_rust::UTransport]'s message queue a source may occupy,
+    /// the queue being made up of a `message_queue_size` lane for every forwarding rule onto it
+    pub max_queue_percent: Option<u8>,
+    /// Share of the payload bytes forwarded onto an out [`UTransport`][up_rust::UTransport]
+    /// within `bandwidth_window` a source may take up, only enforced while other sources are
+    /// forwarding onto it too
+    pub max_bandwidth_percent: Option<u8>,
+    /// The window over which bandwidth shares are accounted
+    pub bandwidth_window: Duration,
+}
+
+impl Default for SourceQuotaConfig {
+    fn default() -> Self {
+        Self {
+            max_queue_percent: None,
+            max_bandwidth_percent: None,
+            bandwidth_window: Duration::from_secs(1),
+        }
+    }
+}
+
+// the authority_name and ue_id of a message's source
+pub(crate) type SourceKey = (String, u32);
+
+pub(crate) fn source_key(msg: &UMessage) -> SourceKey {
+    msg.attributes
+        .as_ref()
+        .and_then(|attributes| attributes.source.as_ref())
+        .map(|source| (source.authority_name.clone(), source.ue_id))
+        .unwrap_or_default()
+}
+
+/// Why a message was refused by [`SourceQuotas::try_admit`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QuotaExceeded {
+    Queue,
+    Bandwidth,
+}
+
+#[derive(Default)]
+struct SourceQuotasState {
+    queued: HashMap<SourceKey, usize>,
+    window_start: Option<Instant>,
+    window_bytes: HashMap<SourceKey, u64>,
+    window_total_bytes: u64,
+}
+
+// the fairness accounting for the queue of a single TransportForwarder
+pub(crate) struct SourceQuotas {
+    // the capacity of the queue, which grows and shrinks with the lanes of the rules feeding it
+    queue_size: AtomicUsize,
+    config: Arc<RwLock<SourceQuotaConfig>>,
+    state: Mutex<SourceQuotasState>,
+}
+
+impl SourceQuotas {
+    pub(crate) fn new(config: Arc<RwLock<SourceQuotaConfig>>) -> Self {
+        Self {
+            queue_size: AtomicUsize::new(0),
+            config,
+            state: Mutex::new(SourceQuotasState::default()),
+        }
+    }
+
+    pub(crate) fn set_queue_size(&self, queue_size: usize) {
+        self.queue_size.store(queue_size, Ordering::Relaxed);
+    }
+
+    /// Accounts for `msg` being queued, unless its source is over quota
+    pub(crate) fn try_admit(&self, msg: &UMessage) -> Result<(), QuotaExceeded> {
+        self.try_admit_at(msg, Instant::now())
+    }
+
+    fn try_admit_at(&self, msg: &UMessage, now: Instant) -> Result<(), QuotaExceeded> {
+        let config = self.config.read().unwrap().clone();
+        let key = source_key(msg);
+        let size = msg.payload.as_ref().map_or(0, |payload| payload.len()) as u64;
+
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(max_queue_percent) = config.max_queue_percent {
+            let queue_size = self.queue_size.load(Ordering::Relaxed);
+            let max_queued = (queue_size * max_queue_percent as usize / 100).max(1);
+            if state.queued.get(&key).copied().unwrap_or(0) >= max_queued {
+                return Err(QuotaExceeded::Queue);
+            }
+        }
+
+        let window_expired = state.window_start.map_or(true, |window_start| {
+            now.duration_since(window_start) >= config.bandwidth_window
+        });
+        if window_expired {
+            state.window_start = Some(now);
+            state.window_bytes.clear();
+            state.window_total_bytes = 0;
+        }
+
+        if let Some(max_bandwidth_percent) = config.max_bandwidth_percent {
+            let source_bytes = state.window_bytes.get(&key).copied().unwrap_or(0) + size;
+            let total_bytes = state.window_total_bytes + size;
+            let contended = state.window_bytes.keys().any(|other_key| *other_key != key);
+            if contended && source_bytes * 100 > total_bytes * max_bandwidth_percent as u64 {
+                return Err(QuotaExceeded::Bandwidth);
+            }
+        }
+
+        *state.queued.entry(key.clone()).or_insert(0) += 1;
+        *state.window_bytes.entry(key).or_insert(0) += size;
+        state.window_total_bytes += size;
+        Ok(())
+    }
+
+    /// Accounts for `msg` having left the queue
+    pub(crate) fn release(&self, msg: &UMessage) {
+        let key = source_key(msg);
+        let mut state = self.state.lock().unwrap();
+        if let Some(queued) = state.queued.get_mut(&key) {
+            *queued -= 1;
+            if *queued == 0 {
+                state.queued.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuotaExceeded, SourceQuotaConfig, SourceQuotas};
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
+    use up_rust::{UAttributes, UMessage, UUri};
+
+    fn message_from(authority_name: &str, ue_id: u32, payload_len: usize) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                source: Some(UUri {
+                    authority_name: authority_name.to_string(),
+                    ue_id,
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(vec![0u8; payload_len].into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_queue_share_is_per_source() {
+        let source_quotas = SourceQuotas::new(Arc::new(RwLock::new(SourceQuotaConfig {
+            max_queue_percent: Some(20),
+            ..Default::default()
+        })));
+        source_quotas.set_queue_size(10);
+        let noisy = message_from("host", 0x1, 0);
+        let quiet = message_from("host", 0x2, 0);
+
+        assert!(source_quotas.try_admit(&noisy).is_ok());
+        assert!(source_quotas.try_admit(&noisy).is_ok());
+        assert_eq!(source_quotas.try_admit(&noisy), Err(QuotaExceeded::Queue));
+        assert!(source_quotas.try_admit(&quiet).is_ok());
+
+        source_quotas.release(&noisy);
+        assert!(source_quotas.try_admit(&noisy).is_ok());
+
+        // a queue twice the size leaves twice the room
+        source_quotas.set_queue_size(20);
+        for _ in 0..2 {
+            assert!(source_quotas.try_admit(&noisy).is_ok());
+        }
+        assert_eq!(source_quotas.try_admit(&noisy), Err(QuotaExceeded::Queue));
+    }
+
+    #[test]
+    fn test_bandwidth_share_only_enforced_under_contention() {
+        let source_quotas = SourceQuotas::new(Arc::new(RwLock::new(SourceQuotaConfig {
+            max_bandwidth_percent: Some(50),
+            bandwidth_window: Duration::from_secs(1),
+            ..Default::default()
+        })));
+        source_quotas.set_queue_size(100);
+        let noisy = message_from("host", 0x1, 100);
+        let quiet = message_from("host", 0x2, 100);
+        let start = Instant::now();
+
+        // alone on the link, the noisy source may take all of it
+        for _ in 0..3 {
+            assert!(source_quotas.try_admit_at(&noisy, start).is_ok());
+        }
+        assert!(source_quotas.try_admit_at(&quiet, start).is_ok());
+        assert_eq!(
+            source_quotas.try_admit_at(&noisy, start),
+            Err(QuotaExceeded::Bandwidth)
+        );
+
+        // a new window starts afresh
+        assert!(source_quotas
+            .try_admit_at(&noisy, start + Duration::from_secs(1))
+            .is_ok());
+    }
+}
@@ -0,0 +1,621 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::task;
+use async_trait::async_trait;
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use up_rust::{UStatus, UTransport};
+
+const TRANSPORT_HEALTH_TAG: &str = "TransportHealth:";
+const TRANSPORT_HEALTH_FN_REEVALUATE_TAG: &str = "reevaluate():";
+const TRANSPORT_HEALTH_MONITOR_TAG: &str = "TransportHealthMonitor:";
+const TRANSPORT_HEALTH_MONITOR_FN_PROBING_TAG: &str = "probing():";
+const TRANSPORT_HEALTH_MONITOR_FN_NOTIFY_TAG: &str = "notify():";
+
+// events a subscriber may fall behind by before further ones are dropped for it
+pub(crate) const HEALTH_EVENTS_CAPACITY: usize = 64;
+
+/// Actively checks whether an out [`UTransport`][up_rust::UTransport] is able to reach its peer,
+/// e.g. by sending a ping to a well-known uE on the other side
+///
+/// Results are combined with the outcome of each `send` made by the streamer to decide whether
+/// an out [`Endpoint`][crate::Endpoint] should be used.
+#[async_trait]
+pub trait HealthProbe: Send + Sync {
+    async fn probe(
+        &self,
+        endpoint_name: &str,
+        transport: Arc<dyn UTransport>,
+    ) -> Result<(), UStatus>;
+}
+
+/// Health of a [`UTransport`][up_rust::UTransport] as seen by the [`UStreamer`][crate::UStreamer]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportHealthState {
+    /// Sends and listeners are working
+    Healthy,
+    /// Some sends are failing or listeners report errors, but messages still get through
+    Degraded,
+    /// Sends keep failing, listeners keep reporting errors or the health probe fails, forwarding
+    /// onto it is paused
+    Down,
+}
+
+/// A change in the [`TransportHealthState`] of the [`UTransport`][up_rust::UTransport] behind an
+/// [`Endpoint`][crate::Endpoint]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransportHealthEvent {
+    /// Name of the [`Endpoint`][crate::Endpoint] the [`UTransport`][up_rust::UTransport] was
+    /// first seen with
+    pub endpoint_name: String,
+    pub previous_state: TransportHealthState,
+    pub state: TransportHealthState,
+    /// Human readable explanation of what caused the change
+    pub reason: String,
+}
+
+/// Configures how the [`UStreamer`][crate::UStreamer] judges the health of its
+/// [`UTransport`][up_rust::UTransport]s
+#[derive(Clone)]
+pub struct TransportHealthConfig {
+    /// Number of `send` failures, or listener errors, in a row after which a
+    /// [`UTransport`][up_rust::UTransport] is [`TransportHealthState::Down`]
+    pub consecutive_failures_until_down: u32,
+    /// Ratio of failed sends among the last `error_rate_window` sends at which a
+    /// [`UTransport`][up_rust::UTransport] is [`TransportHealthState::Degraded`]
+    pub degraded_error_ratio: f64,
+    /// Number of most recent sends the error ratio is computed over
+    pub error_rate_window: usize,
+    /// While [`TransportHealthState::Down`], how long to wait after the last failed `send`
+    /// before letting a message through to check whether it recovered
+    pub retry_interval: Duration,
+    /// Optional active check run against every [`UTransport`][up_rust::UTransport] in use
+    pub health_probe: Option<Arc<dyn HealthProbe>>,
+    /// How often `health_probe` is run
+    pub health_probe_interval: Duration,
+}
+
+impl Default for TransportHealthConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures_until_down: 5,
+            degraded_error_ratio: 0.1,
+            error_rate_window: 100,
+            retry_interval: Duration::from_secs(5),
+            health_probe: None,
+            health_probe_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+// health as observed for a single `UTransport`, both by the TransportForwarder sending on it and
+// by the ForwardingListeners registered on it
+//
+// the ForwardingListeners are registered on their in `UTransport`, so we mustn't keep it alive
+pub(crate) struct TransportHealth {
+    endpoint_name: String,
+    transport: Weak<dyn UTransport>,
+    consecutive_send_failures: AtomicU32,
+    last_send_failure: Mutex<Option<Instant>>,
+    recent_sends: Mutex<VecDeque<bool>>,
+    consecutive_listener_errors: AtomicU32,
+    probe_failing: AtomicBool,
+    state: Mutex<TransportHealthState>,
+    monitor: Weak<TransportHealthMonitor>,
+}
+
+impl TransportHealth {
+    pub(crate) fn new(
+        endpoint_name: &str,
+        transport: &Arc<dyn UTransport>,
+        monitor: Weak<TransportHealthMonitor>,
+    ) -> Self {
+        Self {
+            endpoint_name: endpoint_name.to_string(),
+            transport: Arc::downgrade(transport),
+            consecutive_send_failures: AtomicU32::new(0),
+            last_send_failure: Mutex::new(None),
+            recent_sends: Mutex::new(VecDeque::new()),
+            consecutive_listener_errors: AtomicU32::new(0),
+            probe_failing: AtomicBool::new(false),
+            state: Mutex::new(TransportHealthState::Healthy),
+            monitor,
+        }
+    }
+
+    fn config(&self) -> TransportHealthConfig {
+        self.monitor
+            .upgrade()
+            .map(|monitor| monitor.config())
+            .unwrap_or_default()
+    }
+
+    fn record_send(&self, succeeded: bool) {
+        let error_rate_window = self.config().error_rate_window.max(1);
+        let mut recent_sends = self.recent_sends.lock().unwrap();
+        recent_sends.push_back(succeeded);
+        while recent_sends.len() > error_rate_window {
+            recent_sends.pop_front();
+        }
+    }
+
+    pub(crate) fn record_send_success(&self) {
+        self.consecutive_send_failures.store(0, Ordering::SeqCst);
+        self.record_send(true);
+        self.reevaluate("send succeeded");
+    }
+
+    pub(crate) fn record_send_failure(&self) {
+        self.consecutive_send_failures
+            .fetch_add(1, Ordering::SeqCst);
+        *self.last_send_failure.lock().unwrap() = Some(Instant::now());
+        self.record_send(false);
+        self.reevaluate("send failed");
+    }
+
+    pub(crate) fn record_listener_message(&self) {
+        if self.consecutive_listener_errors.swap(0, Ordering::SeqCst) > 0 {
+            self.reevaluate("listener received a message");
+        }
+    }
+
+    pub(crate) fn record_listener_error(&self) {
+        self.consecutive_listener_errors
+            .fetch_add(1, Ordering::SeqCst);
+        self.reevaluate("listener reported an error");
+    }
+
+    pub(crate) fn record_probe_result(&self, probe_res: &Result<(), UStatus>) {
+        let was_failing = self
+            .probe_failing
+            .swap(probe_res.is_err(), Ordering::SeqCst);
+        if was_failing != probe_res.is_err() {
+            self.reevaluate(if probe_res.is_err() {
+                "health probe failed"
+            } else {
+                "health probe succeeded"
+            });
+        }
+    }
+
+    pub(crate) fn consecutive_send_failures(&self) -> u32 {
+        self.consecutive_send_failures.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn state(&self) -> TransportHealthState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Whether messages should be sent over this transport
+    ///
+    /// A transport which has failed `failure_threshold` sends in a row is given another try once
+    /// `retry_interval` has passed since its last failure, so that we notice when it recovers
+    /// even without a [`HealthProbe`].
+    pub(crate) fn is_available(&self, failure_threshold: u32, retry_interval: Duration) -> bool {
+        if self.probe_failing.load(Ordering::SeqCst) {
+            return false;
+        }
+        if self.consecutive_send_failures() < failure_threshold {
+            return true;
+        }
+        match *self.last_send_failure.lock().unwrap() {
+            Some(last_send_failure) => last_send_failure.elapsed() >= retry_interval,
+            None => true,
+        }
+    }
+
+    /// Whether forwarding onto this transport should be paused since it's down
+    ///
+    /// That is whenever it's [`TransportHealthState::Down`], other than when it's down for its
+    /// failed sends alone and `retry_interval` has passed, as with
+    /// [`TransportHealth::is_available`].
+    pub(crate) fn is_paused(&self) -> bool {
+        let config = self.config();
+        self.consecutive_listener_errors.load(Ordering::SeqCst)
+            >= config.consecutive_failures_until_down
+            || !self.is_available(
+                config.consecutive_failures_until_down,
+                config.retry_interval,
+            )
+    }
+
+    fn evaluate(&self, config: &TransportHealthConfig) -> TransportHealthState {
+        if self.probe_failing.load(Ordering::SeqCst)
+            || self.consecutive_send_failures() >= config.consecutive_failures_until_down
+            || self.consecutive_listener_errors.load(Ordering::SeqCst)
+                >= config.consecutive_failures_until_down
+        {
+            return TransportHealthState::Down;
+        }
+
+        let (failed_sends, sends) = {
+            let recent_sends = self.recent_sends.lock().unwrap();
+            (
+                recent_sends.iter().filter(|succeeded| !**succeeded).count(),
+                recent_sends.len(),
+            )
+        };
+        let error_ratio = if sends == 0 {
+            0.0
+        } else {
+            failed_sends as f64 / sends as f64
+        };
+        if (failed_sends > 0 && error_ratio >= config.degraded_error_ratio)
+            || self.consecutive_listener_errors.load(Ordering::SeqCst) > 0
+        {
+            return TransportHealthState::Degraded;
+        }
+
+        TransportHealthState::Healthy
+    }
+
+    fn reevaluate(&self, reason: &str) {
+        let state = self.evaluate(&self.config());
+        let previous_state = {
+            let mut current_state = self.state.lock().unwrap();
+            let previous_state = *current_state;
+            *current_state = state;
+            previous_state
+        };
+        if previous_state == state {
+            return;
+        }
+
+        let event = TransportHealthEvent {
+            endpoint_name: self.endpoint_name.clone(),
+            previous_state,
+            state,
+            reason: reason.to_string(),
+        };
+        if state == TransportHealthState::Down {
+            warn!("{TRANSPORT_HEALTH_TAG}:{TRANSPORT_HEALTH_FN_REEVALUATE_TAG} {event:?}");
+        } else {
+            info!("{TRANSPORT_HEALTH_TAG}:{TRANSPORT_HEALTH_FN_REEVALUATE_TAG} {event:?}");
+        }
+        if let Some(monitor) = self.monitor.upgrade() {
+            monitor.notify(event);
+        }
+    }
+}
+
+// keyed on the address of the `UTransport`, rather than on the `UTransport` itself, so as not to
+// keep it alive
+type TransportKey = usize;
+
+fn transport_key(transport: &Arc<dyn UTransport>) -> TransportKey {
+    Arc::as_ptr(transport) as *const () as usize
+}
+
+// keeps track of the health of every `UTransport` used by a UStreamer, runs the optional health
+// probe against them and notifies subscribers of state changes
+pub(crate) struct TransportHealthMonitor {
+    config: RwLock<TransportHealthConfig>,
+    healths: Mutex<HashMap<TransportKey, Weak<TransportHealth>>>,
+    subscribers: Mutex<Vec<Sender<TransportHealthEvent>>>,
+}
+
+impl TransportHealthMonitor {
+    pub(crate) fn new() -> Arc<Self> {
+        let monitor = Arc::new(Self {
+            config: RwLock::new(TransportHealthConfig::default()),
+            healths: Mutex::new(HashMap::new()),
+            subscribers: Mutex::new(Vec::new()),
+        });
+        Self::spawn_probing(Arc::downgrade(&monitor));
+        monitor
+    }
+
+    pub(crate) fn config(&self) -> TransportHealthConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    pub(crate) fn set_config(&self, config: TransportHealthConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Returns the health for `transport`, shared by everyone sending or listening on it
+    pub(crate) fn health(
+        self: &Arc<Self>,
+        endpoint_name: &str,
+        transport: Arc<dyn UTransport>,
+    ) -> Arc<TransportHealth> {
+        let mut healths = self.healths.lock().unwrap();
+        healths.retain(|_, health| health.strong_count() > 0);
+        let key = transport_key(&transport);
+        // a health outliving its transport mustn't be mistaken for that of a new one at the same
+        // address
+        if let Some(health) = healths
+            .get(&key)
+            .and_then(|health| health.upgrade())
+            .filter(|health| health.transport.strong_count() > 0)
+        {
+            return health;
+        }
+        let health = Arc::new(TransportHealth::new(
+            endpoint_name,
+            &transport,
+            Arc::downgrade(self),
+        ));
+        healths.insert(key, Arc::downgrade(&health));
+        health
+    }
+
+    pub(crate) fn state(&self, transport: Arc<dyn UTransport>) -> Option<TransportHealthState> {
+        self.healths
+            .lock()
+            .unwrap()
+            .get(&transport_key(&transport))
+            .and_then(|health| health.upgrade())
+            .filter(|health| health.transport.strong_count() > 0)
+            .map(|health| health.state())
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<TransportHealthEvent> {
+        let (tx, rx) = channel::bounded(HEALTH_EVENTS_CAPACITY);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    // a subscriber which falls behind misses events rather than holding us up or piling them up
+    fn notify(&self, event: TransportHealthEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(event)) => {
+                    debug!("{TRANSPORT_HEALTH_MONITOR_TAG}:{TRANSPORT_HEALTH_MONITOR_FN_NOTIFY_TAG} Subscriber falling behind, dropping: {event:?}");
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+    }
+
+    // runs the health probe, if configured, until the UStreamer has been dropped
+    fn spawn_probing(monitor: Weak<Self>) {
+        thread::spawn(move || {
+            task::block_on(async move {
+                loop {
+                    let Some(config) = monitor.upgrade().map(|monitor| monitor.config()) else {
+                        break;
+                    };
+                    task::sleep(config.health_probe_interval).await;
+                    let Some(health_probe) = config.health_probe else {
+                        continue;
+                    };
+
+                    let healths: Vec<Arc<TransportHealth>> = {
+                        let Some(monitor) = monitor.upgrade() else {
+                            break;
+                        };
+                        let mut healths = monitor.healths.lock().unwrap();
+                        healths.retain(|_, health| health.strong_count() > 0);
+                        healths
+                            .values()
+                            .filter_map(|health| health.upgrade())
+                            .collect()
+                    };
+                    for health in healths {
+                        let Some(transport) = health.transport.upgrade() else {
+                            continue;
+                        };
+                        let probe_res = health_probe.probe(&health.endpoint_name, transport).await;
+                        if let Err(err) = &probe_res {
+                            debug!(
+                                "{TRANSPORT_HEALTH_MONITOR_TAG}:{TRANSPORT_HEALTH_MONITOR_FN_PROBING_TAG} Health probe of endpoint: {} failed: {err:?}",
+                                health.endpoint_name
+                            );
+                        }
+                        health.record_probe_result(&probe_res);
+                    }
+                }
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        TransportHealthConfig, TransportHealthMonitor, TransportHealthState, HEALTH_EVENTS_CAPACITY,
+    };
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use up_rust::{UCode, UListener, UMessage, UStatus, UTransport, UUri};
+
+    pub struct UPClientFoo;
+
+    #[async_trait]
+    impl UTransport for UPClientFoo {
+        async fn send(&self, _message: UMessage) -> Result<(), UStatus> {
+            todo!()
+        }
+
+        async fn receive(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+        ) -> Result<UMessage, UStatus> {
+            todo!()
+        }
+
+        async fn register_listener(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            _listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            Ok(())
+        }
+
+        async fn unregister_listener(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            _listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            Ok(())
+        }
+    }
+
+    fn monitor_with_config(config: TransportHealthConfig) -> Arc<TransportHealthMonitor> {
+        let monitor = TransportHealthMonitor::new();
+        monitor.set_config(config);
+        monitor
+    }
+
+    #[test]
+    fn test_unavailable_after_consecutive_failures_until_success() {
+        let monitor = TransportHealthMonitor::new();
+        let health = monitor.health("foo_endpoint", Arc::new(UPClientFoo));
+        let retry_interval = Duration::from_secs(60);
+
+        health.record_send_failure();
+        health.record_send_failure();
+        assert!(health.is_available(3, retry_interval));
+
+        health.record_send_failure();
+        assert!(!health.is_available(3, retry_interval));
+
+        health.record_send_success();
+        assert!(health.is_available(3, retry_interval));
+    }
+
+    #[test]
+    fn test_retried_after_retry_interval() {
+        let monitor = TransportHealthMonitor::new();
+        let health = monitor.health("foo_endpoint", Arc::new(UPClientFoo));
+
+        health.record_send_failure();
+        assert!(!health.is_available(1, Duration::from_secs(60)));
+        assert!(health.is_available(1, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_unavailable_while_probe_failing() {
+        let monitor = TransportHealthMonitor::new();
+        let health = monitor.health("foo_endpoint", Arc::new(UPClientFoo));
+
+        health.record_probe_result(&Err(UStatus::fail_with_code(
+            UCode::UNAVAILABLE,
+            "no route to peer",
+        )));
+        assert!(!health.is_available(1, Duration::ZERO));
+        assert_eq!(health.state(), TransportHealthState::Down);
+
+        health.record_probe_result(&Ok(()));
+        assert!(health.is_available(1, Duration::ZERO));
+        assert_eq!(health.state(), TransportHealthState::Healthy);
+    }
+
+    #[async_std::test]
+    async fn test_state_changes_are_notified() {
+        let monitor = monitor_with_config(TransportHealthConfig {
+            consecutive_failures_until_down: 2,
+            degraded_error_ratio: 0.5,
+            error_rate_window: 4,
+            ..Default::default()
+        });
+        let events = monitor.subscribe();
+        let health = monitor.health("foo_endpoint", Arc::new(UPClientFoo));
+
+        health.record_send_success();
+        health.record_send_failure();
+        assert_eq!(health.state(), TransportHealthState::Degraded);
+        health.record_send_failure();
+        assert_eq!(health.state(), TransportHealthState::Down);
+        assert!(health.is_paused());
+
+        // recovering sends first bring us back to degraded, and healthy once the window is clean
+        health.record_send_success();
+        assert_eq!(health.state(), TransportHealthState::Degraded);
+        for _ in 0..4 {
+            health.record_send_success();
+        }
+        assert_eq!(health.state(), TransportHealthState::Healthy);
+
+        let states: Vec<TransportHealthState> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| event.state)
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                TransportHealthState::Degraded,
+                TransportHealthState::Down,
+                TransportHealthState::Degraded,
+                TransportHealthState::Healthy
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subscribers_falling_behind_miss_events() {
+        let monitor = monitor_with_config(TransportHealthConfig {
+            consecutive_failures_until_down: 1,
+            ..Default::default()
+        });
+        let events = monitor.subscribe();
+        let dropped_events = monitor.subscribe();
+        drop(dropped_events);
+        let health = monitor.health("foo_endpoint", Arc::new(UPClientFoo));
+
+        for _ in 0..HEALTH_EVENTS_CAPACITY {
+            health.record_send_failure();
+            health.record_send_success();
+        }
+        assert_eq!(events.len(), HEALTH_EVENTS_CAPACITY);
+        assert_eq!(monitor.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_health_doesnt_keep_its_transport_alive() {
+        let monitor = TransportHealthMonitor::new();
+        let transport: Arc<dyn UTransport> = Arc::new(UPClientFoo);
+        let health = monitor.health("foo_endpoint", transport.clone());
+        assert_eq!(
+            monitor.state(transport.clone()),
+            Some(TransportHealthState::Healthy)
+        );
+
+        let weak_transport = Arc::downgrade(&transport);
+        drop(transport);
+        assert!(weak_transport.upgrade().is_none());
+        drop(health);
+    }
+
+    #[test]
+    fn test_listener_errors_degrade_and_take_down() {
+        let monitor = monitor_with_config(TransportHealthConfig {
+            consecutive_failures_until_down: 2,
+            ..Default::default()
+        });
+        let health = monitor.health("foo_endpoint", Arc::new(UPClientFoo));
+
+        health.record_listener_error();
+        assert_eq!(health.state(), TransportHealthState::Degraded);
+        assert!(!health.is_paused());
+        health.record_listener_error();
+        assert_eq!(health.state(), TransportHealthState::Down);
+        assert!(health.is_paused());
+        health.record_listener_message();
+        assert_eq!(health.state(), TransportHealthState::Healthy);
+        assert!(!health.is_paused());
+    }
+}
@@ -11,17 +11,53 @@
  * SPDX-License-Identifier: Apache-2.0
  ********************************************************************************/
 
-use crate::endpoint::Endpoint;
-use async_std::channel::{Receiver, Sender};
+use crate::access_policy::{AccessDecision, AccessPolicy};
+use crate::authority_pattern::{authority_matches, is_authority_pattern, most_specific_match};
+use crate::compression::{self, CompressionConfig};
+use crate::conflation::{Conflation, Conflator};
+use crate::content_filter::{ContentFilter, DecodedPayloads};
+use crate::endpoint::{Endpoint, OversizePolicy};
+use crate::fair_queue::{fair_queue, FairQueueConsumer, FairQueueProducer, LaneSender};
+use crate::forwarding_rule_options::{
+    FailoverConfig, ForwardingRuleOptions, LoadBalancingStrategy, OutSelection,
+};
+use crate::fragmentation::{self, Reassembler, Reassembly, ReassemblyConfig};
+use crate::listener_reconciliation::ListenerReconciliationConfig;
+use crate::message_script::MessageScript;
+use crate::metrics::{StreamerCounters, StreamerMetrics};
+use crate::payload_protection::{self, PayloadProtection, ProtectionKeys, ProtectionRequirement};
+use crate::payload_translation::PayloadTranslation;
+use crate::rate_limit::{RateLimitPolicy, RateLimiter};
+use crate::replay_protection::ReplayGuard;
+use crate::request_tracker::{
+    OutstandingRequest, RequestRoute, RequestTracker, RequestTrackingConfig, ResponseMatch,
+    RpcLatencyStats,
+};
+use crate::response_cache::ResponseCache;
+use crate::retained_values::{self, RetainedValues};
+use crate::source_quota::{SourceQuotaConfig, SourceQuotas};
+use crate::transport_health::{
+    HealthProbe, TransportHealth, TransportHealthConfig, TransportHealthEvent,
+    TransportHealthMonitor, TransportHealthState,
+};
+use crate::wasm_filter::{WasmFilter, WasmVerdict};
+use async_std::channel::Receiver;
 use async_std::sync::{Arc, Mutex};
-use async_std::{channel, task};
+use async_std::task;
 use async_trait::async_trait;
 use log::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Weak;
 use std::thread;
-use up_rust::{UCode, UListener, UMessage, UStatus, UTransport, UUIDBuilder, UUri};
+use std::time::{Duration, Instant};
+use up_rust::{
+    UAttributes, UCode, UListener, UMessage, UMessageBuilder, UMessageType, UStatus, UTransport,
+    UUIDBuilder, UUri,
+};
 
 const USTREAMER_TAG: &str = "UStreamer:";
 const USTREAMER_FN_NEW_TAG: &str = "new():";
@@ -38,6 +74,16 @@ fn uauthority_to_uuri(authority_name: &str) -> UUri {
     }
 }
 
+// authority patterns can't be expressed as a transport level filter, so for those we register
+// for all authorities and leave it to the ForwardingListener to match at forwarding time
+fn registration_authority(out_authority: &str) -> &str {
+    if is_authority_pattern(out_authority) {
+        "*"
+    } else {
+        out_authority
+    }
+}
+
 fn any_uuri() -> UUri {
     UUri {
         authority_name: "*".to_string(),
@@ -50,60 +96,103 @@ fn any_uuri() -> UUri {
 
 // the 'gatekeeper' which will prevent us from erroneously being able to add duplicate
 // forwarding rules or delete those rules which don't exist
-type ForwardingRules = Mutex<HashSet<(String, String, ComparableTransport, ComparableTransport)>>;
+//
+// rules are expressed per endpoint, i.e. keyed on in and out endpoint names rather than on
+// authorities, since an endpoint may serve several authorities
+//
+// we hold onto every out endpoint of a rule, e.g. its standbys, so that we can clean up after them
+//
+// each rule is also given an id of its own for its lanes and listeners, since rules whose
+// endpoints only differ in their transports would share the same forwarding_id
+//
+// as well as the values retained on its in endpoint, whose retaining listener it keeps registered
+type ForwardingRuleKey = (String, String, ComparableTransport, ComparableTransport);
+type ForwardingRuleId = u64;
+type ForwardingRules = Mutex<
+    HashMap<ForwardingRuleKey, (ForwardingRuleId, Vec<Endpoint>, Option<Arc<RetainedValues>>)>,
+>;
 
 const TRANSPORT_FORWARDERS_TAG: &str = "TransportForwarders:";
 const TRANSPORT_FORWARDERS_FN_INSERT_TAG: &str = "insert:";
 const TRANSPORT_FORWARDERS_FN_REMOVE_TAG: &str = "remove:";
 
 type TransportForwardersContainer =
-    Mutex<HashMap<ComparableTransport, (usize, Arc<TransportForwarder>, Sender<Arc<UMessage>>)>>;
+    Mutex<HashMap<ComparableTransport, (usize, Arc<TransportForwarder>)>>;
 
 // we only need one TransportForwarder per out `UTransport`, so we keep track of that one here
-// and the Sender necessary to hand off to the listener for the in `UTransport`
+//
+// each rule onto it gets its own lane in the TransportForwarder's queue and with it the
+// LaneSender necessary to hand off to the listener for the in `UTransport`
 struct TransportForwarders {
     message_queue_size: usize,
     forwarders: TransportForwardersContainer,
+    source_quota_config: Arc<std::sync::RwLock<SourceQuotaConfig>>,
+    counters: Arc<StreamerCounters>,
 }
 
 impl TransportForwarders {
-    pub fn new(message_queue_size: usize) -> Self {
+    pub fn new(message_queue_size: usize, counters: Arc<StreamerCounters>) -> Self {
         Self {
             message_queue_size,
             forwarders: Mutex::new(HashMap::new()),
+            source_quota_config: Arc::new(std::sync::RwLock::new(SourceQuotaConfig::default())),
+            counters,
         }
     }
 
-    pub async fn insert(&mut self, out_transport: Arc<dyn UTransport>) -> Sender<Arc<UMessage>> {
+    pub fn set_source_quota_config(&self, config: SourceQuotaConfig) {
+        *self.source_quota_config.write().unwrap() = config;
+    }
+
+    pub async fn insert(
+        &mut self,
+        out_transport: Arc<dyn UTransport>,
+        out_health: Arc<TransportHealth>,
+        rule_id: ForwardingRuleId,
+        scheduling_weight: u32,
+    ) -> (LaneSender, Arc<TransportForwarder>) {
         let out_comparable_transport = ComparableTransport::new(out_transport.clone());
 
         let mut transport_forwarders = self.forwarders.lock().await;
 
-        let (active, _, sender) = transport_forwarders
+        let (active, transport_forwarder) = transport_forwarders
             .entry(out_comparable_transport)
             .or_insert_with(|| {
                 debug!(
                     "{TRANSPORT_FORWARDERS_TAG}:{TRANSPORT_FORWARDERS_FN_INSERT_TAG} Inserting..."
                 );
-                let (tx, rx) = channel::bounded(self.message_queue_size);
-                (0, Arc::new(TransportForwarder::new(out_transport, rx)), tx)
+                (
+                    0,
+                    Arc::new(TransportForwarder::new(
+                        out_transport,
+                        self.message_queue_size,
+                        out_health,
+                        Arc::new(SourceQuotas::new(self.source_quota_config.clone())),
+                        self.counters.clone(),
+                    )),
+                )
             });
         *active += 1;
-        sender.clone()
+        (
+            transport_forwarder.add_lane(rule_id, scheduling_weight),
+            transport_forwarder.clone(),
+        )
     }
 
-    pub async fn remove(&mut self, out_transport: Arc<dyn UTransport>) {
+    pub async fn remove(&mut self, out_transport: Arc<dyn UTransport>, rule_id: ForwardingRuleId) {
         let out_comparable_transport = ComparableTransport::new(out_transport.clone());
 
         let mut transport_forwarders = self.forwarders.lock().await;
 
         let active_num = {
-            let Some((active, _, _)) = transport_forwarders.get_mut(&out_comparable_transport)
+            let Some((active, transport_forwarder)) =
+                transport_forwarders.get_mut(&out_comparable_transport)
             else {
                 warn!("{TRANSPORT_FORWARDERS_TAG}:{TRANSPORT_FORWARDERS_FN_REMOVE_TAG} no such out_comparable_transport");
                 return;
             };
 
+            transport_forwarder.remove_lane(rule_id);
             *active -= 1;
             *active
         };
@@ -123,40 +212,146 @@ impl TransportForwarders {
 const FORWARDING_LISTENERS_TAG: &str = "ForwardingListeners:";
 const FORWARDING_LISTENERS_FN_INSERT_TAG: &str = "insert:";
 const FORWARDING_LISTENERS_FN_REMOVE_TAG: &str = "remove:";
+const FORWARDING_LISTENERS_FN_RECONCILE_TAG: &str = "reconcile:";
+const FORWARDING_LISTENERS_FN_RECONCILIATION_LOOP_TAG: &str = "reconciliation_loop:";
+const FORWARDING_LISTENERS_FN_GIVE_UP_ON_REQUESTS_TAG: &str = "give_up_on_requests:";
 
+// subscribe requests whose retained values were replayed which we remember, so as not to replay
+// them again for every other rule forwarding the same request
+const REPLAYED_SUBSCRIPTIONS_REMEMBERED: usize = 64;
+
+// keyed on in UTransport, forwarding rule and out authority
+type ForwardingListenerKey = (ComparableTransport, ForwardingRuleId, String);
 type ForwardingListenersContainer =
-    Mutex<HashMap<(ComparableTransport, String), (usize, Arc<ForwardingListener>)>>;
+    Mutex<HashMap<ForwardingListenerKey, (usize, Arc<ForwardingListener>)>>;
+// keyed on in UTransport and the values retained on the in endpoint, shared by all of its rules
+type RetainingListenerKey = (ComparableTransport, usize);
+type RetainingListenersContainer =
+    Mutex<HashMap<RetainingListenerKey, (usize, Arc<RetainingListener>)>>;
+
+// the checks every ForwardingListener applies before forwarding, set for the UStreamer as a whole
+#[derive(Default)]
+pub(crate) struct ListenerPolicies {
+    access_policy: std::sync::RwLock<AccessPolicy>,
+    strict_source_authority: AtomicBool,
+    reassembler: Reassembler,
+    protection_keys: std::sync::RwLock<ProtectionKeys>,
+    request_tracker: RequestTracker,
+}
 
-// we must have only a single listener per in UTransport and out UAuthority
+// we must have only a single listener per in UTransport, forwarding rule and out UAuthority
+//
+// rules sharing an in UTransport and an out authority, e.g. onto the same authority over two
+// different transports, each get their own listener, so that every rule forwards with its own
+// ForwardingRoute and in endpoint
 struct ForwardingListeners {
     listeners: ForwardingListenersContainer,
+    retaining_listeners: RetainingListenersContainer,
+    // the out authorities listened for on each in UTransport, so that a message matching several
+    // of them is only forwarded for the most specific one
+    out_authorities: std::sync::RwLock<HashMap<ComparableTransport, HashMap<String, usize>>>,
+    // the ids of the last subscribe requests retained values were replayed for, oldest first
+    replayed_subscriptions: std::sync::Mutex<VecDeque<(u64, u64)>>,
+    reconciliation_config: std::sync::RwLock<ListenerReconciliationConfig>,
+    policies: Arc<ListenerPolicies>,
+    request_expiry: std::sync::Once,
+    counters: Arc<StreamerCounters>,
 }
 
 impl ForwardingListeners {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(
+        counters: Arc<StreamerCounters>,
+        health_events: Receiver<TransportHealthEvent>,
+    ) -> Arc<Self> {
+        let forwarding_listeners = Arc::new(Self {
             listeners: Mutex::new(HashMap::new()),
-        }
+            retaining_listeners: Mutex::new(HashMap::new()),
+            out_authorities: std::sync::RwLock::new(HashMap::new()),
+            replayed_subscriptions: std::sync::Mutex::new(VecDeque::new()),
+            reconciliation_config: std::sync::RwLock::new(ListenerReconciliationConfig::default()),
+            policies: Arc::new(ListenerPolicies::default()),
+            request_expiry: std::sync::Once::new(),
+            counters,
+        });
+        Self::spawn_reconciliation(Arc::downgrade(&forwarding_listeners), health_events);
+        forwarding_listeners
+    }
+
+    pub fn reconciliation_config(&self) -> ListenerReconciliationConfig {
+        self.reconciliation_config.read().unwrap().clone()
+    }
+
+    pub fn set_reconciliation_config(&self, config: ListenerReconciliationConfig) {
+        *self.reconciliation_config.write().unwrap() = config;
+    }
+
+    pub fn set_access_policy(&self, access_policy: AccessPolicy) {
+        *self.policies.access_policy.write().unwrap() = access_policy;
+    }
+
+    pub fn set_strict_source_authority(&self, strict_source_authority: bool) {
+        self.policies
+            .strict_source_authority
+            .store(strict_source_authority, Ordering::Relaxed);
+    }
+
+    pub fn set_reassembly_config(&self, config: ReassemblyConfig) {
+        self.policies.reassembler.set_config(config);
+    }
+
+    pub fn set_protection_keys(&self, protection_keys: ProtectionKeys) {
+        *self.policies.protection_keys.write().unwrap() = protection_keys;
+    }
+
+    pub fn set_request_tracking_config(&self, config: RequestTrackingConfig) {
+        self.policies.request_tracker.set_config(config);
+    }
+
+    pub fn rpc_latency_stats(&self) -> Vec<RpcLatencyStats> {
+        self.policies.request_tracker.latency_stats()
     }
 
     pub async fn insert(
-        &self,
-        in_transport: Arc<dyn UTransport>,
+        self: &Arc<Self>,
+        in_endpoint: &Endpoint,
         out_authority: &str,
+        rule_id: ForwardingRuleId,
         forwarding_id: &str,
-        out_sender: Sender<Arc<UMessage>>,
+        forwarding_route: Arc<ForwardingRoute>,
+        in_health: Arc<TransportHealth>,
     ) -> Option<Arc<ForwardingListener>> {
+        let in_transport = in_endpoint.transport.clone();
         let in_comparable_transport = ComparableTransport::new(in_transport.clone());
 
         let mut forwarding_listeners = self.listeners.lock().await;
 
         let (active, forwarding_listener) = forwarding_listeners
-            .entry((in_comparable_transport.clone(), out_authority.to_string()))
+            .entry((
+                in_comparable_transport.clone(),
+                rule_id,
+                out_authority.to_string(),
+            ))
             .or_insert_with(|| {
-                let forwarding_listener = Arc::new(ForwardingListener::new(forwarding_id, out_sender));
+                let forwarding_listener = Arc::new(ForwardingListener::new(
+                    rule_id,
+                    forwarding_id,
+                    out_authority,
+                    forwarding_route,
+                    in_endpoint,
+                    in_health,
+                    self,
+                ));
+                *self
+                    .out_authorities
+                    .write()
+                    .unwrap()
+                    .entry(in_comparable_transport.clone())
+                    .or_default()
+                    .entry(out_authority.to_string())
+                    .or_default() += 1;
 
                 let reg_res = task::block_on(in_transport
-                    .register_listener(&any_uuri(), Some(&uauthority_to_uuri(out_authority)), forwarding_listener.clone()));
+                    .register_listener(&any_uuri(), Some(&uauthority_to_uuri(registration_authority(out_authority))), forwarding_listener.clone()));
 
                 if let Err(err) = reg_res {
                     warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_INSERT_TAG} unable to register listener, error: {err}");
@@ -178,15 +373,19 @@ impl ForwardingListeners {
         }
     }
 
-    pub async fn remove(&self, in_transport: Arc<dyn UTransport>, out_authority: &str) {
+    pub async fn remove(
+        &self,
+        in_transport: Arc<dyn UTransport>,
+        rule_id: ForwardingRuleId,
+        out_authority: &str,
+    ) {
         let in_comparable_transport = ComparableTransport::new(in_transport.clone());
+        let key = (in_comparable_transport, rule_id, out_authority.to_string());
 
         let mut forwarding_listeners = self.listeners.lock().await;
 
         let active_num = {
-            let Some((active, _)) = forwarding_listeners
-                .get_mut(&(in_comparable_transport.clone(), out_authority.to_string()))
-            else {
+            let Some((active, _)) = forwarding_listeners.get_mut(&key) else {
                 warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_REMOVE_TAG} no such out_comparable_transport, out_authority: {out_authority:?}");
                 return;
             };
@@ -195,13 +394,13 @@ impl ForwardingListeners {
         };
 
         if active_num == 0 {
-            let removed =
-                forwarding_listeners.remove(&(in_comparable_transport, out_authority.to_string()));
+            let removed = forwarding_listeners.remove(&key);
+            self.remove_out_authority(&key.0, out_authority);
             warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_REMOVE_TAG} removing ForwardingListener, out_authority: {out_authority:?}");
             if let Some((_, forwarding_listener)) = removed {
                 warn!("ForwardingListeners::remove: ForwardingListener found we can remove, out_authority: {out_authority:?}");
                 let unreg_res = task::block_on(in_transport.unregister_listener(
-                    &uauthority_to_uuri(out_authority),
+                    &uauthority_to_uuri(registration_authority(out_authority)),
                     Some(&any_uuri()),
                     forwarding_listener,
                 ));
@@ -216,6 +415,387 @@ impl ForwardingListeners {
             }
         }
     }
+
+    fn remove_out_authority(&self, in_transport: &ComparableTransport, out_authority: &str) {
+        let mut out_authorities = self.out_authorities.write().unwrap();
+        let Some(transport_out_authorities) = out_authorities.get_mut(in_transport) else {
+            return;
+        };
+        if let Some(listeners) = transport_out_authorities.get_mut(out_authority) {
+            *listeners -= 1;
+            if *listeners == 0 {
+                transport_out_authorities.remove(out_authority);
+            }
+        }
+        if transport_out_authorities.is_empty() {
+            out_authorities.remove(in_transport);
+        }
+    }
+
+    // whether a message for `sink_authority` which arrived on `in_transport` is to be forwarded for
+    // `out_authority`, rather than for one which matches it more specifically, e.g. `zone-1` over
+    // `zone-*`
+    fn is_most_specific_out_authority(
+        &self,
+        in_transport: &ComparableTransport,
+        out_authority: &str,
+        sink_authority: &str,
+    ) -> bool {
+        let out_authorities = self.out_authorities.read().unwrap();
+        let Some(transport_out_authorities) = out_authorities.get(in_transport) else {
+            return true;
+        };
+        most_specific_match(
+            transport_out_authorities.keys().map(String::as_str),
+            sink_authority,
+        )
+        .map_or(true, |most_specific| most_specific == out_authority)
+    }
+
+    // retaining happens ahead of the listeners of the rules, which only hear what they forward
+    pub async fn insert_retaining(
+        &self,
+        in_transport: Arc<dyn UTransport>,
+        retained_values: &Arc<RetainedValues>,
+    ) {
+        let key = (
+            ComparableTransport::new(in_transport.clone()),
+            Arc::as_ptr(retained_values) as usize,
+        );
+
+        let mut retaining_listeners = self.retaining_listeners.lock().await;
+        let (active, _) = retaining_listeners.entry(key).or_insert_with(|| {
+            let retaining_listener = Arc::new(RetainingListener {
+                retained_values: retained_values.clone(),
+            });
+            if let Err(err) =
+                task::block_on(register_retaining_listener(&in_transport, &retaining_listener))
+            {
+                warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_INSERT_TAG} unable to register retaining listener, error: {err}");
+            }
+            (0, retaining_listener)
+        });
+        *active += 1;
+    }
+
+    pub async fn remove_retaining(
+        &self,
+        in_transport: Arc<dyn UTransport>,
+        retained_values: &Arc<RetainedValues>,
+    ) {
+        let key = (
+            ComparableTransport::new(in_transport.clone()),
+            Arc::as_ptr(retained_values) as usize,
+        );
+
+        let mut retaining_listeners = self.retaining_listeners.lock().await;
+        let Some((active, _)) = retaining_listeners.get_mut(&key) else {
+            warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_REMOVE_TAG} no such retaining listener");
+            return;
+        };
+        *active -= 1;
+        if *active > 0 {
+            return;
+        }
+        if let Some((_, retaining_listener)) = retaining_listeners.remove(&key) {
+            task::block_on(unregister_retaining_listener(
+                &in_transport,
+                &retaining_listener,
+            ));
+        }
+    }
+
+    /// Replays the values retained for `subscriber_authority` through every rule onto it, once per
+    /// rule
+    ///
+    /// Every rule forwarding the subscribe request `subscribe_request_id` hears it, so the values
+    /// are only replayed for the first of them.
+    pub async fn replay_retained_values_to(
+        &self,
+        subscriber_authority: &str,
+        subscribe_request_id: Option<(u64, u64)>,
+    ) {
+        if let Some(subscribe_request_id) = subscribe_request_id {
+            let mut replayed_subscriptions = self.replayed_subscriptions.lock().unwrap();
+            if replayed_subscriptions.contains(&subscribe_request_id) {
+                return;
+            }
+            if replayed_subscriptions.len() >= REPLAYED_SUBSCRIPTIONS_REMEMBERED {
+                replayed_subscriptions.pop_front();
+            }
+            replayed_subscriptions.push_back(subscribe_request_id);
+        }
+        let replaying: Vec<Arc<ForwardingListener>> = {
+            let forwarding_listeners = self.listeners.lock().await;
+            let mut replaying_rules = HashSet::new();
+            forwarding_listeners
+                .iter()
+                .filter(|((_, rule_id, out_authority), (_, forwarding_listener))| {
+                    forwarding_listener.in_retained_values.is_some()
+                        && authority_matches(out_authority, subscriber_authority)
+                        && replaying_rules.insert(*rule_id)
+                })
+                .map(|(_, (_, forwarding_listener))| forwarding_listener.clone())
+                .collect()
+        };
+        for forwarding_listener in replaying {
+            forwarding_listener
+                .replay_retained_values(&[subscriber_authority.to_string()])
+                .await;
+        }
+    }
+
+    /// Re-registers the listeners on `in_transport`, or on every in `UTransport` if `None`,
+    /// retrying with backoff those the `UTransport` refuses
+    pub async fn reconcile(
+        &self,
+        in_transport: Option<Arc<dyn UTransport>>,
+    ) -> Result<(), UStatus> {
+        let config = self.reconciliation_config();
+        let in_comparable_transport = in_transport.map(ComparableTransport::new);
+
+        let mut pending: Vec<(ForwardingListenerKey, Arc<ForwardingListener>)> = self
+            .listeners
+            .lock()
+            .await
+            .iter()
+            .filter(|((transport, _, _), _)| {
+                in_comparable_transport
+                    .as_ref()
+                    .map_or(true, |in_comparable_transport| {
+                        in_comparable_transport == transport
+                    })
+            })
+            .map(|(key, (_, forwarding_listener))| (key.clone(), forwarding_listener.clone()))
+            .collect();
+
+        {
+            // hold onto the lock while registering, so that we can't race the retaining listener
+            // being removed
+            let retaining_listeners = self.retaining_listeners.lock().await;
+            for ((transport, _), (_, retaining_listener)) in retaining_listeners.iter() {
+                if in_comparable_transport
+                    .as_ref()
+                    .is_some_and(|in_comparable_transport| in_comparable_transport != transport)
+                {
+                    continue;
+                }
+                unregister_retaining_listener(&transport.transport, retaining_listener).await;
+                if let Err(err) =
+                    register_retaining_listener(&transport.transport, retaining_listener).await
+                {
+                    warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_RECONCILE_TAG} unable to re-register retaining listener, error: {err}");
+                }
+            }
+        }
+
+        let max_attempts = config.max_attempts.max(1);
+        let mut backoff = config.initial_backoff;
+        for attempt in 1..=max_attempts {
+            let mut failed = Vec::new();
+            for (key, forwarding_listener) in pending {
+                // hold onto the lock while registering, so that we can't race a rule being deleted
+                let forwarding_listeners = self.listeners.lock().await;
+                let still_registered = forwarding_listeners
+                    .get(&key)
+                    .is_some_and(|(_, current)| Arc::ptr_eq(current, &forwarding_listener));
+                if !still_registered {
+                    continue;
+                }
+
+                let (in_comparable_transport, _, out_authority) = &key;
+                let in_transport = &in_comparable_transport.transport;
+                let sink_filter = uauthority_to_uuri(registration_authority(out_authority));
+                // a transport may well take the same listener twice, so we take ours off it first,
+                // which also tells us whether it still had it
+                let was_registered = in_transport
+                    .unregister_listener(
+                        &any_uuri(),
+                        Some(&sink_filter),
+                        forwarding_listener.clone(),
+                    )
+                    .await
+                    .is_ok();
+                let reg_res = in_transport
+                    .register_listener(&any_uuri(), Some(&sink_filter), forwarding_listener.clone())
+                    .await;
+                match reg_res {
+                    Ok(()) if was_registered => {}
+                    Ok(()) => {
+                        info!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_RECONCILE_TAG} re-registered listener, out_authority: {out_authority:?}");
+                        StreamerCounters::increment(&self.counters.listener_reregistrations);
+                    }
+                    Err(err) => {
+                        warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_RECONCILE_TAG} unable to re-register listener, attempt: {attempt}, out_authority: {out_authority:?}, error: {err}");
+                        failed.push((key, forwarding_listener));
+                    }
+                }
+            }
+
+            pending = failed;
+            if pending.is_empty() {
+                return Ok(());
+            }
+            if attempt < max_attempts {
+                task::sleep(backoff).await;
+                backoff = (backoff * 2).min(config.max_backoff);
+            }
+        }
+
+        for _ in &pending {
+            StreamerCounters::increment(&self.counters.listener_reregistration_failures);
+        }
+        Err(UStatus::fail_with_code(
+            UCode::UNAVAILABLE,
+            format!(
+                "unable to re-register {} listeners after {max_attempts} attempts",
+                pending.len()
+            ),
+        ))
+    }
+
+    // re-registers the listeners periodically and whenever a transport comes back up, until the
+    // UStreamer has been dropped
+    fn spawn_reconciliation(
+        forwarding_listeners: Weak<Self>,
+        health_events: Receiver<TransportHealthEvent>,
+    ) {
+        thread::spawn(move || {
+            task::block_on(async move {
+                loop {
+                    let Some(config) = forwarding_listeners
+                        .upgrade()
+                        .map(|forwarding_listeners| forwarding_listeners.reconciliation_config())
+                    else {
+                        break;
+                    };
+
+                    // without a periodic interval we still wake up now and then to notice being dropped
+                    let wait = config.interval.unwrap_or(Duration::from_secs(1));
+                    let reconcile =
+                        match async_std::future::timeout(wait, health_events.recv()).await {
+                            Ok(Ok(event)) => {
+                                config.on_transport_recovery
+                                    && event.previous_state == TransportHealthState::Down
+                                    && event.state != TransportHealthState::Down
+                            }
+                            Ok(Err(_)) => break,
+                            Err(_) => config.interval.is_some(),
+                        };
+                    if !reconcile {
+                        continue;
+                    }
+
+                    let Some(forwarding_listeners) = forwarding_listeners.upgrade() else {
+                        break;
+                    };
+                    if let Err(err) = forwarding_listeners.reconcile(None).await {
+                        warn!("{FORWARDING_LISTENERS_TAG}:{FORWARDING_LISTENERS_FN_RECONCILIATION_LOOP_TAG} {err:?}");
+                    }
+                }
+            })
+        });
+    }
+
+    // starts giving up on the requests whose TTL ran out, once the first one is tracked
+    fn start_request_expiry(self: &Arc<Self>) {
+        self.request_expiry.call_once(|| {
+            Self::spawn_request_expiry(
+                Arc::downgrade(self),
+                self.policies.request_tracker.expiry_wakeups(),
+            )
+        });
+    }
+
+    // gives up on the requests whose TTL ran out as they do, until we're dropped
+    fn spawn_request_expiry(forwarding_listeners: Weak<Self>, expiry_wakeups: Receiver<()>) {
+        thread::spawn(move || {
+            task::block_on(async move {
+                loop {
+                    let Some(next_expiry) =
+                        forwarding_listeners.upgrade().map(|forwarding_listeners| {
+                            forwarding_listeners.policies.request_tracker.next_expiry()
+                        })
+                    else {
+                        break;
+                    };
+
+                    // a request which runs out sooner than the one we're waiting for wakes us up,
+                    // as does the UStreamer being dropped
+                    let woken_up = match next_expiry {
+                        Some(next_expiry) => async_std::future::timeout(
+                            next_expiry.saturating_duration_since(Instant::now()),
+                            expiry_wakeups.recv(),
+                        )
+                        .await
+                        .unwrap_or(Ok(())),
+                        None => expiry_wakeups.recv().await,
+                    };
+                    if woken_up.is_err() {
+                        break;
+                    }
+
+                    let Some(forwarding_listeners) = forwarding_listeners.upgrade() else {
+                        break;
+                    };
+                    let expired = forwarding_listeners.policies.request_tracker.take_expired();
+                    forwarding_listeners.give_up_on_requests(expired).await;
+                    let interval = forwarding_listeners
+                        .policies
+                        .request_tracker
+                        .config()
+                        .expiry_check_interval;
+                    drop(forwarding_listeners);
+                    task::sleep(interval).await;
+                }
+            })
+        });
+    }
+
+    // answers the requests given up on, as their TTL ran out or to make room for newer ones, with
+    // DEADLINE_EXCEEDED for rules asking for it, so that their senders learn the request died
+    // beyond us rather than waiting on their own timers
+    async fn give_up_on_requests(&self, given_up: Vec<OutstandingRequest>) {
+        StreamerCounters::add(&self.counters.expired_requests, given_up.len() as u64);
+
+        for outstanding_request in given_up {
+            let attributes = &outstanding_request.attributes;
+            debug!(
+                "{}:{} Request: {} forwarded from in endpoint: {} was given up on without a response",
+                FORWARDING_LISTENERS_TAG,
+                FORWARDING_LISTENERS_FN_GIVE_UP_ON_REQUESTS_TAG,
+                attributes.id.to_hyphenated_string(),
+                outstanding_request.route.in_endpoint
+            );
+            let Some(reply_transport) = outstanding_request
+                .route
+                .deadline_reply_transport
+                .and_then(|reply_transport| reply_transport.upgrade())
+            else {
+                continue;
+            };
+            let response = match UMessageBuilder::response_for_request(attributes)
+                .with_comm_status(UCode::DEADLINE_EXCEEDED)
+                .build()
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    warn!(
+                        "{}:{} Unable to build DEADLINE_EXCEEDED response: {err:?}",
+                        FORWARDING_LISTENERS_TAG, FORWARDING_LISTENERS_FN_GIVE_UP_ON_REQUESTS_TAG
+                    );
+                    continue;
+                }
+            };
+            match reply_transport.send(response).await {
+                Ok(()) => StreamerCounters::increment(&self.counters.deadline_exceeded_responses),
+                Err(err) => warn!(
+                    "{}:{} Unable to send DEADLINE_EXCEEDED response: {err:?}",
+                    FORWARDING_LISTENERS_TAG, FORWARDING_LISTENERS_FN_GIVE_UP_ON_REQUESTS_TAG
+                ),
+            }
+        }
+    }
 }
 
 /// A [`UStreamer`] is used to coordinate the addition and deletion of forwarding rules between
@@ -408,8 +988,11 @@ impl ForwardingListeners {
 pub struct UStreamer {
     name: String,
     registered_forwarding_rules: ForwardingRules,
+    next_rule_id: ForwardingRuleId,
     transport_forwarders: TransportForwarders,
-    forwarding_listeners: ForwardingListeners,
+    forwarding_listeners: Arc<ForwardingListeners>,
+    counters: Arc<StreamerCounters>,
+    health_monitor: Arc<TransportHealthMonitor>,
 }
 
 impl UStreamer {
@@ -418,8 +1001,8 @@ impl UStreamer {
     /// # Parameters
     ///
     /// * name - Used to uniquely identify this UStreamer in logs
-    /// * message_queue_size - Determines size of channel used to communicate between `ForwardingListener`
-    ///                        and the worker tasks for each currently endpointd `UTransport`
+    /// * message_queue_size - Determines size of the queue each forwarding rule has in front of the worker
+    ///                        task for each of its out `UTransport`s, which serves those queues in turn
     pub fn new(name: &str, message_queue_size: u16) -> Self {
         let name = format!("{USTREAMER_TAG}:{name}:");
         // Try to initiate logging.
@@ -431,19 +1014,162 @@ impl UStreamer {
             &name, USTREAMER_TAG, USTREAMER_FN_NEW_TAG
         );
 
+        let counters = Arc::new(StreamerCounters::default());
+        let health_monitor = TransportHealthMonitor::new();
+
         Self {
             name: name.to_string(),
-            registered_forwarding_rules: Mutex::new(HashSet::new()),
-            transport_forwarders: TransportForwarders::new(message_queue_size as usize),
-            forwarding_listeners: ForwardingListeners::new(),
+            registered_forwarding_rules: Mutex::new(HashMap::new()),
+            next_rule_id: 0,
+            transport_forwarders: TransportForwarders::new(
+                message_queue_size as usize,
+                counters.clone(),
+            ),
+            forwarding_listeners: ForwardingListeners::new(
+                counters.clone(),
+                health_monitor.subscribe(),
+            ),
+            counters,
+            health_monitor,
         }
     }
 
+    /// Returns a snapshot of the counters kept while forwarding, e.g. the number of failovers
+    pub fn metrics(&self) -> StreamerMetrics {
+        self.counters.snapshot()
+    }
+
+    /// Sets how the health of each [`UTransport`][up_rust::UTransport] is judged, applies to
+    /// every [`Endpoint`][crate::Endpoint] from now on
+    ///
+    /// While a [`UTransport`][up_rust::UTransport] is
+    /// [`TransportHealthState::Down`][crate::TransportHealthState::Down], forwarding onto it is
+    /// paused and messages for it are dropped, rather than hammering it with sends.
+    pub fn set_transport_health_config(&self, config: TransportHealthConfig) {
+        self.health_monitor.set_config(config);
+    }
+
+    /// Returns the current health of the [`UTransport`][up_rust::UTransport] behind `endpoint`,
+    /// or `None` if it isn't used by any forwarding rule
+    pub fn transport_health_state(&self, endpoint: &Endpoint) -> Option<TransportHealthState> {
+        self.health_monitor.state(endpoint.transport.clone())
+    }
+
+    /// Returns a [`Receiver`][async_std::channel::Receiver] notified of every change in the
+    /// health of a [`UTransport`][up_rust::UTransport] used by this [`UStreamer`], e.g. to alert
+    /// operators when one goes down
+    ///
+    /// Up to 64 changes are kept for the [`Receiver`][async_std::channel::Receiver], any more are
+    /// dropped until it catches up.
+    pub fn transport_health_events(&self) -> Receiver<TransportHealthEvent> {
+        self.health_monitor.subscribe()
+    }
+
+    /// Sets the [`AccessPolicy`][crate::AccessPolicy] deciding which messages are forwarded at
+    /// all, applies to every forwarding rule, including those already added
+    ///
+    /// Denied messages are dropped and counted. If they are requests, their sender is sent a
+    /// response with [`UCode::PERMISSION_DENIED`][up_rust::UCode::PERMISSION_DENIED].
+    pub fn set_access_policy(&self, access_policy: AccessPolicy) {
+        self.forwarding_listeners.set_access_policy(access_policy);
+    }
+
+    /// Sets whether messages are only forwarded if the authority of their source
+    /// [`UUri`][up_rust::UUri] is one of the authorities of the in [`Endpoint`][crate::Endpoint]
+    /// they arrived on, applies to every forwarding rule, including those already added
+    ///
+    /// Off by default. When on, other messages are logged, counted and dropped, so that a node on
+    /// one network can't impersonate an authority on another across the bridge.
+    pub fn set_strict_source_authority(&self, strict_source_authority: bool) {
+        self.forwarding_listeners
+            .set_strict_source_authority(strict_source_authority);
+    }
+
+    /// Sets the limits on reassembling messages which a peer [`UStreamer`] split into fragments
+    /// with [`OversizePolicy::Fragment`][crate::OversizePolicy::Fragment], see
+    /// [`ReassemblyConfig`][crate::ReassemblyConfig]
+    ///
+    /// Its `max_message_size` also bounds the payloads of messages a peer [`UStreamer`] compressed.
+    pub fn set_reassembly_config(&self, reassembly_config: ReassemblyConfig) {
+        self.forwarding_listeners
+            .set_reassembly_config(reassembly_config);
+    }
+
+    /// Sets the keys messages protected by a peer [`UStreamer`] with a
+    /// [`PayloadProtection`][crate::PayloadProtection] are checked and decrypted with, see
+    /// [`ProtectionKeys`][crate::ProtectionKeys]
+    ///
+    /// Protected messages which fail the checks are logged, counted and dropped.
+    pub fn set_protection_keys(&self, protection_keys: ProtectionKeys) {
+        self.forwarding_listeners
+            .set_protection_keys(protection_keys);
+    }
+
+    /// Sets how responses are correlated with the requests they answer, see
+    /// [`RequestTrackingConfig`][crate::RequestTrackingConfig]
+    ///
+    /// Every request forwarded is tracked, along with the in [`Endpoint`][crate::Endpoint] it
+    /// arrived on, until its response is forwarded or its TTL runs out. Orphaned and misrouted
+    /// responses are logged and counted in [`UStreamer::metrics`].
+    pub fn set_request_tracking_config(&self, request_tracking_config: RequestTrackingConfig) {
+        self.forwarding_listeners
+            .set_request_tracking_config(request_tracking_config);
+    }
+
+    /// Returns the round-trip latency of the requests forwarded to each service, see
+    /// [`RpcLatencyStats`][crate::RpcLatencyStats]
+    pub fn rpc_latency_stats(&self) -> Vec<RpcLatencyStats> {
+        self.forwarding_listeners.rpc_latency_stats()
+    }
+
+    /// Sets the quotas each source may use of every out [`UTransport`][up_rust::UTransport]'s
+    /// forwarding, see [`SourceQuotaConfig`][crate::SourceQuotaConfig]
+    ///
+    /// Applies to every forwarding rule, including those already added.
+    pub fn set_source_quota_config(&self, config: SourceQuotaConfig) {
+        self.transport_forwarders.set_source_quota_config(config);
+    }
+
+    /// Sets when the listeners of the forwarding rules are re-registered on their in
+    /// [`UTransport`][up_rust::UTransport]s, see
+    /// [`ListenerReconciliationConfig`][crate::ListenerReconciliationConfig]
+    pub fn set_listener_reconciliation_config(&self, config: ListenerReconciliationConfig) {
+        self.forwarding_listeners.set_reconciliation_config(config);
+    }
+
+    /// Re-registers the listeners of every forwarding rule on their in
+    /// [`UTransport`][up_rust::UTransport]s
+    ///
+    /// Each listener is unregistered before it's registered again, so that a
+    /// [`UTransport`][up_rust::UTransport] which still has it, and would take it twice, doesn't
+    /// end up delivering every message twice.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`UStatus`][up_rust::UStatus] with [`UCode::UNAVAILABLE`][up_rust::UCode::UNAVAILABLE]
+    /// if some listeners couldn't be re-registered within the configured number of attempts.
+    pub async fn reconcile_listeners(&self) -> Result<(), UStatus> {
+        self.forwarding_listeners.reconcile(None).await
+    }
+
+    /// Signals that the [`UTransport`][up_rust::UTransport] behind `endpoint` re-established its
+    /// session, e.g. after its router restarted, so that the listeners of every forwarding rule
+    /// bridging from it are re-registered
+    ///
+    /// # Errors
+    ///
+    /// As with [`UStreamer::reconcile_listeners`].
+    pub async fn notify_transport_reconnected(&self, endpoint: &Endpoint) -> Result<(), UStatus> {
+        self.forwarding_listeners
+            .reconcile(Some(endpoint.transport.clone()))
+            .await
+    }
+
     #[inline(always)]
     fn forwarding_id(r#in: &Endpoint, out: &Endpoint) -> String {
         format!(
-            "[in.name: {}, in.authority: {:?} ; out.name: {}, out.authority: {:?}]",
-            r#in.name, r#in.authority, out.name, out.authority
+            "[in.name: {}, in.authorities: {:?} ; out.name: {}, out.authorities: {:?}]",
+            r#in.name, r#in.authorities, out.name, out.authorities
         )
     }
 
@@ -472,6 +1198,12 @@ impl UStreamer {
     /// * [`UMessageType::UMESSAGE_TYPE_REQUEST`][up_rust::UMessageType::UMESSAGE_TYPE_REQUEST]
     /// * [`UMessageType::UMESSAGE_TYPE_RESPONSE`][up_rust::UMessageType::UMESSAGE_TYPE_RESPONSE]
     ///
+    /// Messages are forwarded for every authority of the `out` [`Endpoint`][crate::Endpoint]. If
+    /// one of those is an authority pattern, e.g. `zone-*`, any message whose sink authority
+    /// matches the pattern is forwarded, unless a rule from the same in
+    /// [`UTransport`][up_rust::UTransport] names it more specifically, e.g. `zone-1`, in which
+    /// case only that rule forwards it.
+    ///
     /// # Parameters
     ///
     /// * `in` - [`Endpoint`][crate::Endpoint] we will bridge _from_
@@ -484,11 +1216,45 @@ impl UStreamer {
     ///
     /// Typical errors include
     /// * already have this forwarding rule registered
-    /// * attempting to forward onto the same [`Endpoint`][crate::Endpoint]
+    /// * attempting to forward onto the same [`Endpoint`][crate::Endpoint], or onto an
+    ///   [`Endpoint`][crate::Endpoint] sharing an authority with the `in`
+    ///   [`Endpoint`][crate::Endpoint]
     pub async fn add_forwarding_rule(
         &mut self,
         r#in: Endpoint,
         out: Endpoint,
+    ) -> Result<(), UStatus> {
+        self.add_forwarding_rule_with_options(r#in, out, ForwardingRuleOptions::default())
+            .await
+    }
+
+    /// Adds a forwarding rule to the [`UStreamer`] as with [`UStreamer::add_forwarding_rule`], with
+    /// additional behavior described by [`ForwardingRuleOptions`][crate::ForwardingRuleOptions]
+    ///
+    /// The rule is identified by `in` and `out`, so it's deleted again with
+    /// [`UStreamer::delete_forwarding_rule`], including e.g. any standby
+    /// [`Endpoint`][crate::Endpoint]s.
+    ///
+    /// # Parameters
+    ///
+    /// * `in` - [`Endpoint`][crate::Endpoint] we will bridge _from_
+    /// * `out` - [`Endpoint`][crate::Endpoint] we will bridge _onto_, the primary if the rule has
+    ///           standby [`Endpoint`][crate::Endpoint]s
+    /// * `options` - [`ForwardingRuleOptions`][crate::ForwardingRuleOptions] for this rule
+    ///
+    /// # Errors
+    ///
+    /// As with [`UStreamer::add_forwarding_rule`], where the checks against the `in`
+    /// [`Endpoint`][crate::Endpoint] apply to every out [`Endpoint`][crate::Endpoint] of the rule.
+    /// Options which can't be honored, e.g. a [`RateLimit`][crate::RateLimit] or
+    /// [`ConflationConfig`][crate::ConflationConfig] of `0` per second, or a standby
+    /// [`Endpoint`][crate::Endpoint] which doesn't serve the authorities of `out`, fail with
+    /// [`UCode::INVALID_ARGUMENT`][up_rust::UCode::INVALID_ARGUMENT].
+    pub async fn add_forwarding_rule_with_options(
+        &mut self,
+        r#in: Endpoint,
+        out: Endpoint,
+        options: ForwardingRuleOptions,
     ) -> Result<(), UStatus> {
         debug!(
             "{}:{}:{} Adding forwarding rule for {}",
@@ -498,45 +1264,161 @@ impl UStreamer {
             Self::forwarding_id(&r#in, &out)
         );
 
-        if r#in.authority == out.authority {
-            return self.fail_due_to_same_authority(&r#in, &out);
+        let out_endpoints: Vec<Endpoint> = std::iter::once(out.clone())
+            .chain(options.additional_out_endpoints.iter().cloned())
+            .collect();
+
+        if let Some(same_authority_out) = out_endpoints
+            .iter()
+            .find(|out_endpoint| out_endpoint.shares_authority_with(&r#in))
+        {
+            return self.fail_due_to_same_authority(&r#in, same_authority_out);
         }
 
+        if let OutSelection::Failover(_) = options.out_selection {
+            if let Some(standby) = options
+                .additional_out_endpoints
+                .iter()
+                .find(|standby| !standby.serves_authorities_of(&out))
+            {
+                let err = UStatus::fail_with_code(
+                    UCode::INVALID_ARGUMENT,
+                    format!(
+                        "standby Endpoint: {} doesn't serve every authority of Endpoint: {}",
+                        standby.name, out.name
+                    ),
+                );
+                warn!(
+                    "{}:{}:{} Adding forwarding rule failed: {:?}",
+                    self.name, USTREAMER_TAG, USTREAMER_FN_ADD_FORWARDING_RULE_TAG, err
+                );
+                return Err(err);
+            }
+        }
+
+        let limiters = options
+            .rate_limit
+            .as_ref()
+            .map(RateLimiter::new)
+            .transpose()
+            .and_then(|rate_limiter| {
+                let conflator = options
+                    .conflation
+                    .as_ref()
+                    .map(Conflator::new)
+                    .transpose()?;
+                Ok((rate_limiter, conflator))
+            });
+        let (rate_limiter, conflator) = match limiters {
+            Ok(limiters) => limiters,
+            Err(err) => {
+                warn!(
+                    "{}:{}:{} Adding forwarding rule failed: {:?}",
+                    self.name, USTREAMER_TAG, USTREAMER_FN_ADD_FORWARDING_RULE_TAG, err
+                );
+                return Err(err);
+            }
+        };
+
         let in_comparable_transport = ComparableTransport::new(r#in.transport.clone());
         let out_comparable_transport = ComparableTransport::new(out.transport.clone());
+        let rule_key = (
+            r#in.name.clone(),
+            out.name.clone(),
+            in_comparable_transport,
+            out_comparable_transport,
+        );
 
-        {
-            let mut registered_forwarding_rules = self.registered_forwarding_rules.lock().await;
-            match registered_forwarding_rules.insert((
-                r#in.authority.clone(),
-                out.authority.clone(),
-                in_comparable_transport,
-                out_comparable_transport,
-            )) {
-                true => {
-                    let out_sender = self
-                        .transport_forwarders
-                        .insert(out.transport.clone())
-                        .await;
-                    self.forwarding_listeners
-                        .insert(
-                            r#in.transport.clone(),
-                            &out.authority,
-                            &Self::forwarding_id(&r#in, &out),
-                            out_sender,
-                        )
-                        .await;
-                    Ok(())
-                }
-                false => {
-                    // TODO: Add logging on inability to register
-                    Err(UStatus::fail_with_code(
-                        UCode::ALREADY_EXISTS,
-                        "already exists",
-                    ))
-                }
-            }
+        let mut registered_forwarding_rules = self.registered_forwarding_rules.lock().await;
+        if registered_forwarding_rules.contains_key(&rule_key) {
+            let err = UStatus::fail_with_code(UCode::ALREADY_EXISTS, "already exists");
+            warn!(
+                "{}:{}:{} Adding forwarding rule failed: {:?}",
+                self.name, USTREAMER_TAG, USTREAMER_FN_ADD_FORWARDING_RULE_TAG, err
+            );
+            return Err(err);
+        }
+
+        let forwarding_id = Self::forwarding_id(&r#in, &out);
+        let rule_id = self.next_rule_id;
+        self.next_rule_id += 1;
+
+        let mut forwarding_targets = Vec::with_capacity(out_endpoints.len());
+        for out_endpoint in &out_endpoints {
+            let out_health = self
+                .health_monitor
+                .health(&out_endpoint.name, out_endpoint.transport.clone());
+            let (out_sender, transport_forwarder) = self
+                .transport_forwarders
+                .insert(
+                    out_endpoint.transport.clone(),
+                    out_health,
+                    rule_id,
+                    options.scheduling_weight,
+                )
+                .await;
+            forwarding_targets.push(ForwardingTarget {
+                endpoint_name: out_endpoint.name.clone(),
+                transport: out_endpoint.transport.clone(),
+                sender: out_sender,
+                health: transport_forwarder.health.clone(),
+                source_quotas: transport_forwarder.source_quotas.clone(),
+                conflating_routes: transport_forwarder.conflating_routes.clone(),
+                max_payload_size: out_endpoint.max_payload_size,
+                oversize_policy: out_endpoint.oversize_policy,
+            });
+        }
+        let forwarding_route = ForwardingRoute::new(
+            &forwarding_id,
+            forwarding_targets,
+            options,
+            rate_limiter,
+            conflator,
+            RewriteChecks {
+                policies: self.forwarding_listeners.policies.clone(),
+                in_authorities: r#in.authorities.clone(),
+                out_authorities: out.authorities.clone(),
+            },
+            self.counters.clone(),
+        );
+
+        let in_health = self
+            .health_monitor
+            .health(&r#in.name, r#in.transport.clone());
+        let mut forwarding_listeners = Vec::with_capacity(out.authorities.len());
+        for out_authority in &out.authorities {
+            forwarding_listeners.extend(
+                self.forwarding_listeners
+                    .insert(
+                        &r#in,
+                        out_authority,
+                        rule_id,
+                        &forwarding_id,
+                        forwarding_route.clone(),
+                        in_health.clone(),
+                    )
+                    .await,
+            );
+        }
+        if let Some(retained_values) = &r#in.retained_values {
+            self.forwarding_listeners
+                .insert_retaining(r#in.transport.clone(), retained_values)
+                .await;
+        }
+        registered_forwarding_rules.insert(
+            rule_key,
+            (rule_id, out_endpoints, r#in.retained_values.clone()),
+        );
+        drop(registered_forwarding_rules);
+
+        // brings the out endpoint up to date with the latest values, once for the whole rule
+        if let Some(forwarding_listener) = forwarding_listeners.first() {
+            forwarding_listener
+                .replay_retained_values(&out.authorities)
+                .await;
         }
+
+        Ok(())
     }
 
     /// Deletes a forwarding rule from the [`UStreamer`] based on an in [`Endpoint`][crate::Endpoint] and an
@@ -574,7 +1456,7 @@ impl UStreamer {
             Self::forwarding_id(&r#in, &out)
         );
 
-        if r#in.authority == out.authority {
+        if out.shares_authority_with(&r#in) {
             return self.fail_due_to_same_authority(&r#in, &out);
         }
 
@@ -584,29 +1466,42 @@ impl UStreamer {
         let remove_res = {
             let mut registered_forwarding_rules = self.registered_forwarding_rules.lock().await;
             registered_forwarding_rules.remove(&(
-                r#in.authority.clone(),
-                out.authority.clone(),
+                r#in.name.clone(),
+                out.name.clone(),
                 in_comparable_transport.clone(),
                 out_comparable_transport.clone(),
             ))
         };
 
         match remove_res {
-            true => {
-                self.transport_forwarders
-                    .remove(out.transport.clone())
-                    .await;
-                self.forwarding_listeners
-                    .remove(r#in.transport.clone(), &out.authority)
-                    .await;
-                Ok(())
-            }
-            false => Err(UStatus::fail_with_code(UCode::NOT_FOUND, "not found")),
-        }
-    }
-}
-
-#[derive(Clone)]
+            Some((rule_id, out_endpoints, in_retained_values)) => {
+                // unregister first, so that no listener is left sending on a lane we've closed
+                //
+                // the listeners are those of the authorities the rule was added with, which `out`
+                // needn't list the same way
+                for out_authority in &out_endpoints[0].authorities {
+                    self.forwarding_listeners
+                        .remove(r#in.transport.clone(), rule_id, out_authority)
+                        .await;
+                }
+                if let Some(retained_values) = &in_retained_values {
+                    self.forwarding_listeners
+                        .remove_retaining(r#in.transport.clone(), retained_values)
+                        .await;
+                }
+                for out_endpoint in out_endpoints {
+                    self.transport_forwarders
+                        .remove(out_endpoint.transport.clone(), rule_id)
+                        .await;
+                }
+                Ok(())
+            }
+            None => Err(UStatus::fail_with_code(UCode::NOT_FOUND, "not found")),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct ComparableTransport {
     transport: Arc<dyn UTransport>,
 }
@@ -633,29 +1528,88 @@ impl Eq for ComparableTransport {}
 
 const TRANSPORT_FORWARDER_TAG: &str = "TransportForwarder:";
 const TRANSPORT_FORWARDER_FN_MESSAGE_FORWARDING_LOOP_TAG: &str = "message_forwarding_loop():";
-pub(crate) struct TransportForwarder {}
+
+// the rules sending through a TransportForwarder whose conflation it flushes
+type ConflatingRoutes = Arc<std::sync::Mutex<Vec<Weak<ForwardingRoute>>>>;
+
+pub(crate) struct TransportForwarder {
+    health: Arc<TransportHealth>,
+    source_quotas: Arc<SourceQuotas>,
+    conflating_routes: ConflatingRoutes,
+    fair_queue: FairQueueProducer,
+}
 
 impl TransportForwarder {
-    fn new(out_transport: Arc<dyn UTransport>, message_receiver: Receiver<Arc<UMessage>>) -> Self {
+    fn new(
+        out_transport: Arc<dyn UTransport>,
+        message_queue_size: usize,
+        health: Arc<TransportHealth>,
+        source_quotas: Arc<SourceQuotas>,
+        counters: Arc<StreamerCounters>,
+    ) -> Self {
+        let (fair_queue, message_receiver) = fair_queue(message_queue_size);
         let out_transport_clone = out_transport.clone();
-        let message_receiver_clone = message_receiver.clone();
+        let health_clone = health.clone();
+        let source_quotas_clone = source_quotas.clone();
+        let conflating_routes = ConflatingRoutes::default();
+        let conflating_routes_clone = conflating_routes.clone();
         thread::spawn(|| {
             task::block_on(Self::message_forwarding_loop(
                 UUIDBuilder::build().to_hyphenated_string(),
                 out_transport_clone,
-                message_receiver_clone,
+                message_receiver,
+                health_clone,
+                source_quotas_clone,
+                conflating_routes_clone,
+                counters,
             ))
         });
 
-        Self {}
+        Self {
+            health,
+            source_quotas,
+            conflating_routes,
+            fair_queue,
+        }
+    }
+
+    fn add_lane(&self, rule_id: ForwardingRuleId, scheduling_weight: u32) -> LaneSender {
+        let lane_sender = self
+            .fair_queue
+            .add_lane(&rule_id.to_string(), scheduling_weight);
+        self.source_quotas
+            .set_queue_size(self.fair_queue.capacity());
+        lane_sender
+    }
+
+    fn remove_lane(&self, rule_id: ForwardingRuleId) {
+        for msg in self.fair_queue.remove_lane(&rule_id.to_string()) {
+            self.source_quotas.release(&msg);
+        }
+        self.source_quotas
+            .set_queue_size(self.fair_queue.capacity());
     }
 
     async fn message_forwarding_loop(
         id: String,
         out_transport: Arc<dyn UTransport>,
-        message_receiver: Receiver<Arc<UMessage>>,
+        message_receiver: FairQueueConsumer,
+        health: Arc<TransportHealth>,
+        source_quotas: Arc<SourceQuotas>,
+        conflating_routes: ConflatingRoutes,
+        counters: Arc<StreamerCounters>,
     ) {
-        while let Ok(msg) = message_receiver.recv().await {
+        loop {
+            let next_due = Self::next_conflation_due(&conflating_routes);
+            let Some(received) = message_receiver.recv_until(next_due).await else {
+                break;
+            };
+            let Some(msg) = received else {
+                Self::flush_conflated(&conflating_routes);
+                continue;
+            };
+            source_quotas.release(&msg);
+
             debug!(
                 "{}:{}:{} Attempting send of message: {:?}",
                 id,
@@ -664,8 +1618,19 @@ impl TransportForwarder {
                 msg
             );
 
+            if health.is_paused() {
+                StreamerCounters::increment(&counters.dropped_transport_down);
+                debug!(
+                    "{}:{}:{} out_transport is down, dropping message",
+                    id, TRANSPORT_FORWARDER_TAG, TRANSPORT_FORWARDER_FN_MESSAGE_FORWARDING_LOOP_TAG
+                );
+                continue;
+            }
+
             let send_res = out_transport.send(msg.deref().clone()).await;
             if let Err(err) = send_res {
+                health.record_send_failure();
+                StreamerCounters::increment(&counters.send_failures);
                 warn!(
                     "{}:{}:{} Sending on out_transport failed: {:?}",
                     id,
@@ -674,6 +1639,8 @@ impl TransportForwarder {
                     err
                 );
             } else {
+                health.record_send_success();
+                StreamerCounters::increment(&counters.messages_sent);
                 debug!(
                     "{}:{}:{} Sending on out_transport succeeded",
                     id, TRANSPORT_FORWARDER_TAG, TRANSPORT_FORWARDER_FN_MESSAGE_FORWARDING_LOOP_TAG
@@ -681,315 +1648,3357 @@ impl TransportForwarder {
             }
         }
     }
-}
 
-const FORWARDING_LISTENER_TAG: &str = "ForwardingListener:";
-const FORWARDING_LISTENER_FN_ON_RECEIVE_TAG: &str = "on_receive():";
-const FORWARDING_LISTENER_FN_ON_ERROR_TAG: &str = "on_error():";
+    // when the earliest message held back by the conflation of a rule sending through us is due,
+    // forgetting the rules which have since been deleted
+    fn next_conflation_due(conflating_routes: &ConflatingRoutes) -> Option<Instant> {
+        let mut conflating_routes = conflating_routes.lock().unwrap();
+        conflating_routes.retain(|forwarding_route| forwarding_route.strong_count() > 0);
+        conflating_routes
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter_map(|forwarding_route| forwarding_route.conflator.as_ref()?.next_due())
+            .min()
+    }
+
+    // takes the messages held back by conflation which are due and forwards them on tasks of their
+    // own, since they may well have to wait for room in our own queue
+    fn flush_conflated(conflating_routes: &ConflatingRoutes) {
+        let forwarding_routes: Vec<Arc<ForwardingRoute>> = conflating_routes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for forwarding_route in forwarding_routes {
+            let Some(conflator) = &forwarding_route.conflator else {
+                continue;
+            };
+            let due = conflator.take_due();
+            if !due.is_empty() {
+                task::spawn(async move { forwarding_route.forward_conflated(due).await });
+            }
+        }
+    }
+}
 
+// one of the out `UTransport`s a rule may send on, along with the health of its TransportForwarder
 #[derive(Clone)]
-pub(crate) struct ForwardingListener {
+pub(crate) struct ForwardingTarget {
+    endpoint_name: String,
+    transport: Arc<dyn UTransport>,
+    sender: LaneSender,
+    health: Arc<TransportHealth>,
+    source_quotas: Arc<SourceQuotas>,
+    conflating_routes: ConflatingRoutes,
+    max_payload_size: Option<usize>,
+    oversize_policy: OversizePolicy,
+}
+
+const FORWARDING_ROUTE_TAG: &str = "ForwardingRoute:";
+const FORWARDING_ROUTE_FN_FORWARD_TAG: &str = "forward():";
+const FORWARDING_ROUTE_FN_SELECT_TARGET_TAG: &str = "select_target():";
+const FORWARDING_ROUTE_FN_HEALTH_PROBING_TAG: &str = "health_probing():";
+const FORWARDING_ROUTE_FN_FORWARD_CONFLATED_TAG: &str = "forward_conflated():";
+
+// what the messages a WASM filter or message script hands back are held to, as they're no longer
+// the ones the ForwardingListener checked
+pub(crate) struct RewriteChecks {
+    policies: Arc<ListenerPolicies>,
+    in_authorities: Vec<String>,
+    out_authorities: Vec<String>,
+}
+
+// the per-rule part of forwarding, shared by the ForwardingListeners registered for each of the
+// out authorities of the rule
+pub(crate) struct ForwardingRoute {
     forwarding_id: String,
-    sender: Sender<Arc<UMessage>>,
+    targets: Vec<ForwardingTarget>,
+    out_selection: OutSelection,
+    active_target: AtomicUsize,
+    next_target: AtomicUsize,
+    rate_limiter: Option<RateLimiter>,
+    conflator: Option<Conflator>,
+    wasm_filter: Option<WasmFilter>,
+    script: Option<MessageScript>,
+    content_filter: Option<ContentFilter>,
+    payload_translation: Option<PayloadTranslation>,
+    compression: Option<CompressionConfig>,
+    protection: Option<PayloadProtection>,
+    protection_requirement: Option<ProtectionRequirement>,
+    deadline_exceeded_responses: bool,
+    response_cache: Option<Arc<ResponseCache>>,
+    rewrite_checks: RewriteChecks,
+    out_endpoint_names: Vec<String>,
+    counters: Arc<StreamerCounters>,
 }
 
-impl ForwardingListener {
-    pub(crate) fn new(forwarding_id: &str, sender: Sender<Arc<UMessage>>) -> Self {
-        Self {
+impl ForwardingRoute {
+    pub(crate) fn new(
+        forwarding_id: &str,
+        targets: Vec<ForwardingTarget>,
+        options: ForwardingRuleOptions,
+        rate_limiter: Option<RateLimiter>,
+        conflator: Option<Conflator>,
+        rewrite_checks: RewriteChecks,
+        counters: Arc<StreamerCounters>,
+    ) -> Arc<Self> {
+        let out_endpoint_names = targets
+            .iter()
+            .map(|target| target.endpoint_name.clone())
+            .collect();
+        let forwarding_route = Arc::new(Self {
             forwarding_id: forwarding_id.to_string(),
-            sender,
+            targets,
+            out_selection: options.out_selection,
+            active_target: AtomicUsize::new(0),
+            next_target: AtomicUsize::new(0),
+            rate_limiter,
+            conflator,
+            wasm_filter: options.wasm_filter,
+            script: options.script,
+            content_filter: options.content_filter,
+            payload_translation: options.payload_translation,
+            compression: options.compression,
+            protection: options.protection,
+            protection_requirement: options.protection_requirement,
+            deadline_exceeded_responses: options.deadline_exceeded_responses,
+            response_cache: options
+                .response_cache
+                .map(|config| Arc::new(ResponseCache::new(config))),
+            rewrite_checks,
+            out_endpoint_names,
+            counters,
+        });
+
+        if let OutSelection::Failover(FailoverConfig {
+            health_probe: Some(health_probe),
+            health_probe_interval,
+            ..
+        }) = &forwarding_route.out_selection
+        {
+            Self::spawn_health_probing(
+                Arc::downgrade(&forwarding_route),
+                health_probe.clone(),
+                *health_probe_interval,
+            );
+        }
+        // the TransportForwarder of the first out endpoint flushes what conflation held back
+        if let (Some(_), Some(target)) = (
+            &forwarding_route.conflator,
+            forwarding_route.targets.first(),
+        ) {
+            target
+                .conflating_routes
+                .lock()
+                .unwrap()
+                .push(Arc::downgrade(&forwarding_route));
         }
+
+        forwarding_route
     }
-}
 
-#[async_trait]
-impl UListener for ForwardingListener {
-    async fn on_receive(&self, msg: UMessage) {
-        debug!(
-            "{}:{}:{} Received message: {:?}",
-            self.forwarding_id,
-            FORWARDING_LISTENER_TAG,
-            FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
-            &msg
-        );
-        if let Err(e) = self.sender.send(Arc::new(msg)).await {
-            error!(
-                "{}:{}:{} Unable to send message to worker pool: {e:?}",
-                self.forwarding_id, FORWARDING_LISTENER_TAG, FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
-            );
+    // forwards the messages held back by conflation which have come due
+    async fn forward_conflated(&self, due: Vec<UMessage>) {
+        for msg in due {
+            if self
+                .forward_now(msg, &mut DecodedPayloads::default())
+                .await
+                .is_err()
+            {
+                debug!(
+                    "{}:{}:{} Unable to forward conflated message",
+                    self.forwarding_id,
+                    FORWARDING_ROUTE_TAG,
+                    FORWARDING_ROUTE_FN_FORWARD_CONFLATED_TAG
+                );
+            }
         }
     }
 
-    async fn on_error(&self, err: UStatus) {
-        error!(
-            "{}:{}:{} Received error instead of message from UTransport, with error: {err:?}",
-            self.forwarding_id, FORWARDING_LISTENER_TAG, FORWARDING_LISTENER_FN_ON_ERROR_TAG
+    // probes each out endpoint until the rule has been deleted
+    fn spawn_health_probing(
+        forwarding_route: Weak<Self>,
+        health_probe: Arc<dyn HealthProbe>,
+        health_probe_interval: Duration,
+    ) {
+        thread::spawn(move || {
+            task::block_on(async move {
+                loop {
+                    task::sleep(health_probe_interval).await;
+                    let Some(forwarding_route) = forwarding_route.upgrade() else {
+                        break;
+                    };
+                    for target in &forwarding_route.targets {
+                        let probe_res = health_probe
+                            .probe(&target.endpoint_name, target.transport.clone())
+                            .await;
+                        if let Err(err) = &probe_res {
+                            warn!(
+                                "{}:{}:{} Health probe of out endpoint: {} failed: {err:?}",
+                                forwarding_route.forwarding_id,
+                                FORWARDING_ROUTE_TAG,
+                                FORWARDING_ROUTE_FN_HEALTH_PROBING_TAG,
+                                target.endpoint_name
+                            );
+                        }
+                        target.health.record_probe_result(&probe_res);
+                    }
+                }
+            })
+        });
+    }
+
+    fn select_target(&self, msg: &UMessage) -> &ForwardingTarget {
+        match &self.out_selection {
+            // content routing has to look at the payload before it's compressed or protected, so
+            // it's done by select_content_target
+            OutSelection::Single | OutSelection::ContentRouting(_) => &self.targets[0],
+            OutSelection::Failover(failover_config) => self.select_failover_target(failover_config),
+            OutSelection::LoadBalancing(strategy) => {
+                self.select_load_balanced_target(*strategy, msg)
+            }
+        }
+    }
+
+    // the out endpoint of the first content route msg matches, or the primary if none, for rules
+    // routing by content
+    fn select_content_target(
+        &self,
+        msg: &UMessage,
+        decoded_payloads: &mut DecodedPayloads,
+    ) -> Option<&ForwardingTarget> {
+        let OutSelection::ContentRouting(content_filters) = &self.out_selection else {
+            return None;
+        };
+        let selected = content_filters
+            .iter()
+            .position(|content_filter| content_filter.matches(msg, decoded_payloads))
+            .map_or(0, |index| index + 1);
+        trace!(
+            "{}:{}:{} Routing by content onto out endpoint: {}",
+            self.forwarding_id,
+            FORWARDING_ROUTE_TAG,
+            FORWARDING_ROUTE_FN_SELECT_TARGET_TAG,
+            self.targets[selected].endpoint_name
         );
+        Some(&self.targets[selected])
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{Endpoint, UStreamer};
-    use async_trait::async_trait;
-    use std::sync::Arc;
-    use up_rust::{UListener, UMessage, UStatus, UTransport, UUri};
+    fn select_load_balanced_target(
+        &self,
+        strategy: LoadBalancingStrategy,
+        msg: &UMessage,
+    ) -> &ForwardingTarget {
+        // if nothing is healthy we may as well go on spreading messages across all of them
+        let mut candidates: Vec<usize> = (0..self.targets.len())
+            .filter(|index| !self.targets[*index].health.is_paused())
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..self.targets.len()).collect();
+        }
 
-    pub struct UPClientFoo;
+        let selected = match strategy {
+            LoadBalancingStrategy::RoundRobin => {
+                candidates[self.next_target.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            LoadBalancingStrategy::LeastQueueDepth => candidates
+                .iter()
+                .copied()
+                .min_by_key(|index| self.targets[*index].sender.len())
+                .unwrap_or(0),
+            LoadBalancingStrategy::SourceHash => {
+                let mut hasher = DefaultHasher::new();
+                if let Some(source) = msg
+                    .attributes
+                    .as_ref()
+                    .and_then(|attributes| attributes.source.as_ref())
+                {
+                    source.authority_name.hash(&mut hasher);
+                    source.ue_id.hash(&mut hasher);
+                    source.ue_version_major.hash(&mut hasher);
+                    source.resource_id.hash(&mut hasher);
+                }
+                candidates[(hasher.finish() % candidates.len() as u64) as usize]
+            }
+        };
+        trace!(
+            "{}:{}:{} Load balancing ({strategy:?}) onto out endpoint: {}",
+            self.forwarding_id,
+            FORWARDING_ROUTE_TAG,
+            FORWARDING_ROUTE_FN_SELECT_TARGET_TAG,
+            self.targets[selected].endpoint_name
+        );
+        &self.targets[selected]
+    }
 
-    #[async_trait]
-    impl UTransport for UPClientFoo {
-        async fn send(&self, _message: UMessage) -> Result<(), UStatus> {
-            todo!()
+    fn select_failover_target(&self, failover_config: &FailoverConfig) -> &ForwardingTarget {
+        let previous = self.active_target.load(Ordering::SeqCst);
+        // if nothing is healthy we may as well stay where we are
+        let selected = self
+            .targets
+            .iter()
+            .position(|target| {
+                target.health.is_available(
+                    failover_config.consecutive_failures_to_fail_over,
+                    failover_config.retry_interval,
+                )
+            })
+            .unwrap_or(previous);
+        self.active_target.store(selected, Ordering::SeqCst);
+
+        if selected > previous {
+            StreamerCounters::increment(&self.counters.failovers);
+            warn!(
+                "{}:{}:{} Failing over from out endpoint: {} onto out endpoint: {}, consecutive send failures: {}",
+                self.forwarding_id,
+                FORWARDING_ROUTE_TAG,
+                FORWARDING_ROUTE_FN_SELECT_TARGET_TAG,
+                self.targets[previous].endpoint_name,
+                self.targets[selected].endpoint_name,
+                self.targets[previous].health.consecutive_send_failures()
+            );
+        } else if selected < previous {
+            StreamerCounters::increment(&self.counters.failbacks);
+            info!(
+                "{}:{}:{} Failing back from out endpoint: {} onto out endpoint: {}",
+                self.forwarding_id,
+                FORWARDING_ROUTE_TAG,
+                FORWARDING_ROUTE_FN_SELECT_TARGET_TAG,
+                self.targets[previous].endpoint_name,
+                self.targets[selected].endpoint_name
+            );
         }
 
-        async fn receive(
-            &self,
-            _source_filter: &UUri,
-            _sink_filter: Option<&UUri>,
-        ) -> Result<UMessage, UStatus> {
-            todo!()
+        &self.targets[selected]
+    }
+
+    // runs the rule's WASM filter on the message if it has one, None if the filter dropped it
+    fn apply_wasm_filter(&self, msg: UMessage) -> Result<Option<UMessage>, ForwardingRejection> {
+        let Some(wasm_filter) = &self.wasm_filter else {
+            return Ok(Some(msg));
+        };
+        match wasm_filter.run(&msg) {
+            Ok(WasmVerdict::Forward(filtered_msg)) => self.check_rewritten(&msg, filtered_msg),
+            Ok(WasmVerdict::Drop) => {
+                trace!(
+                    "{}:{}:{} Dropping message as the WASM filter asked for",
+                    self.forwarding_id,
+                    FORWARDING_ROUTE_TAG,
+                    FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.wasm_filtered);
+                Ok(None)
+            }
+            Ok(WasmVerdict::Reject) => {
+                debug!(
+                    "{}:{}:{} Rejecting message as the WASM filter asked for",
+                    self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.wasm_filtered);
+                Err(ForwardingRejection {
+                    code: UCode::PERMISSION_DENIED,
+                    msg,
+                })
+            }
+            Err(reason) => {
+                warn!(
+                    "{}:{}:{} WASM filter failed: {reason}, dropping",
+                    self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.wasm_filter_failures);
+                Err(ForwardingRejection {
+                    code: UCode::INTERNAL,
+                    msg,
+                })
+            }
         }
+    }
 
-        async fn register_listener(
-            &self,
-            source_filter: &UUri,
-            _sink_filter: Option<&UUri>,
-            _listener: Arc<dyn UListener>,
-        ) -> Result<(), UStatus> {
-            println!(
-                "UPClientFoo: registering source_filter: {:?}",
-                source_filter
+    // holds `rewritten_msg` to the same authorities and access policy as `msg`, which it was made
+    // from, so that a filter or script can't send it anywhere the rule doesn't forward to, None if
+    // it's to be dropped
+    fn check_rewritten(
+        &self,
+        msg: &UMessage,
+        rewritten_msg: UMessage,
+    ) -> Result<Option<UMessage>, ForwardingRejection> {
+        let checks = &self.rewrite_checks;
+        let attributes = rewritten_msg.attributes.get_or_default();
+        let source_authority = attributes
+            .source
+            .as_ref()
+            .map_or("", |source| source.authority_name.as_str());
+        if checks
+            .policies
+            .strict_source_authority
+            .load(Ordering::Relaxed)
+            && !checks
+                .in_authorities
+                .iter()
+                .any(|in_authority| authority_matches(in_authority, source_authority))
+        {
+            warn!(
+                "{}:{}:{} Rewritten source authority: {source_authority:?} is not one of the in authorities: {:?}, dropping",
+                self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG, checks.in_authorities
             );
-            Ok(())
+            StreamerCounters::increment(&self.counters.source_authority_mismatches);
+            return Ok(None);
         }
-
-        async fn unregister_listener(
-            &self,
-            source_filter: &UUri,
-            _sink_filter: Option<&UUri>,
-            _listener: Arc<dyn UListener>,
-        ) -> Result<(), UStatus> {
-            println!(
-                "UPClientFoo: unregistering source_filter: {:?}",
-                source_filter
+        let sink_authority = attributes
+            .sink
+            .as_ref()
+            .map_or("", |sink| sink.authority_name.as_str());
+        if !sink_authority.is_empty()
+            && !checks
+                .out_authorities
+                .iter()
+                .any(|out_authority| authority_matches(out_authority, sink_authority))
+        {
+            warn!(
+                "{}:{}:{} Rewritten sink authority: {sink_authority:?} is not one of the out authorities: {:?}, dropping",
+                self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG, checks.out_authorities
             );
-            Ok(())
+            StreamerCounters::increment(&self.counters.rewritten_sink_mismatches);
+            return Ok(None);
+        }
+        let access_decision = checks
+            .policies
+            .access_policy
+            .read()
+            .unwrap()
+            .decide(&rewritten_msg);
+        if access_decision == AccessDecision::Deny {
+            info!(
+                "{}:{}:{} Access policy denies forwarding rewritten message, dropping",
+                self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+            );
+            StreamerCounters::increment(&self.counters.access_denied);
+            return Err(ForwardingRejection {
+                code: UCode::PERMISSION_DENIED,
+                msg: msg.clone(),
+            });
         }
+        Ok(Some(rewritten_msg))
     }
 
-    pub struct UPClientBar;
-
-    #[async_trait]
-    impl UTransport for UPClientBar {
-        async fn send(&self, _message: UMessage) -> Result<(), UStatus> {
-            todo!()
+    // converts the payload between protobuf and JSON if the rule asks for it, or returns why that
+    // failed
+    fn translate(&self, msg: UMessage) -> Result<UMessage, ForwardingRejection> {
+        let Some(payload_translation) = &self.payload_translation else {
+            return Ok(msg);
+        };
+        match payload_translation.translate(&msg) {
+            Ok(Some(translated_msg)) => {
+                StreamerCounters::increment(&self.counters.translated_messages);
+                Ok(translated_msg)
+            }
+            Ok(None) => Ok(msg),
+            Err(reason) => {
+                warn!(
+                    "{}:{}:{} Unable to translate payload: {reason}, dropping",
+                    self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.translation_failures);
+                Err(ForwardingRejection {
+                    code: UCode::INVALID_ARGUMENT,
+                    msg,
+                })
+            }
         }
+    }
 
-        async fn receive(
-            &self,
-            _source_filter: &UUri,
-            _sink_filter: Option<&UUri>,
-        ) -> Result<UMessage, UStatus> {
-            todo!()
+    // compresses the message if the rule asks for it and it's worth it
+    fn compress(&self, msg: UMessage) -> UMessage {
+        let Some(compression_config) = &self.compression else {
+            return msg;
+        };
+        match compression::compress(&msg, compression_config) {
+            Some(compressed_msg) => {
+                StreamerCounters::increment(&self.counters.compressed_messages);
+                compressed_msg
+            }
+            None => msg,
         }
+    }
 
-        async fn register_listener(
-            &self,
-            source_filter: &UUri,
-            _sink_filter: Option<&UUri>,
-            _listener: Arc<dyn UListener>,
-        ) -> Result<(), UStatus> {
-            println!(
-                "UPClientBar: registering source_filter: {:?}",
-                source_filter
-            );
-            Ok(())
+    // signs and/or encrypts the message if the rule asks for it, None if that failed
+    fn protect(&self, msg: UMessage) -> Option<UMessage> {
+        let Some(protection) = &self.protection else {
+            return Some(msg);
+        };
+        match payload_protection::protect(&msg, protection) {
+            Ok(protected_msg) => {
+                StreamerCounters::increment(&self.counters.protected_messages);
+                Some(protected_msg)
+            }
+            Err(reason) => {
+                error!(
+                    "{}:{}:{} Unable to protect message: {reason}, dropping",
+                    self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.protection_failures);
+                None
+            }
         }
+    }
 
-        async fn unregister_listener(
-            &self,
-            source_filter: &UUri,
-            _sink_filter: Option<&UUri>,
-            _listener: Arc<dyn UListener>,
-        ) -> Result<(), UStatus> {
-            println!(
-                "UPClientBar: unregistering source_filter: {:?}",
-                source_filter
-            );
-            Ok(())
-        }
+    /// The names of the out [`Endpoint`]s messages may be forwarded on
+    pub(crate) fn out_endpoint_names(&self) -> &[String] {
+        &self.out_endpoint_names
     }
 
-    #[async_std::test]
-    async fn test_simple_with_a_single_input_and_output_endpoint() {
-        // Local endpoint
-        let local_authority = "local";
-        let local_transport: Arc<dyn UTransport> = Arc::new(UPClientFoo);
-        let local_endpoint =
-            Endpoint::new("local_endpoint", local_authority, local_transport.clone());
+    /// Whether requests forwarded on this route are answered with DEADLINE_EXCEEDED once their TTL
+    /// runs out without a response
+    pub(crate) fn sends_deadline_exceeded_responses(&self) -> bool {
+        self.deadline_exceeded_responses
+    }
 
-        // A remote endpoint
-        let remote_authority = "remote";
-        let remote_transport: Arc<dyn UTransport> = Arc::new(UPClientBar);
-        let remote_endpoint = Endpoint::new(
-            "remote_endpoint",
-            remote_authority,
-            remote_transport.clone(),
-        );
+    /// Where the responses to requests forwarded on this route are kept, if anywhere
+    pub(crate) fn response_cache(&self) -> Option<Arc<ResponseCache>> {
+        self.response_cache.clone()
+    }
 
-        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
-        // Add forwarding rules to endpoint local<->remote
-        assert!(ustreamer
-            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
-            .await
-            .is_ok());
-        assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
-            .await
-            .is_ok());
+    /// How messages arriving for this route must have been protected by the peer [`UStreamer`],
+    /// if at all
+    pub(crate) fn protection_requirement(&self) -> Option<&ProtectionRequirement> {
+        self.protection_requirement.as_ref()
+    }
 
-        // Add forwarding rules to endpoint local<->local, should report an error
-        assert!(ustreamer
-            .add_forwarding_rule(local_endpoint.clone(), local_endpoint.clone())
-            .await
-            .is_err());
+    // holds the message back or tells us to drop it, according to the rule's rate limit
+    async fn admit(&self, msg: &UMessage) -> bool {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return true;
+        };
+        let size = msg.payload.as_ref().map_or(0, |payload| payload.len());
 
-        // Rule already exists so it should report an error
-        assert!(ustreamer
-            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
-            .await
-            .is_err());
+        let mut delayed = false;
+        loop {
+            let Err(wait) = rate_limiter.try_acquire(size) else {
+                return true;
+            };
+            match rate_limiter.policy() {
+                RateLimitPolicy::Drop => {
+                    debug!(
+                        "{}:{}:{} Dropping message exceeding the rate limit",
+                        self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+                    );
+                    StreamerCounters::increment(&self.counters.rate_limited_dropped);
+                    return false;
+                }
+                RateLimitPolicy::Delay => {
+                    if !delayed {
+                        delayed = true;
+                        StreamerCounters::increment(&self.counters.rate_limited_delayed);
+                    }
+                    task::sleep(wait).await;
+                }
+            }
+        }
+    }
+
+    /// Hands `msg` to the TransportForwarder of the selected out endpoint, or returns why it was
+    /// refused if its sender should be told
+    pub(crate) async fn forward(&self, msg: UMessage) -> Result<(), ForwardingRejection> {
+        let Some(msg) = self.apply_wasm_filter(msg)? else {
+            return Ok(());
+        };
+        let Some(script) = &self.script else {
+            return self.filter_and_forward(msg).await;
+        };
+        let scripted_msgs = match script.run(&msg) {
+            Ok(scripted_msgs) => scripted_msgs,
+            Err(reason) => {
+                warn!(
+                    "{}:{}:{} Message script failed: {reason}, dropping",
+                    self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.script_failures);
+                return Err(ForwardingRejection {
+                    code: UCode::INTERNAL,
+                    msg,
+                });
+            }
+        };
+        if scripted_msgs.is_empty() {
+            trace!(
+                "{}:{}:{} Dropping message as the message script asked for",
+                self.forwarding_id,
+                FORWARDING_ROUTE_TAG,
+                FORWARDING_ROUTE_FN_FORWARD_TAG,
+            );
+            StreamerCounters::increment(&self.counters.script_dropped);
+            return Ok(());
+        }
+        // the first refusal is the one the sender is told about
+        let mut forwarding_res = Ok(());
+        for scripted_msg in scripted_msgs {
+            let res = match self.check_rewritten(&msg, scripted_msg) {
+                Ok(Some(scripted_msg)) => self.filter_and_forward(scripted_msg).await,
+                Ok(None) => Ok(()),
+                Err(rejection) => Err(rejection),
+            };
+            if forwarding_res.is_ok() {
+                forwarding_res = res;
+            }
+        }
+        forwarding_res
+    }
+
+    // everything past the message script
+    async fn filter_and_forward(&self, msg: UMessage) -> Result<(), ForwardingRejection> {
+        // the content filter and content routing look into the same payload
+        let mut decoded_payloads = DecodedPayloads::default();
+        if let Some(content_filter) = &self.content_filter {
+            if !content_filter.matches(&msg, &mut decoded_payloads) {
+                debug!(
+                    "{}:{}:{} Dropping message whose payload doesn't match the content filter",
+                    self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.content_filtered);
+                return Err(ForwardingRejection {
+                    code: UCode::PERMISSION_DENIED,
+                    msg,
+                });
+            }
+        }
+        let Some(conflator) = &self.conflator else {
+            return self.forward_now(msg, &mut decoded_payloads).await;
+        };
+        match conflator.offer(msg) {
+            Conflation::Forward(msg) => self.forward_now(msg, &mut decoded_payloads).await,
+            Conflation::Held => {
+                // it may be due before whatever the TransportForwarder is waiting for
+                if let Some(target) = self.targets.first() {
+                    target.sender.wake();
+                }
+                Ok(())
+            }
+            Conflation::Superseded => {
+                trace!(
+                    "{}:{}:{} Conflating message with the one held back before it",
+                    self.forwarding_id,
+                    FORWARDING_ROUTE_TAG,
+                    FORWARDING_ROUTE_FN_FORWARD_TAG,
+                );
+                StreamerCounters::increment(&self.counters.conflated_messages);
+                Ok(())
+            }
+        }
+    }
+
+    // everything past conflation, which messages held back by it go through once they're due
+    async fn forward_now(
+        &self,
+        msg: UMessage,
+        decoded_payloads: &mut DecodedPayloads,
+    ) -> Result<(), ForwardingRejection> {
+        let content_target = self.select_content_target(&msg, decoded_payloads);
+        let msg = self.translate(msg)?;
+        let msg = self.compress(msg);
+        let Some(msg) = self.protect(msg) else {
+            return Ok(());
+        };
+        if !self.admit(&msg).await {
+            return Ok(());
+        }
+        let target = content_target.unwrap_or_else(|| self.select_target(&msg));
+        let payload_size = msg.payload.as_ref().map_or(0, |payload| payload.len());
+        if let Some(max_payload_size) = target
+            .max_payload_size
+            .filter(|max_payload_size| payload_size > *max_payload_size)
+        {
+            match target.oversize_policy {
+                OversizePolicy::Reject => {
+                    warn!(
+                        "{}:{}:{} Payload of {payload_size} bytes exceeds the max_payload_size: {max_payload_size} of out endpoint: {}, dropping",
+                        self.forwarding_id,
+                        FORWARDING_ROUTE_TAG,
+                        FORWARDING_ROUTE_FN_FORWARD_TAG,
+                        target.endpoint_name,
+                    );
+                    StreamerCounters::increment(&self.counters.oversize_rejected);
+                    return Err(ForwardingRejection {
+                        code: UCode::RESOURCE_EXHAUSTED,
+                        msg,
+                    });
+                }
+                OversizePolicy::Fragment => {
+                    let Some(fragments) = fragmentation::fragment(&msg, max_payload_size) else {
+                        warn!(
+                            "{}:{}:{} Payload of {payload_size} bytes can't be fragmented to fit the max_payload_size: {max_payload_size} of out endpoint: {}, dropping",
+                            self.forwarding_id,
+                            FORWARDING_ROUTE_TAG,
+                            FORWARDING_ROUTE_FN_FORWARD_TAG,
+                            target.endpoint_name,
+                        );
+                        StreamerCounters::increment(&self.counters.oversize_rejected);
+                        return Err(ForwardingRejection {
+                            code: UCode::RESOURCE_EXHAUSTED,
+                            msg,
+                        });
+                    };
+                    debug!(
+                        "{}:{}:{} Splitting payload of {payload_size} bytes into {} fragments for out endpoint: {}",
+                        self.forwarding_id,
+                        FORWARDING_ROUTE_TAG,
+                        FORWARDING_ROUTE_FN_FORWARD_TAG,
+                        fragments.len(),
+                        target.endpoint_name,
+                    );
+                    StreamerCounters::increment(&self.counters.fragmented_messages);
+                    for fragment in fragments {
+                        self.enqueue(target, fragment).await;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        self.enqueue(target, msg).await;
+        Ok(())
+    }
+
+    // queues msg for the target's TransportForwarder, unless its source is over quota
+    async fn enqueue(&self, target: &ForwardingTarget, msg: UMessage) {
+        if let Err(quota_exceeded) = target.source_quotas.try_admit(&msg) {
+            debug!(
+                "{}:{}:{} Dropping message over its source's {quota_exceeded:?} quota on out endpoint: {}",
+                self.forwarding_id,
+                FORWARDING_ROUTE_TAG,
+                FORWARDING_ROUTE_FN_FORWARD_TAG,
+                target.endpoint_name,
+            );
+            StreamerCounters::increment(&self.counters.quota_dropped);
+            return;
+        }
+        let msg = Arc::new(msg);
+        if let Err(e) = target.sender.send(msg.clone()).await {
+            target.source_quotas.release(&msg);
+            error!(
+                "{}:{}:{} Unable to send message to worker pool: {e:?}",
+                self.forwarding_id, FORWARDING_ROUTE_TAG, FORWARDING_ROUTE_FN_FORWARD_TAG,
+            );
+        }
+    }
+}
+
+// a message a ForwardingRoute refused to forward, along with the code to answer it with if it's
+// a request
+pub(crate) struct ForwardingRejection {
+    code: UCode,
+    msg: UMessage,
+}
+
+const FORWARDING_LISTENER_TAG: &str = "ForwardingListener:";
+const FORWARDING_LISTENER_FN_ON_RECEIVE_TAG: &str = "on_receive():";
+const FORWARDING_LISTENER_FN_ON_ERROR_TAG: &str = "on_error():";
+const FORWARDING_LISTENER_FN_REPLAY_RETAINED_VALUES_TAG: &str = "replay_retained_values():";
+
+#[derive(Clone)]
+pub(crate) struct ForwardingListener {
+    rule_id: ForwardingRuleId,
+    forwarding_id: String,
+    out_authority: String,
+    forwarding_route: Arc<ForwardingRoute>,
+    // we're registered on the in `UTransport`, so we mustn't keep it alive ourselves
+    in_transport: Weak<dyn UTransport>,
+    in_endpoint_name: String,
+    in_authorities: Vec<String>,
+    in_replay_guard: Option<Arc<ReplayGuard>>,
+    in_retained_values: Option<Arc<RetainedValues>>,
+    in_health: Arc<TransportHealth>,
+    policies: Arc<ListenerPolicies>,
+    // the listeners we're one of, e.g. to bring newly subscribed authorities up to date through
+    // the others
+    forwarding_listeners: Weak<ForwardingListeners>,
+    counters: Arc<StreamerCounters>,
+}
+
+impl ForwardingListener {
+    fn new(
+        rule_id: ForwardingRuleId,
+        forwarding_id: &str,
+        out_authority: &str,
+        forwarding_route: Arc<ForwardingRoute>,
+        in_endpoint: &Endpoint,
+        in_health: Arc<TransportHealth>,
+        forwarding_listeners: &Arc<ForwardingListeners>,
+    ) -> Self {
+        Self {
+            rule_id,
+            forwarding_id: forwarding_id.to_string(),
+            out_authority: out_authority.to_string(),
+            forwarding_route,
+            in_transport: Arc::downgrade(&in_endpoint.transport),
+            in_endpoint_name: in_endpoint.name.clone(),
+            in_authorities: in_endpoint.authorities.clone(),
+            in_replay_guard: in_endpoint.replay_guard.clone(),
+            in_retained_values: in_endpoint.retained_values.clone(),
+            in_health,
+            policies: forwarding_listeners.policies.clone(),
+            forwarding_listeners: Arc::downgrade(forwarding_listeners),
+            counters: forwarding_listeners.counters.clone(),
+        }
+    }
+
+    // forwards the values retained on our in endpoint which may go to any of `out_authorities`,
+    // through the same checks as if they had just arrived
+    pub(crate) async fn replay_retained_values(&self, out_authorities: &[String]) {
+        let Some(retained_values) = &self.in_retained_values else {
+            return;
+        };
+        let replayed = retained_values.replay_for(out_authorities);
+        debug!(
+            "{}:{}:{} Replaying {} retained values for {:?}",
+            self.forwarding_id,
+            FORWARDING_LISTENER_TAG,
+            FORWARDING_LISTENER_FN_REPLAY_RETAINED_VALUES_TAG,
+            replayed.len(),
+            out_authorities
+        );
+        for (msg, retained_for) in replayed {
+            let Some(mut msg) = self.check_in(msg).await else {
+                continue;
+            };
+            retained_values::restamp(&mut msg, retained_for);
+            if self.forwarding_route.forward(msg).await.is_ok() {
+                StreamerCounters::increment(&self.counters.retained_values_replayed);
+            }
+        }
+    }
+
+    // a message must come from one of the authorities of the in endpoint it arrived on, lest a
+    // node on one network impersonate an authority on another
+    fn is_from_in_authority(&self, msg: &UMessage) -> bool {
+        let Some(source) = msg
+            .attributes
+            .as_ref()
+            .and_then(|attributes| attributes.source.as_ref())
+        else {
+            return false;
+        };
+        self.in_authorities
+            .iter()
+            .any(|in_authority| authority_matches(in_authority, &source.authority_name))
+    }
+
+    // answers a retransmitted request from the response cache, or drops it while the first
+    // transmission still awaits its response, true if it was taken care of
+    async fn answer_retransmission(&self, msg: &UMessage) -> bool {
+        let Some(attributes) = msg.attributes.as_ref() else {
+            return false;
+        };
+        if attributes.type_.enum_value_or_default() != UMessageType::UMESSAGE_TYPE_REQUEST {
+            return false;
+        }
+        let Some(response_cache) = self.forwarding_route.response_cache() else {
+            return false;
+        };
+        if let Some((response, response_route)) = response_cache.get(attributes) {
+            self.send_cached_response(attributes, response, response_route)
+                .await;
+            return true;
+        }
+        if self.policies.request_tracker.is_outstanding(&attributes.id) {
+            debug!(
+                "{}:{}:{} Dropping retransmission of request: {} still awaiting its response",
+                self.forwarding_id,
+                FORWARDING_LISTENER_TAG,
+                FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                attributes.id.to_hyphenated_string()
+            );
+            StreamerCounters::increment(&self.counters.duplicate_requests_dropped);
+            return true;
+        }
+        false
+    }
+
+    // correlates the requests and responses we forward, false if the message is to be dropped
+    async fn track_rpc(&self, msg: &UMessage) -> bool {
+        let Some(attributes) = msg.attributes.as_ref() else {
+            return true;
+        };
+        let request_tracker = &self.policies.request_tracker;
+        match attributes.type_.enum_value_or_default() {
+            UMessageType::UMESSAGE_TYPE_REQUEST => {
+                let given_up = request_tracker.track_request(
+                    msg,
+                    RequestRoute {
+                        in_endpoint: self.in_endpoint_name.clone(),
+                        out_endpoints: self.forwarding_route.out_endpoint_names().to_vec(),
+                        deadline_reply_transport: self
+                            .forwarding_route
+                            .sends_deadline_exceeded_responses()
+                            .then(|| self.in_transport.clone()),
+                        response_cache: self.forwarding_route.response_cache(),
+                    },
+                );
+                if let Some(forwarding_listeners) = self.forwarding_listeners.upgrade() {
+                    forwarding_listeners.start_request_expiry();
+                    forwarding_listeners.give_up_on_requests(given_up).await;
+                }
+                true
+            }
+            UMessageType::UMESSAGE_TYPE_RESPONSE => {
+                let response_match = request_tracker.match_response(
+                    msg,
+                    &self.in_endpoint_name,
+                    self.forwarding_route.out_endpoint_names(),
+                    Arc::downgrade(&self.forwarding_route),
+                );
+                let counter = match response_match {
+                    ResponseMatch::Matched => return true,
+                    ResponseMatch::Orphaned => &self.counters.orphaned_responses,
+                    ResponseMatch::Misrouted => &self.counters.misrouted_responses,
+                };
+                warn!(
+                    "{}:{}:{} {response_match:?} response to request: {} arrived on in endpoint: {}",
+                    self.forwarding_id,
+                    FORWARDING_LISTENER_TAG,
+                    FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                    attributes.reqid.to_hyphenated_string(),
+                    self.in_endpoint_name
+                );
+                StreamerCounters::increment(counter);
+                !request_tracker.config().drop_unmatched_responses
+            }
+            _ => true,
+        }
+    }
+
+    // answers a retransmitted request with the response its first transmission got, forwarded
+    // the same way that response was
+    async fn send_cached_response(
+        &self,
+        request_attributes: &UAttributes,
+        response: UMessage,
+        response_route: Weak<ForwardingRoute>,
+    ) {
+        let Some(response_route) = response_route.upgrade() else {
+            debug!(
+                "{}:{}:{} The rule the cached response was forwarded by is gone, dropping retransmitted request: {}",
+                self.forwarding_id,
+                FORWARDING_LISTENER_TAG,
+                FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                request_attributes.id.to_hyphenated_string()
+            );
+            return;
+        };
+        if self
+            .policies
+            .access_policy
+            .read()
+            .unwrap()
+            .decide(&response)
+            == AccessDecision::Deny
+        {
+            StreamerCounters::increment(&self.counters.access_denied);
+            return;
+        }
+        debug!(
+            "{}:{}:{} Answering retransmitted request: {} from the response cache",
+            self.forwarding_id,
+            FORWARDING_LISTENER_TAG,
+            FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+            request_attributes.id.to_hyphenated_string()
+        );
+        match response_route.forward(response).await {
+            Ok(()) => StreamerCounters::increment(&self.counters.cached_responses_sent),
+            Err(_) => warn!(
+                "{}:{}:{} Unable to forward cached response",
+                self.forwarding_id, FORWARDING_LISTENER_TAG, FORWARDING_LISTENER_FN_ON_RECEIVE_TAG
+            ),
+        }
+    }
+
+    // lets the sender of a request we won't forward know why, rather than leaving it to time out
+    async fn reject_request(&self, msg: &UMessage, code: UCode) {
+        let Some(attributes) = msg.attributes.as_ref() else {
+            return;
+        };
+        if attributes.type_.enum_value_or_default() != UMessageType::UMESSAGE_TYPE_REQUEST {
+            return;
+        }
+        let Some(in_transport) = self.in_transport.upgrade() else {
+            return;
+        };
+
+        let response = match UMessageBuilder::response_for_request(attributes)
+            .with_comm_status(code)
+            .build()
+        {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(
+                    "{}:{}:{} Unable to build {code:?} response: {err:?}",
+                    self.forwarding_id,
+                    FORWARDING_LISTENER_TAG,
+                    FORWARDING_LISTENER_FN_ON_RECEIVE_TAG
+                );
+                return;
+            }
+        };
+        if let Err(err) = in_transport.send(response).await {
+            warn!(
+                "{}:{}:{} Unable to send {code:?} response: {err:?}",
+                self.forwarding_id, FORWARDING_LISTENER_TAG, FORWARDING_LISTENER_FN_ON_RECEIVE_TAG
+            );
+        }
+    }
+
+    // unwraps what the peer streamer did to `msg` and applies the checks of the forwarding rule
+    // and the UStreamer, None if it's to be dropped
+    async fn check_in(&self, msg: UMessage) -> Option<UMessage> {
+        let msg = if payload_protection::is_protected(&msg) {
+            let protection_keys = self.policies.protection_keys.read().unwrap().clone();
+            match payload_protection::unprotect(
+                msg,
+                &protection_keys,
+                self.forwarding_route.protection_requirement(),
+            ) {
+                Ok(msg) => msg,
+                Err(reason) => {
+                    warn!(
+                        "{}:{}:{} Unable to verify protected message: {reason}, dropping",
+                        self.forwarding_id,
+                        FORWARDING_LISTENER_TAG,
+                        FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                    );
+                    StreamerCounters::increment(&self.counters.protection_failures);
+                    return None;
+                }
+            }
+        } else if self.forwarding_route.protection_requirement().is_some() {
+            warn!(
+                "{}:{}:{} Message is not protected but the forwarding rule requires it, dropping",
+                self.forwarding_id, FORWARDING_LISTENER_TAG, FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+            );
+            StreamerCounters::increment(&self.counters.protection_failures);
+            return None;
+        } else {
+            msg
+        };
+        let msg = if compression::is_compressed(&msg) {
+            match compression::decompress(msg, self.policies.reassembler.max_message_size()) {
+                Ok(msg) => {
+                    StreamerCounters::increment(&self.counters.decompressed_messages);
+                    msg
+                }
+                Err(reason) => {
+                    warn!(
+                        "{}:{}:{} Unable to decompress message: {reason}, dropping",
+                        self.forwarding_id,
+                        FORWARDING_LISTENER_TAG,
+                        FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                    );
+                    StreamerCounters::increment(&self.counters.decompression_failures);
+                    return None;
+                }
+            }
+        } else {
+            msg
+        };
+        if self
+            .policies
+            .strict_source_authority
+            .load(Ordering::Relaxed)
+            && !self.is_from_in_authority(&msg)
+        {
+            warn!(
+                "{}:{}:{} Source authority: {:?} is not one of the in authorities: {:?}, dropping",
+                self.forwarding_id,
+                FORWARDING_LISTENER_TAG,
+                FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                msg.attributes
+                    .as_ref()
+                    .and_then(|attributes| attributes.source.as_ref())
+                    .map(|source| source.authority_name.as_str()),
+                self.in_authorities
+            );
+            StreamerCounters::increment(&self.counters.source_authority_mismatches);
+            return None;
+        }
+        let access_decision = self.policies.access_policy.read().unwrap().decide(&msg);
+        if access_decision == AccessDecision::Deny {
+            info!(
+                "{}:{}:{} Access policy denies forwarding message: {}, dropping",
+                self.forwarding_id,
+                FORWARDING_LISTENER_TAG,
+                FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                msg.attributes
+                    .as_ref()
+                    .map(|attributes| attributes.id.to_hyphenated_string())
+                    .unwrap_or_default()
+            );
+            StreamerCounters::increment(&self.counters.access_denied);
+            self.reject_request(&msg, UCode::PERMISSION_DENIED).await;
+            return None;
+        }
+        Some(msg)
+    }
+
+    fn is_for_out_authority(&self, msg: &UMessage) -> bool {
+        let Some(sink) = msg
+            .attributes
+            .as_ref()
+            .and_then(|attributes| attributes.sink.as_ref())
+        else {
+            return false;
+        };
+        if !authority_matches(&self.out_authority, &sink.authority_name) {
+            return false;
+        }
+        // the rule of a pattern leaves the authorities it shares with more specific rules to them
+        if !is_authority_pattern(&self.out_authority) {
+            return true;
+        }
+        let (Some(forwarding_listeners), Some(in_transport)) = (
+            self.forwarding_listeners.upgrade(),
+            self.in_transport.upgrade(),
+        ) else {
+            return true;
+        };
+        forwarding_listeners.is_most_specific_out_authority(
+            &ComparableTransport::new(in_transport),
+            &self.out_authority,
+            &sink.authority_name,
+        )
+    }
+}
+
+#[async_trait]
+impl UListener for ForwardingListener {
+    async fn on_receive(&self, msg: UMessage) {
+        debug!(
+            "{}:{}:{} Received message: {:?}",
+            self.forwarding_id,
+            FORWARDING_LISTENER_TAG,
+            FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+            &msg
+        );
+        self.in_health.record_listener_message();
+        if !self.is_for_out_authority(&msg) {
+            debug!(
+                "{}:{}:{} Sink authority does not match out authority: {}, not forwarding",
+                self.forwarding_id,
+                FORWARDING_LISTENER_TAG,
+                FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                self.out_authority
+            );
+            return;
+        }
+        let msg = if fragmentation::is_fragment(&msg) {
+            let (reassembly, given_up) = self.policies.reassembler.accept(self.rule_id, msg);
+            StreamerCounters::add(&self.counters.reassembly_failures, given_up as u64);
+            match reassembly {
+                Reassembly::Complete(msg) => {
+                    StreamerCounters::increment(&self.counters.reassembled_messages);
+                    msg
+                }
+                Reassembly::Pending => return,
+                Reassembly::Dropped(reason) => {
+                    warn!(
+                        "{}:{}:{} Unable to reassemble fragmented message: {reason}, dropping",
+                        self.forwarding_id,
+                        FORWARDING_LISTENER_TAG,
+                        FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                    );
+                    StreamerCounters::increment(&self.counters.reassembly_failures);
+                    return;
+                }
+            }
+        } else {
+            msg
+        };
+        let Some(msg) = self.check_in(msg).await else {
+            return;
+        };
+        // retransmitted requests reuse the id of their first transmission, so they're taken care
+        // of before the replay guard would reject them
+        if self.answer_retransmission(&msg).await {
+            return;
+        }
+        if let Some(replay_guard) = &self.in_replay_guard {
+            if let Err(replay_rejection) = replay_guard.check(self.rule_id, &msg) {
+                warn!(
+                    "{}:{}:{} Rejecting message as a possible replay, {replay_rejection:?}: {}, dropping",
+                    self.forwarding_id,
+                    FORWARDING_LISTENER_TAG,
+                    FORWARDING_LISTENER_FN_ON_RECEIVE_TAG,
+                    msg.attributes
+                        .as_ref()
+                        .map(|attributes| attributes.id.to_hyphenated_string())
+                        .unwrap_or_default()
+                );
+                StreamerCounters::increment(&self.counters.replays_rejected);
+                return;
+            }
+        }
+        if !self.track_rpc(&msg).await {
+            return;
+        }
+        let subscription =
+            retained_values::subscriber_authority(&msg).map(|subscriber_authority| {
+                let subscribe_request_id = msg
+                    .attributes
+                    .as_ref()
+                    .and_then(|attributes| attributes.id.as_ref())
+                    .map(|id| (id.msb, id.lsb));
+                (subscriber_authority.to_string(), subscribe_request_id)
+            });
+        if let Err(rejection) = self.forwarding_route.forward(msg).await {
+            self.policies.request_tracker.forget_request(&rejection.msg);
+            self.reject_request(&rejection.msg, rejection.code).await;
+            return;
+        }
+        if let Some((subscriber_authority, subscribe_request_id)) = subscription {
+            if let Some(forwarding_listeners) = self.forwarding_listeners.upgrade() {
+                forwarding_listeners
+                    .replay_retained_values_to(&subscriber_authority, subscribe_request_id)
+                    .await;
+            }
+        }
+    }
+
+    async fn on_error(&self, err: UStatus) {
+        error!(
+            "{}:{}:{} Received error instead of message from UTransport, with error: {err:?}",
+            self.forwarding_id, FORWARDING_LISTENER_TAG, FORWARDING_LISTENER_FN_ON_ERROR_TAG
+        );
+        self.in_health.record_listener_error();
+    }
+}
+
+// for both notifications and publish messages, which have no sink, a transport still holding
+// either registration is fine
+async fn register_retaining_listener(
+    in_transport: &Arc<dyn UTransport>,
+    retaining_listener: &Arc<RetainingListener>,
+) -> Result<(), UStatus> {
+    for sink in [Some(any_uuri()), None] {
+        match in_transport
+            .register_listener(&any_uuri(), sink.as_ref(), retaining_listener.clone())
+            .await
+        {
+            Err(err) if err.get_code() != UCode::ALREADY_EXISTS => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+async fn unregister_retaining_listener(
+    in_transport: &Arc<dyn UTransport>,
+    retaining_listener: &Arc<RetainingListener>,
+) {
+    for sink in [Some(any_uuri()), None] {
+        if let Err(err) = in_transport
+            .unregister_listener(&any_uuri(), sink.as_ref(), retaining_listener.clone())
+            .await
+        {
+            debug!(
+                "{FORWARDING_LISTENERS_TAG} unable to unregister retaining listener, error: {err}"
+            );
+        }
+    }
+}
+
+// retains the values arriving on an in endpoint, whether or not a rule forwards them to their
+// authority yet, for rules and subscribers joining later
+//
+// values are retained as they arrived, the rule replaying them applies its checks then, fragments
+// aren't retained as they're only a part of a value
+pub(crate) struct RetainingListener {
+    retained_values: Arc<RetainedValues>,
+}
+
+#[async_trait]
+impl UListener for RetainingListener {
+    async fn on_receive(&self, msg: UMessage) {
+        if !fragmentation::is_fragment(&msg) {
+            self.retained_values.retain(&msg);
+        }
+    }
+
+    async fn on_error(&self, _err: UStatus) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::descriptor_registry::tests::{hello_file_descriptor, hello_request};
+    use crate::wasm_filter::tests::{wasm, REWRITING_FILTER};
+    use crate::{
+        AccessDecision, AccessPolicy, AccessRule, CompressionConfig, ConflationConfig,
+        ContentFilter, Endpoint, FailoverConfig, ForwardingRuleOptions, LoadBalancingStrategy,
+        MessageScript, OversizePolicy, PayloadProtection, ProtectionKeys, ProtectionRequirement,
+        RateLimit, RateLimitPolicy, ReplayProtectionConfig, RequestTrackingConfig,
+        ResponseCacheConfig, RetainedValuesConfig, ScriptLimits, Signing, TransportHealthConfig,
+        TransportHealthState, UStreamer, UUriPattern, WasmFilter, WasmFilterLimits,
+    };
+    use async_std::task;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use up_rust::{
+        UAttributes, UCode, UListener, UMessage, UMessageBuilder, UMessageType, UPayloadFormat,
+        UStatus, UTransport, UUIDBuilder, UUri, UUID,
+    };
+
+    // records what is sent on it and lets us deliver messages to its registered listeners
+    #[derive(Default)]
+    pub struct UPClientRecorder {
+        fail_sends: AtomicBool,
+        // like transports which register the same listener as often as they're asked to
+        accept_duplicates: AtomicBool,
+        sent: Mutex<Vec<UMessage>>,
+        listeners: Mutex<Vec<Arc<dyn UListener>>>,
+    }
+
+    impl UPClientRecorder {
+        fn sent_count(&self) -> usize {
+            self.sent.lock().unwrap().len()
+        }
+
+        fn sent(&self) -> Vec<UMessage> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        // forwarding happens on the TransportForwarder's own thread, so we give it a moment to
+        // catch up, returning whether `count` messages were sent in time
+        async fn wait_for_sent(&self, count: usize) -> bool {
+            wait_until(|| self.sent_count() >= count).await
+        }
+
+        fn listener_count(&self) -> usize {
+            self.listeners.lock().unwrap().len()
+        }
+
+        // what a transport losing its session, e.g. on a router restart, looks like to us
+        fn lose_session(&self) {
+            self.listeners.lock().unwrap().clear();
+        }
+
+        async fn deliver(&self, msg: UMessage) {
+            let listeners = self.listeners.lock().unwrap().clone();
+            for listener in listeners {
+                listener.on_receive(msg.clone()).await;
+            }
+        }
+    }
+
+    // polls `condition` for up to 5s, returning whether it came to hold
+    async fn wait_until(condition: impl Fn() -> bool) -> bool {
+        for _ in 0..500 {
+            if condition() {
+                return true;
+            }
+            task::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    // a local and a remote Endpoint, each on a UPClientRecorder of its own
+    fn local_and_remote_endpoints() -> (
+        Arc<UPClientRecorder>,
+        Endpoint,
+        Arc<UPClientRecorder>,
+        Endpoint,
+    ) {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint = Endpoint::new("remote_endpoint", "remote", remote_transport.clone());
+        (
+            local_transport,
+            local_endpoint,
+            remote_transport,
+            remote_endpoint,
+        )
+    }
+
+    #[async_trait]
+    impl UTransport for UPClientRecorder {
+        async fn send(&self, message: UMessage) -> Result<(), UStatus> {
+            if self.fail_sends.load(Ordering::SeqCst) {
+                return Err(UStatus::fail_with_code(UCode::UNAVAILABLE, "link down"));
+            }
+            self.sent.lock().unwrap().push(message);
+            Ok(())
+        }
+
+        async fn receive(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+        ) -> Result<UMessage, UStatus> {
+            todo!()
+        }
+
+        async fn register_listener(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            let mut listeners = self.listeners.lock().unwrap();
+            if !self.accept_duplicates.load(Ordering::SeqCst)
+                && listeners
+                    .iter()
+                    .any(|registered| Arc::ptr_eq(registered, &listener))
+            {
+                return Err(UStatus::fail_with_code(
+                    UCode::ALREADY_EXISTS,
+                    "already registered",
+                ));
+            }
+            listeners.push(listener);
+            Ok(())
+        }
+
+        async fn unregister_listener(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            let mut listeners = self.listeners.lock().unwrap();
+            let Some(position) = listeners
+                .iter()
+                .position(|registered| Arc::ptr_eq(registered, &listener))
+            else {
+                return Err(UStatus::fail_with_code(UCode::NOT_FOUND, "not registered"));
+            };
+            listeners.remove(position);
+            Ok(())
+        }
+    }
+
+    fn message_for_authority(authority_name: &str) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                sink: Some(UUri {
+                    authority_name: authority_name.to_string(),
+                    ue_id: 0x1234,
+                    ue_version_major: 1,
+                    resource_id: 0x8001,
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    pub struct UPClientFoo;
+
+    #[async_trait]
+    impl UTransport for UPClientFoo {
+        async fn send(&self, _message: UMessage) -> Result<(), UStatus> {
+            todo!()
+        }
+
+        async fn receive(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+        ) -> Result<UMessage, UStatus> {
+            todo!()
+        }
+
+        async fn register_listener(
+            &self,
+            source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            _listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            println!(
+                "UPClientFoo: registering source_filter: {:?}",
+                source_filter
+            );
+            Ok(())
+        }
+
+        async fn unregister_listener(
+            &self,
+            source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            _listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            println!(
+                "UPClientFoo: unregistering source_filter: {:?}",
+                source_filter
+            );
+            Ok(())
+        }
+    }
+
+    pub struct UPClientBar;
+
+    #[async_trait]
+    impl UTransport for UPClientBar {
+        async fn send(&self, _message: UMessage) -> Result<(), UStatus> {
+            todo!()
+        }
+
+        async fn receive(
+            &self,
+            _source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+        ) -> Result<UMessage, UStatus> {
+            todo!()
+        }
+
+        async fn register_listener(
+            &self,
+            source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            _listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            println!(
+                "UPClientBar: registering source_filter: {:?}",
+                source_filter
+            );
+            Ok(())
+        }
+
+        async fn unregister_listener(
+            &self,
+            source_filter: &UUri,
+            _sink_filter: Option<&UUri>,
+            _listener: Arc<dyn UListener>,
+        ) -> Result<(), UStatus> {
+            println!(
+                "UPClientBar: unregistering source_filter: {:?}",
+                source_filter
+            );
+            Ok(())
+        }
+    }
+
+    #[async_std::test]
+    async fn test_simple_with_a_single_input_and_output_endpoint() {
+        // Local endpoint
+        let local_authority = "local";
+        let local_transport: Arc<dyn UTransport> = Arc::new(UPClientFoo);
+        let local_endpoint =
+            Endpoint::new("local_endpoint", local_authority, local_transport.clone());
+
+        // A remote endpoint
+        let remote_authority = "remote";
+        let remote_transport: Arc<dyn UTransport> = Arc::new(UPClientBar);
+        let remote_endpoint = Endpoint::new(
+            "remote_endpoint",
+            remote_authority,
+            remote_transport.clone(),
+        );
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        // Add forwarding rules to endpoint local<->remote
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+
+        // Add forwarding rules to endpoint local<->local, should report an error
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), local_endpoint.clone())
+            .await
+            .is_err());
+
+        // Rule already exists so it should report an error
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_err());
 
         // Add forwarding rules to endpoint remote<->remote, should report an error
         assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint.clone(), remote_endpoint.clone())
+            .add_forwarding_rule(remote_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_err());
+
+        // remove valid routing rules
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .delete_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+
+        // Try and remove a rule that doesn't exist, should report an error
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn test_advanced_where_there_is_a_local_endpoint_and_two_remote_endpoints() {
+        // Local endpoint
+        let local_authority = "local";
+        let local_transport: Arc<dyn UTransport> = Arc::new(UPClientFoo);
+        let local_endpoint =
+            Endpoint::new("local_endpoint", local_authority, local_transport.clone());
+
+        // Remote endpoint - A
+        let remote_authority_a = "remote_a";
+        let remote_transport_a: Arc<dyn UTransport> = Arc::new(UPClientBar);
+        let remote_endpoint_a = Endpoint::new(
+            "remote_endpoint_a",
+            remote_authority_a,
+            remote_transport_a.clone(),
+        );
+
+        // Remote endpoint - B
+        let remote_authority_b = "remote_b";
+        let remote_transport_b: Arc<dyn UTransport> = Arc::new(UPClientBar);
+        let remote_endpoint_b = Endpoint::new(
+            "remote_endpoint_b",
+            remote_authority_b,
+            remote_transport_b.clone(),
+        );
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+
+        // Add forwarding rules to endpoint local<->remote_a
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_a.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint_a.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+
+        // Add forwarding rules to endpoint local<->remote_b
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_b.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint_b.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+
+        // Add forwarding rules to endpoint remote_a<->remote_b
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint_a.clone(), remote_endpoint_b.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint_b.clone(), remote_endpoint_a.clone())
+            .await
+            .is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_advanced_where_there_is_a_local_endpoint_and_two_remote_endpoints_but_the_remote_endpoints_have_the_same_instance_of_utransport(
+    ) {
+        // Local endpoint
+        let local_authority = "local";
+        let local_transport: Arc<dyn UTransport> = Arc::new(UPClientFoo);
+        let local_endpoint =
+            Endpoint::new("local_endpoint", local_authority, local_transport.clone());
+
+        let remote_transport: Arc<dyn UTransport> = Arc::new(UPClientBar);
+
+        // Remote endpoint - A
+        let remote_authority_a = "remote_a";
+        let remote_endpoint_a = Endpoint::new(
+            "remote_endpoint_a",
+            remote_authority_a,
+            remote_transport.clone(),
+        );
+
+        // Remote endpoint - B
+        let remote_authority_b = "remote_b";
+        let remote_endpoint_b = Endpoint::new(
+            "remote_endpoint_b",
+            remote_authority_b,
+            remote_transport.clone(),
+        );
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+
+        // Add forwarding rules to endpoint local<->remote_a
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_a.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint_a.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+
+        // Add forwarding rules to endpoint local<->remote_b
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_b.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint_b.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_endpoints_serving_multiple_authorities() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        // several authorities behind one transport
+        let remote_endpoint = Endpoint::new_with_authorities(
+            "remote_endpoint",
+            &["remote_a", "remote_b", "zone-*"],
+            remote_transport.clone(),
+        )
+        .unwrap();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+
+        // each of the remote authorities is forwarded to, once, and no other
+        for authority_name in ["remote_a", "remote_b", "zone-7", "elsewhere"] {
+            local_transport
+                .deliver(message_for_authority(authority_name))
+                .await;
+        }
+        assert!(remote_transport.wait_for_sent(3).await);
+        let sink_authorities: Vec<String> = remote_transport
+            .sent()
+            .iter()
+            .map(|msg| msg.attributes.as_ref().unwrap().sink.authority_name.clone())
+            .collect();
+        assert_eq!(sink_authorities, ["remote_a", "remote_b", "zone-7"]);
+
+        // and each of them may send back
+        remote_transport
+            .deliver(message_for_authority("local"))
+            .await;
+        assert!(local_transport.wait_for_sent(1).await);
+
+        // an endpoint overlapping with one of the remote authorities should report an error
+        let overlapping_endpoint = Endpoint::new_with_authorities(
+            "overlapping_endpoint",
+            &["remote_c", "zone-1"],
+            local_transport.clone(),
+        )
+        .unwrap();
+        assert!(ustreamer
+            .add_forwarding_rule(overlapping_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_err());
+
+        // nor may an endpoint have no authorities at all
+        let err = Endpoint::new_with_authorities("empty_endpoint", &[], local_transport.clone())
+            .err()
+            .unwrap();
+        assert_eq!(err.get_code(), UCode::INVALID_ARGUMENT);
+
+        // the rule's listeners go with it, even if it's named by an endpoint rebuilt with its
+        // authorities in another order, or only some of them
+        let rebuilt_remote_endpoint = Endpoint::new_with_authorities(
+            "remote_endpoint",
+            &["zone-*", "remote_a"],
+            remote_transport.clone(),
+        )
+        .unwrap();
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint.clone(), rebuilt_remote_endpoint)
+            .await
+            .is_ok());
+        assert_eq!(local_transport.listener_count(), 0);
+        assert!(ustreamer
+            .delete_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+        assert_eq!(remote_transport.listener_count(), 0);
+    }
+
+    #[async_std::test]
+    async fn test_rules_onto_the_same_authority_over_different_transports_both_forward() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport_zenoh = Arc::new(UPClientRecorder::default());
+        let remote_transport_someip = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint_zenoh = Endpoint::new(
+            "remote_endpoint_zenoh",
+            "remote",
+            remote_transport_zenoh.clone(),
+        );
+        let remote_endpoint_someip = Endpoint::new(
+            "remote_endpoint_someip",
+            "remote",
+            remote_transport_someip.clone(),
+        );
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_zenoh.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_someip.clone())
+            .await
+            .is_ok());
+
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(remote_transport_zenoh.wait_for_sent(1).await);
+        assert!(remote_transport_someip.wait_for_sent(1).await);
+    }
+
+    #[async_std::test]
+    async fn test_only_the_most_specific_rule_forwards_a_message() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let zone_1_transport = Arc::new(UPClientRecorder::default());
+        let zones_transport = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let zone_1_endpoint = Endpoint::new("zone_1_endpoint", "zone-1", zone_1_transport.clone());
+        let zones_endpoint = Endpoint::new("zones_endpoint", "zone-*", zones_transport.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), zone_1_endpoint.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), zones_endpoint.clone())
+            .await
+            .is_ok());
+
+        local_transport
+            .deliver(message_for_authority("zone-1"))
+            .await;
+        local_transport
+            .deliver(message_for_authority("zone-2"))
+            .await;
+        assert!(zone_1_transport.wait_for_sent(1).await);
+        assert!(zones_transport.wait_for_sent(1).await);
+
+        // once the more specific rule is gone, the pattern takes over
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint.clone(), zone_1_endpoint.clone())
+            .await
+            .is_ok());
+        local_transport
+            .deliver(message_for_authority("zone-1"))
+            .await;
+        assert!(zones_transport.wait_for_sent(2).await);
+        let sink_authorities: Vec<String> = zones_transport
+            .sent()
+            .iter()
+            .map(|msg| msg.attributes.as_ref().unwrap().sink.authority_name.clone())
+            .collect();
+        assert_eq!(sink_authorities, ["zone-2", "zone-1"]);
+        assert_eq!(zone_1_transport.sent_count(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_deleting_a_rule_leaves_another_rule_from_the_same_in_endpoint_forwarding() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport_a = Arc::new(UPClientRecorder::default());
+        let remote_transport_b = Arc::new(UPClientRecorder::default());
+
+        // the out endpoints only differ in their transports, so the rules onto them do too
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint_a =
+            Endpoint::new("remote_endpoint", "remote", remote_transport_a.clone());
+        let remote_endpoint_b =
+            Endpoint::new("remote_endpoint", "remote", remote_transport_b.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_a.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_b.clone())
+            .await
+            .is_ok());
+
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint.clone(), remote_endpoint_a.clone())
+            .await
+            .is_ok());
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(remote_transport_b.wait_for_sent(1).await);
+        assert_eq!(remote_transport_a.sent_count(), 0);
+    }
+
+    #[async_std::test]
+    async fn test_failover_onto_standby_and_failback_onto_primary() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let primary_transport = Arc::new(UPClientRecorder::default());
+        let standby_transport = Arc::new(UPClientRecorder::default());
+        primary_transport.fail_sends.store(true, Ordering::SeqCst);
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let primary_endpoint =
+            Endpoint::new("primary_endpoint", "remote", primary_transport.clone());
+        let standby_endpoint =
+            Endpoint::new("standby_endpoint", "remote", standby_transport.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                primary_endpoint.clone(),
+                ForwardingRuleOptions::new().with_failover(
+                    vec![standby_endpoint.clone()],
+                    FailoverConfig {
+                        consecutive_failures_to_fail_over: 1,
+                        retry_interval: Duration::from_millis(300),
+                        ..Default::default()
+                    },
+                ),
+            )
+            .await
+            .is_ok());
+
+        // the first send fails on the primary, after which we fail over onto the standby
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(wait_until(|| ustreamer.metrics().failovers == 1).await);
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(standby_transport.wait_for_sent(1).await);
+        assert_eq!(primary_transport.sent_count(), 0);
+        assert_eq!(standby_transport.sent_count(), 1);
+        assert_eq!(ustreamer.metrics().failovers, 1);
+        assert_eq!(ustreamer.metrics().send_failures, 1);
+
+        // once the primary recovers and the retry interval has passed we fail back onto it
+        primary_transport.fail_sends.store(false, Ordering::SeqCst);
+        task::sleep(Duration::from_millis(300)).await;
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(primary_transport.wait_for_sent(1).await);
+        assert_eq!(standby_transport.sent_count(), 1);
+        assert_eq!(ustreamer.metrics().failbacks, 1);
+
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint, primary_endpoint)
+            .await
+            .is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_standbys_must_serve_the_primarys_authorities() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let primary_transport = Arc::new(UPClientRecorder::default());
+        let standby_transport = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let primary_endpoint = Endpoint::new_with_authorities(
+            "primary_endpoint",
+            &["remote_a", "remote_b"],
+            primary_transport.clone(),
+        )
+        .unwrap();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        let rule_with_standby = |standby_authorities: &[&str]| {
+            let standby_endpoint = Endpoint::new_with_authorities(
+                "standby_endpoint",
+                standby_authorities,
+                standby_transport.clone(),
+            )
+            .unwrap();
+            (
+                local_endpoint.clone(),
+                primary_endpoint.clone(),
+                ForwardingRuleOptions::new()
+                    .with_failover(vec![standby_endpoint], FailoverConfig::default()),
+            )
+        };
+
+        // a standby only reaching one of the primary's authorities can't stand in for it
+        let (r#in, out, options) = rule_with_standby(&["remote_a"]);
+        let err = ustreamer
+            .add_forwarding_rule_with_options(r#in, out, options)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.get_code(), UCode::INVALID_ARGUMENT);
+        assert_eq!(local_transport.listener_count(), 0);
+
+        // while one reaching them by a pattern can
+        let (r#in, out, options) = rule_with_standby(&["remote_*"]);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(r#in, out, options)
+            .await
+            .is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_load_balancing_round_robin_and_source_hash() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport_a = Arc::new(UPClientRecorder::default());
+        let remote_transport_b = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint_a =
+            Endpoint::new("remote_endpoint_a", "remote", remote_transport_a.clone());
+        let remote_endpoint_b =
+            Endpoint::new("remote_endpoint_b", "remote", remote_transport_b.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint_a.clone(),
+                ForwardingRuleOptions::new().with_load_balancing(
+                    vec![remote_endpoint_b.clone()],
+                    LoadBalancingStrategy::RoundRobin
+                ),
+            )
+            .await
+            .is_ok());
+
+        for _ in 0..4 {
+            local_transport
+                .deliver(message_for_authority("remote"))
+                .await;
+        }
+        assert!(remote_transport_a.wait_for_sent(2).await);
+        assert!(remote_transport_b.wait_for_sent(2).await);
+
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint.clone(), remote_endpoint_a.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint_a.clone(),
+                ForwardingRuleOptions::new().with_load_balancing(
+                    vec![remote_endpoint_b.clone()],
+                    LoadBalancingStrategy::SourceHash
+                ),
+            )
+            .await
+            .is_ok());
+
+        // messages from the same source all end up on the same out endpoint, while the sources
+        // are spread across both
+        let sources: Vec<u32> = (1..=16).collect();
+        for _ in 0..3 {
+            for ue_id in &sources {
+                let mut msg = message_for_authority("remote");
+                msg.attributes.as_mut().unwrap().source = Some(UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: *ue_id,
+                    ue_version_major: 1,
+                    ..Default::default()
+                })
+                .into();
+                local_transport.deliver(msg).await;
+            }
+        }
+        let sources_sent_on = |remote_transport: &UPClientRecorder| {
+            let mut ue_ids: Vec<u32> = remote_transport
+                .sent()
+                .iter()
+                .filter_map(|msg| Some(msg.attributes.as_ref()?.source.as_ref()?.ue_id))
+                .collect();
+            ue_ids.sort();
+            ue_ids
+        };
+        assert!(
+            wait_until(|| {
+                remote_transport_a.sent_count() + remote_transport_b.sent_count() >= 4 + 3 * 16
+            })
+            .await
+        );
+        let mut sources_a = sources_sent_on(&remote_transport_a);
+        let mut sources_b = sources_sent_on(&remote_transport_b);
+        assert_eq!(sources_a.len() + sources_b.len(), 3 * 16);
+        sources_a.dedup();
+        sources_b.dedup();
+        assert!(!sources_a.is_empty() && !sources_b.is_empty());
+        assert!(sources_a.iter().all(|ue_id| !sources_b.contains(ue_id)));
+    }
+
+    #[async_std::test]
+    async fn test_load_balancing_leaves_out_endpoints_which_are_down() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport_a = Arc::new(UPClientRecorder::default());
+        let remote_transport_b = Arc::new(UPClientRecorder::default());
+        remote_transport_b.fail_sends.store(true, Ordering::SeqCst);
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint_a =
+            Endpoint::new("remote_endpoint_a", "remote", remote_transport_a.clone());
+        let remote_endpoint_b =
+            Endpoint::new("remote_endpoint_b", "remote", remote_transport_b.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        ustreamer.set_transport_health_config(TransportHealthConfig {
+            consecutive_failures_until_down: 1,
+            ..Default::default()
+        });
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint_a.clone(),
+                ForwardingRuleOptions::new().with_load_balancing(
+                    vec![remote_endpoint_b.clone()],
+                    LoadBalancingStrategy::RoundRobin
+                ),
+            )
+            .await
+            .is_ok());
+
+        // the first message goes to a, the second fails on b, which is then left out
+        for _ in 0..2 {
+            local_transport
+                .deliver(message_for_authority("remote"))
+                .await;
+        }
+        assert!(
+            wait_until(|| {
+                ustreamer.transport_health_state(&remote_endpoint_b)
+                    == Some(TransportHealthState::Down)
+            })
+            .await
+        );
+
+        for _ in 0..4 {
+            local_transport
+                .deliver(message_for_authority("remote"))
+                .await;
+        }
+        assert!(remote_transport_a.wait_for_sent(5).await);
+        assert_eq!(ustreamer.metrics().send_failures, 1);
+    }
+
+    #[async_std::test]
+    async fn test_content_filter_and_routing_look_at_the_payload() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport_eu = Arc::new(UPClientRecorder::default());
+        let remote_transport_us = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint_eu =
+            Endpoint::new("remote_endpoint_eu", "remote", remote_transport_eu.clone());
+        let remote_endpoint_us =
+            Endpoint::new("remote_endpoint_us", "remote", remote_transport_us.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint_eu.clone(),
+                ForwardingRuleOptions::new()
+                    .with_content_filter(ContentFilter::new("name ~ 'Al*'").unwrap())
+                    .with_content_routing(vec![(
+                        ContentFilter::new("region == 'us'").unwrap(),
+                        remote_endpoint_us.clone()
+                    )]),
+            )
+            .await
+            .is_ok());
+
+        let hello = |payload: &str| {
+            let mut msg = message_for_authority("remote");
+            msg.attributes.mut_or_insert_default().payload_format =
+                UPayloadFormat::UPAYLOAD_FORMAT_JSON.into();
+            msg.payload = Some(payload.as_bytes().to_vec().into());
+            msg
+        };
+        local_transport
+            .deliver(hello(r#"{"name": "Alice", "region": "us"}"#))
+            .await;
+        local_transport
+            .deliver(hello(r#"{"name": "Alfred", "region": "eu"}"#))
+            .await;
+        local_transport
+            .deliver(hello(r#"{"name": "Bob", "region": "us"}"#))
+            .await;
+        assert!(remote_transport_us.wait_for_sent(1).await);
+        assert!(remote_transport_eu.wait_for_sent(1).await);
+
+        assert_eq!(
+            remote_transport_us.sent(),
+            vec![hello(r#"{"name": "Alice", "region": "us"}"#)]
+        );
+        assert_eq!(
+            remote_transport_eu.sent(),
+            vec![hello(r#"{"name": "Alfred", "region": "eu"}"#)]
+        );
+        assert_eq!(ustreamer.metrics().content_filtered, 1);
+    }
+
+    #[async_std::test]
+    async fn test_payloads_are_translated_from_protobuf_to_json() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let descriptors = DescriptorRegistry::new().with_file_descriptor(&hello_file_descriptor());
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_payload_translation(
+                    PayloadTranslation::protobuf_to_json(descriptors)
+                        .with_message_type("example.hello.HelloRequest")
+                ),
+            )
+            .await
+            .is_ok());
+
+        let mut msg = message_for_authority("remote");
+        msg.attributes.mut_or_insert_default().payload_format =
+            UPayloadFormat::UPAYLOAD_FORMAT_PROTOBUF.into();
+        msg.payload = Some(hello_request("Alice", 3).into());
+        local_transport.deliver(msg).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        let sent = remote_transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].attributes.payload_format.enum_value(),
+            Ok(UPayloadFormat::UPAYLOAD_FORMAT_JSON)
+        );
+        let json: serde_json::Value =
+            serde_json::from_slice(sent[0].payload.as_ref().unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({"name": "Alice", "count": 3}));
+        assert_eq!(ustreamer.metrics().translated_messages, 1);
+    }
+
+    #[async_std::test]
+    async fn test_messages_are_rewritten_and_dropped_by_the_message_script() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let script = MessageScript::new(
+            r#"
+                fn on_message(msg) {
+                    if msg.payload.as_string() == "drop" {
+                        return ();
+                    }
+                    msg.attributes.sink.resourceId = 0x8002;
+                    msg
+                }
+            "#,
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_script(script),
+            )
+            .await
+            .is_ok());
+
+        let with_payload = |payload: &str| {
+            let mut msg = message_for_authority("remote");
+            msg.payload = Some(payload.as_bytes().to_vec().into());
+            msg
+        };
+        local_transport.deliver(with_payload("drop")).await;
+        local_transport.deliver(with_payload("keep")).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        let mut rewritten = with_payload("keep");
+        rewritten
+            .attributes
+            .mut_or_insert_default()
+            .sink
+            .mut_or_insert_default()
+            .resource_id = 0x8002;
+        assert_eq!(remote_transport.sent(), vec![rewritten]);
+        assert_eq!(ustreamer.metrics().script_dropped, 1);
+    }
+
+    #[async_std::test]
+    async fn test_scripted_messages_are_held_to_the_rule() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let script = MessageScript::new(
+            r#"
+                fn on_message(msg) {
+                    let text = msg.payload.as_string();
+                    if text == "redirect" {
+                        msg.attributes.sink.authorityName = "elsewhere";
+                    }
+                    if text == "spoof" {
+                        msg.attributes.source.authorityName = "remote";
+                    }
+                    msg
+                }
+            "#,
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        ustreamer.set_strict_source_authority(true);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_script(script),
+            )
+            .await
+            .is_ok());
+
+        let with_payload = |payload: &str| {
+            let mut msg = message_for_authority("remote");
+            msg.attributes.mut_or_insert_default().source = Some(UUri {
+                authority_name: "local".to_string(),
+                ue_id: 0x4321,
+                ue_version_major: 1,
+                ..Default::default()
+            })
+            .into();
+            msg.payload = Some(payload.as_bytes().to_vec().into());
+            msg
+        };
+        local_transport.deliver(with_payload("redirect")).await;
+        local_transport.deliver(with_payload("spoof")).await;
+        local_transport.deliver(with_payload("keep")).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport.sent(), vec![with_payload("keep")]);
+        assert_eq!(ustreamer.metrics().rewritten_sink_mismatches, 1);
+        assert_eq!(ustreamer.metrics().source_authority_mismatches, 1);
+    }
+
+    #[async_std::test]
+    async fn test_messages_are_rewritten_and_dropped_by_the_wasm_filter() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let wasm_filter =
+            WasmFilter::new(&wasm(REWRITING_FILTER), WasmFilterLimits::default()).unwrap();
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_wasm_filter(wasm_filter),
+            )
+            .await
+            .is_ok());
+
+        let with_payload = |payload: &str| {
+            let mut msg = message_for_authority("remote");
+            msg.payload = Some(payload.as_bytes().to_vec().into());
+            msg
+        };
+        local_transport.deliver(with_payload("drop")).await;
+        local_transport.deliver(with_payload("hello")).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport.sent(), vec![with_payload("rewritten")]);
+        assert_eq!(ustreamer.metrics().wasm_filtered, 1);
+    }
+
+    #[async_std::test]
+    async fn test_listeners_are_re_registered_after_reconnect() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+
+        // nothing was lost, so nothing is registered again
+        assert!(ustreamer.reconcile_listeners().await.is_ok());
+        assert_eq!(ustreamer.metrics().listener_reregistrations, 0);
+
+        // with its listeners gone, nothing arriving on the transport reaches us
+        local_transport.lose_session();
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert_eq!(remote_transport.sent_count(), 0);
+
+        assert!(ustreamer
+            .notify_transport_reconnected(&local_endpoint)
+            .await
+            .is_ok());
+        assert_eq!(ustreamer.metrics().listener_reregistrations, 1);
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint, remote_endpoint)
+            .await
+            .is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_reconciling_doesnt_register_listeners_twice() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        local_transport
+            .accept_duplicates
+            .store(true, Ordering::SeqCst);
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint = Endpoint::new("remote_endpoint", "remote", remote_transport.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+        assert_eq!(local_transport.listener_count(), 1);
+
+        assert!(ustreamer.reconcile_listeners().await.is_ok());
+        assert!(ustreamer
+            .notify_transport_reconnected(&local_endpoint)
+            .await
+            .is_ok());
+        assert_eq!(local_transport.listener_count(), 1);
+        assert_eq!(ustreamer.metrics().listener_reregistrations, 0);
+
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(remote_transport.wait_for_sent(1).await);
+        assert_eq!(remote_transport.sent_count(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_rate_limit_drops_or_delays_excess_messages() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let rate_limit = RateLimit {
+            messages_per_second: Some(10),
+            message_burst: 2,
+            ..Default::default()
+        };
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_rate_limit(rate_limit.clone()),
+            )
             .await
-            .is_err());
+            .is_ok());
+
+        for _ in 0..5 {
+            local_transport
+                .deliver(message_for_authority("remote"))
+                .await;
+        }
+        assert!(remote_transport.wait_for_sent(2).await);
+        assert!(wait_until(|| ustreamer.metrics().rate_limited_dropped == 3).await);
+        assert_eq!(remote_transport.sent_count(), 2);
 
-        // remove valid routing rules
         assert!(ustreamer
             .delete_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
             .await
             .is_ok());
         assert!(ustreamer
-            .delete_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_rate_limit(RateLimit {
+                    policy: RateLimitPolicy::Delay,
+                    ..rate_limit
+                }),
+            )
             .await
             .is_ok());
 
-        // Try and remove a rule that doesn't exist, should report an error
+        // the burst goes through at once, the remaining three are spaced 100ms apart
+        let start = std::time::Instant::now();
+        for _ in 0..5 {
+            local_transport
+                .deliver(message_for_authority("remote"))
+                .await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert!(remote_transport.wait_for_sent(7).await);
+        assert_eq!(ustreamer.metrics().rate_limited_delayed, 3);
+    }
+
+    #[async_std::test]
+    async fn test_conflation_forwards_the_newest_value() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
         assert!(ustreamer
-            .delete_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_conflation(ConflationConfig {
+                    messages_per_second: 10,
+                }),
+            )
             .await
-            .is_err());
+            .is_ok());
+
+        let wheel_speed = |value: u8| {
+            let mut msg = message_for_authority("remote");
+            let attributes = msg.attributes.mut_or_insert_default();
+            attributes.type_ = UMessageType::UMESSAGE_TYPE_NOTIFICATION.into();
+            attributes.source = Some(UUri {
+                authority_name: "local".to_string(),
+                ue_id: 0x5678,
+                ue_version_major: 1,
+                resource_id: 0x8001,
+                ..Default::default()
+            })
+            .into();
+            msg.payload = Some(vec![value].into());
+            msg
+        };
+        for value in 0..5 {
+            local_transport.deliver(wheel_speed(value)).await;
+        }
+        assert!(remote_transport.wait_for_sent(1).await);
+        assert_eq!(remote_transport.sent(), vec![wheel_speed(0)]);
+
+        // the newest value held back goes out once the next one is due
+        assert!(remote_transport.wait_for_sent(2).await);
+        assert_eq!(
+            remote_transport.sent(),
+            vec![wheel_speed(0), wheel_speed(4)]
+        );
+        assert_eq!(ustreamer.metrics().conflated_messages, 3);
+    }
+
+    // replayed values are sent with an id of their own
+    fn without_ids(msgs: Vec<UMessage>) -> Vec<UMessage> {
+        msgs.into_iter()
+            .map(|mut msg| {
+                msg.attributes.mut_or_insert_default().id = Default::default();
+                msg
+            })
+            .collect()
+    }
+
+    fn notification_for_authority(authority_name: &str) -> UMessage {
+        let mut notification = message_for_authority(authority_name);
+        notification.attributes.mut_or_insert_default().type_ =
+            UMessageType::UMESSAGE_TYPE_NOTIFICATION.into();
+        notification
     }
 
     #[async_std::test]
-    async fn test_advanced_where_there_is_a_local_endpoint_and_two_remote_endpoints() {
-        // Local endpoint
-        let local_authority = "local";
-        let local_transport: Arc<dyn UTransport> = Arc::new(UPClientFoo);
-        let local_endpoint =
-            Endpoint::new("local_endpoint", local_authority, local_transport.clone());
+    async fn test_retained_values_are_replayed_to_added_rules() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+        let other_transport = Arc::new(UPClientRecorder::default());
 
-        // Remote endpoint - A
-        let remote_authority_a = "remote_a";
-        let remote_transport_a: Arc<dyn UTransport> = Arc::new(UPClientBar);
-        let remote_endpoint_a = Endpoint::new(
-            "remote_endpoint_a",
-            remote_authority_a,
-            remote_transport_a.clone(),
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone())
+            .with_retained_values(RetainedValuesConfig::default());
+        let remote_endpoint = Endpoint::new("remote_endpoint", "remote", remote_transport.clone());
+        let other_endpoint = Endpoint::new("other_endpoint", "other", other_transport.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+
+        // the value for the other side is retained though no rule forwards it there yet
+        let notification = notification_for_authority("remote");
+        let other_notification = notification_for_authority("other");
+        local_transport.deliver(notification.clone()).await;
+        local_transport.deliver(other_notification.clone()).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        // the remote side reconnecting gets its value again right away, the other side gets only
+        // its own once its rule is added
+        assert!(ustreamer
+            .delete_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(local_endpoint.clone(), other_endpoint.clone())
+            .await
+            .is_ok());
+        assert!(remote_transport.wait_for_sent(2).await);
+        assert!(other_transport.wait_for_sent(1).await);
+
+        assert_eq!(
+            without_ids(remote_transport.sent()),
+            vec![notification.clone(), notification]
+        );
+        assert_eq!(
+            without_ids(other_transport.sent()),
+            vec![other_notification]
         );
+        assert_eq!(ustreamer.metrics().retained_values_replayed, 2);
+    }
 
-        // Remote endpoint - B
-        let remote_authority_b = "remote_b";
-        let remote_transport_b: Arc<dyn UTransport> = Arc::new(UPClientBar);
-        let remote_endpoint_b = Endpoint::new(
-            "remote_endpoint_b",
-            remote_authority_b,
-            remote_transport_b.clone(),
+    #[async_std::test]
+    async fn test_retained_values_are_replayed_to_new_subscribers() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let local_transport_someip = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone())
+            .with_retained_values(RetainedValuesConfig::default());
+        let local_endpoint_someip = Endpoint::new(
+            "local_endpoint_someip",
+            "local",
+            local_transport_someip.clone(),
         );
+        let remote_endpoint = Endpoint::new("remote_endpoint", "remote", remote_transport.clone());
 
         let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
-
-        // Add forwarding rules to endpoint local<->remote_a
         assert!(ustreamer
-            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_a.clone())
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
             .await
             .is_ok());
+        // the subscribe request is carried by two rules, but replayed for once
+        for local_endpoint in [local_endpoint.clone(), local_endpoint_someip] {
+            assert!(ustreamer
+                .add_forwarding_rule(remote_endpoint.clone(), local_endpoint)
+                .await
+                .is_ok());
+        }
+
+        let mut notification = notification_for_authority("remote");
+        notification.attributes.mut_or_insert_default().id = Some(UUIDBuilder::build()).into();
+        local_transport.deliver(notification.clone()).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        // a uE on the remote side subscribing through the local uSubscription
+        let subscribe_request = UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_REQUEST.into(),
+                id: Some(UUIDBuilder::build()).into(),
+                source: Some(UUri {
+                    authority_name: "remote".to_string(),
+                    ue_id: 0x5678,
+                    ue_version_major: 1,
+                    ..Default::default()
+                })
+                .into(),
+                sink: Some(UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: 0x0000,
+                    ue_version_major: 3,
+                    resource_id: 0x0001,
+                    ..Default::default()
+                })
+                .into(),
+                ttl: Some(1000),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        };
+        remote_transport.deliver(subscribe_request.clone()).await;
+        assert!(local_transport.wait_for_sent(1).await);
+        assert!(local_transport_someip.wait_for_sent(1).await);
+        assert!(remote_transport.wait_for_sent(2).await);
+
+        assert_eq!(local_transport.sent(), vec![subscribe_request.clone()]);
+        assert_eq!(local_transport_someip.sent(), vec![subscribe_request]);
+        let sent = remote_transport.sent();
+        assert_eq!(
+            without_ids(sent.clone()),
+            without_ids(vec![notification.clone(); 2])
+        );
+        // the replayed value is sent anew, lest a peer's replay protection reject it
+        assert_ne!(sent[1].attributes.id, notification.attributes.id);
+        assert_eq!(ustreamer.metrics().retained_values_replayed, 1);
+    }
+
+    #[async_std::test]
+    async fn test_access_policy_denies_requests_with_permission_denied() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        ustreamer.set_access_policy(
+            AccessPolicy::new(AccessDecision::Allow).with_rule(
+                AccessRule::deny(UUriPattern::any(), UUriPattern::authority("remote"))
+                    .for_message_types(&[UMessageType::UMESSAGE_TYPE_REQUEST]),
+            ),
+        );
         assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint_a.clone(), local_endpoint.clone())
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
             .await
             .is_ok());
 
-        // Add forwarding rules to endpoint local<->remote_b
+        let request = UMessageBuilder::request(
+            UUri {
+                authority_name: "remote".to_string(),
+                ue_id: 0x4,
+                ue_version_major: 1,
+                resource_id: 0x1,
+                ..Default::default()
+            },
+            UUri {
+                authority_name: "local".to_string(),
+                ue_id: 0x1101,
+                ue_version_major: 1,
+                resource_id: 0x0,
+                ..Default::default()
+            },
+            1000,
+        )
+        .build()
+        .unwrap();
+        local_transport.deliver(request).await;
+        // notifications aren't restricted by the policy
+        local_transport
+            .deliver(message_for_authority("remote"))
+            .await;
+        assert!(remote_transport.wait_for_sent(1).await);
+        assert!(local_transport.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport.sent_count(), 1);
+        assert_eq!(ustreamer.metrics().access_denied, 1);
+        let responses = local_transport.sent();
+        assert_eq!(responses.len(), 1);
+        let response_attributes = responses[0].attributes.as_ref().unwrap();
+        assert_eq!(
+            response_attributes.type_.enum_value_or_default(),
+            UMessageType::UMESSAGE_TYPE_RESPONSE
+        );
+        assert_eq!(
+            response_attributes.commstatus,
+            Some(UCode::PERMISSION_DENIED.into())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_strict_source_authority_drops_spoofed_messages() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        ustreamer.set_strict_source_authority(true);
         assert!(ustreamer
-            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_b.clone())
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
             .await
             .is_ok());
+
+        for source_authority in ["local", "other"] {
+            let mut msg = message_for_authority("remote");
+            msg.attributes.mut_or_insert_default().source = Some(UUri {
+                authority_name: source_authority.to_string(),
+                ue_id: 0x1101,
+                ue_version_major: 1,
+                ..Default::default()
+            })
+            .into();
+            local_transport.deliver(msg).await;
+        }
+        assert!(remote_transport.wait_for_sent(1).await);
+        assert!(wait_until(|| ustreamer.metrics().source_authority_mismatches == 1).await);
+        assert_eq!(remote_transport.sent_count(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_oversize_messages_are_rejected_before_sending() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone());
+        let remote_endpoint = Endpoint::new("remote_endpoint", "remote", remote_transport.clone())
+            .with_max_payload_size(8, OversizePolicy::Reject);
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
         assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint_b.clone(), local_endpoint.clone())
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
             .await
             .is_ok());
 
-        // Add forwarding rules to endpoint remote_a<->remote_b
+        for payload_size in [8, 9] {
+            let mut msg = message_for_authority("remote");
+            msg.payload = Some(vec![0u8; payload_size].into());
+            local_transport.deliver(msg).await;
+        }
+        assert!(remote_transport.wait_for_sent(1).await);
+        assert!(wait_until(|| ustreamer.metrics().oversize_rejected == 1).await);
+        assert_eq!(remote_transport.sent_count(), 1);
+        // the oversize message was a notification, so there's nobody to answer
+        assert_eq!(local_transport.sent_count(), 0);
+    }
+
+    #[async_std::test]
+    async fn test_fragmented_messages_are_reassembled_by_the_peer_streamer() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let link_transport = Arc::new(UPClientRecorder::default());
+        let peer_link_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        // local -> link, fragmenting onto a constrained link
+        let mut ustreamer = UStreamer::new("fragmenting_streamer", 100);
         assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint_a.clone(), remote_endpoint_b.clone())
+            .add_forwarding_rule(
+                Endpoint::new("local_endpoint", "local", local_transport.clone()),
+                Endpoint::new("link_endpoint", "remote", link_transport.clone())
+                    .with_max_payload_size(64, OversizePolicy::Fragment),
+            )
             .await
             .is_ok());
+
+        // link -> remote, on the other end of the link
+        let mut peer_ustreamer = UStreamer::new("reassembling_streamer", 100);
+        assert!(peer_ustreamer
+            .add_forwarding_rule(
+                Endpoint::new("peer_link_endpoint", "local", peer_link_transport.clone()),
+                Endpoint::new("remote_endpoint", "remote", remote_transport.clone()),
+            )
+            .await
+            .is_ok());
+
+        let mut msg = message_for_authority("remote");
+        msg.attributes.mut_or_insert_default().id = Some(UUIDBuilder::build()).into();
+        msg.payload = Some((0..200).map(|i| i as u8).collect::<Vec<u8>>().into());
+        local_transport.deliver(msg.clone()).await;
+        assert!(link_transport.wait_for_sent(6).await);
+
+        let fragments = link_transport.sent();
+        assert_eq!(fragments.len(), 6);
+        assert!(fragments
+            .iter()
+            .all(|fragment| fragment.payload.as_ref().unwrap().len() <= 64));
+        assert_eq!(ustreamer.metrics().fragmented_messages, 1);
+
+        for fragment in fragments {
+            peer_link_transport.deliver(fragment).await;
+        }
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport.sent(), vec![msg]);
+        assert_eq!(peer_ustreamer.metrics().reassembled_messages, 1);
+    }
+
+    #[async_std::test]
+    async fn test_fragmented_messages_are_reassembled_for_every_rule() {
+        let link_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport_zenoh = Arc::new(UPClientRecorder::default());
+        let remote_transport_someip = Arc::new(UPClientRecorder::default());
+
+        let link_endpoint = Endpoint::new("link_endpoint", "local", link_transport.clone());
+        let mut ustreamer = UStreamer::new("reassembling_streamer", 100);
+        for remote_endpoint in [
+            Endpoint::new(
+                "remote_endpoint_zenoh",
+                "remote",
+                remote_transport_zenoh.clone(),
+            ),
+            Endpoint::new(
+                "remote_endpoint_someip",
+                "remote",
+                remote_transport_someip.clone(),
+            ),
+        ] {
+            assert!(ustreamer
+                .add_forwarding_rule(link_endpoint.clone(), remote_endpoint)
+                .await
+                .is_ok());
+        }
+
+        let mut msg = message_for_authority("remote");
+        msg.attributes.mut_or_insert_default().id = Some(UUIDBuilder::build()).into();
+        msg.payload = Some((0..200).map(|i| i as u8).collect::<Vec<u8>>().into());
+        for fragment in crate::fragmentation::fragment(&msg, 64).unwrap() {
+            link_transport.deliver(fragment).await;
+        }
+        assert!(remote_transport_zenoh.wait_for_sent(1).await);
+        assert!(remote_transport_someip.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport_zenoh.sent(), vec![msg.clone()]);
+        assert_eq!(remote_transport_someip.sent(), vec![msg]);
+        assert_eq!(ustreamer.metrics().reassembled_messages, 2);
+        assert_eq!(ustreamer.metrics().reassembly_failures, 0);
+    }
+
+    #[async_std::test]
+    async fn test_compressed_messages_are_decompressed_by_the_peer_streamer() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let link_transport = Arc::new(UPClientRecorder::default());
+        let peer_link_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        let mut ustreamer = UStreamer::new("compressing_streamer", 100);
         assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint_b.clone(), remote_endpoint_a.clone())
+            .add_forwarding_rule_with_options(
+                Endpoint::new("local_endpoint", "local", local_transport.clone()),
+                Endpoint::new("link_endpoint", "remote", link_transport.clone()),
+                ForwardingRuleOptions::new().with_compression(CompressionConfig {
+                    min_size: 100,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .is_ok());
+
+        let mut peer_ustreamer = UStreamer::new("decompressing_streamer", 100);
+        assert!(peer_ustreamer
+            .add_forwarding_rule(
+                Endpoint::new("peer_link_endpoint", "local", peer_link_transport.clone()),
+                Endpoint::new("remote_endpoint", "remote", remote_transport.clone()),
+            )
             .await
             .is_ok());
+
+        let mut small_msg = message_for_authority("remote");
+        small_msg.payload = Some(vec![0u8; 99].into());
+        let mut large_msg = message_for_authority("remote");
+        large_msg.payload = Some(vec![0u8; 1000].into());
+        local_transport.deliver(small_msg.clone()).await;
+        local_transport.deliver(large_msg.clone()).await;
+        assert!(link_transport.wait_for_sent(2).await);
+
+        let sent = link_transport.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], small_msg);
+        assert!(sent[1].payload.as_ref().unwrap().len() < 100);
+        assert_eq!(ustreamer.metrics().compressed_messages, 1);
+
+        for msg in sent {
+            peer_link_transport.deliver(msg).await;
+        }
+        assert!(remote_transport.wait_for_sent(2).await);
+
+        assert_eq!(remote_transport.sent(), vec![small_msg, large_msg]);
+        assert_eq!(peer_ustreamer.metrics().decompressed_messages, 1);
     }
 
     #[async_std::test]
-    async fn test_advanced_where_there_is_a_local_endpoint_and_two_remote_endpoints_but_the_remote_endpoints_have_the_same_instance_of_utransport(
-    ) {
-        // Local endpoint
-        let local_authority = "local";
-        let local_transport: Arc<dyn UTransport> = Arc::new(UPClientFoo);
-        let local_endpoint =
-            Endpoint::new("local_endpoint", local_authority, local_transport.clone());
+    async fn test_protected_messages_are_verified_and_decrypted_by_the_peer_streamer() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let link_transport = Arc::new(UPClientRecorder::default());
+        let peer_link_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
 
-        let remote_transport: Arc<dyn UTransport> = Arc::new(UPClientBar);
+        let mut ustreamer = UStreamer::new("protecting_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                Endpoint::new("local_endpoint", "local", local_transport.clone()),
+                Endpoint::new("link_endpoint", "remote", link_transport.clone()),
+                ForwardingRuleOptions::new().with_protection(PayloadProtection {
+                    key_id: 1,
+                    signing: Some(Signing::HmacSha256 {
+                        key: b"shared secret".to_vec(),
+                    }),
+                    encryption_key: Some([0x42; 32]),
+                }),
+            )
+            .await
+            .is_ok());
 
-        // Remote endpoint - A
-        let remote_authority_a = "remote_a";
-        let remote_endpoint_a = Endpoint::new(
-            "remote_endpoint_a",
-            remote_authority_a,
-            remote_transport.clone(),
+        let mut peer_ustreamer = UStreamer::new("verifying_streamer", 100);
+        peer_ustreamer.set_protection_keys(
+            ProtectionKeys::new()
+                .with_hmac_key(1, b"shared secret".to_vec())
+                .with_encryption_key(1, [0x42; 32]),
         );
+        assert!(peer_ustreamer
+            .add_forwarding_rule_with_options(
+                Endpoint::new("peer_link_endpoint", "local", peer_link_transport.clone()),
+                Endpoint::new("remote_endpoint", "remote", remote_transport.clone()),
+                ForwardingRuleOptions::new().with_protection_required(ProtectionRequirement {
+                    key_id: Some(1),
+                    encrypted: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .is_ok());
 
-        // Remote endpoint - B
-        let remote_authority_b = "remote_b";
-        let remote_endpoint_b = Endpoint::new(
-            "remote_endpoint_b",
-            remote_authority_b,
-            remote_transport.clone(),
+        let mut msg = message_for_authority("remote");
+        msg.payload = Some(b"secret".to_vec().into());
+        local_transport.deliver(msg.clone()).await;
+        assert!(link_transport.wait_for_sent(1).await);
+
+        let sent = link_transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_ne!(sent[0].payload, msg.payload);
+        assert_eq!(ustreamer.metrics().protected_messages, 1);
+
+        peer_link_transport.deliver(sent[0].clone()).await;
+        // an unprotected message injected on the link is dropped
+        peer_link_transport.deliver(msg.clone()).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport.sent(), vec![msg]);
+        assert_eq!(peer_ustreamer.metrics().protection_failures, 1);
+    }
+
+    #[async_std::test]
+    async fn test_replayed_messages_are_rejected_on_the_in_endpoint() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule(
+                Endpoint::new("local_endpoint", "local", local_transport.clone())
+                    .with_replay_protection(ReplayProtectionConfig::default()),
+                Endpoint::new("remote_endpoint", "remote", remote_transport.clone()),
+            )
+            .await
+            .is_ok());
+
+        let mut msg = message_for_authority("remote");
+        msg.attributes.mut_or_insert_default().id = Some(UUIDBuilder::build()).into();
+        let mut stale_msg = message_for_authority("remote");
+        stale_msg.attributes.mut_or_insert_default().id = Some(UUID {
+            msb: 1 << 16,
+            lsb: 1,
+            ..Default::default()
+        })
+        .into();
+
+        local_transport.deliver(msg.clone()).await;
+        local_transport.deliver(msg.clone()).await;
+        local_transport.deliver(stale_msg).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport.sent(), vec![msg]);
+        assert_eq!(ustreamer.metrics().replays_rejected, 2);
+    }
+
+    #[async_std::test]
+    async fn test_every_rule_from_a_replay_protected_in_endpoint_forwards() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport_zenoh = Arc::new(UPClientRecorder::default());
+        let remote_transport_someip = Arc::new(UPClientRecorder::default());
+
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone())
+            .with_replay_protection(ReplayProtectionConfig::default());
+        let remote_endpoint_zenoh = Endpoint::new(
+            "remote_endpoint_zenoh",
+            "remote",
+            remote_transport_zenoh.clone(),
+        );
+        let remote_endpoint_someip = Endpoint::new(
+            "remote_endpoint_someip",
+            "remote",
+            remote_transport_someip.clone(),
         );
 
         let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        for remote_endpoint in [remote_endpoint_zenoh, remote_endpoint_someip] {
+            assert!(ustreamer
+                .add_forwarding_rule(local_endpoint.clone(), remote_endpoint)
+                .await
+                .is_ok());
+        }
 
-        // Add forwarding rules to endpoint local<->remote_a
+        let mut msg = message_for_authority("remote");
+        msg.attributes.mut_or_insert_default().id = Some(UUIDBuilder::build()).into();
+        local_transport.deliver(msg.clone()).await;
+        local_transport.deliver(msg.clone()).await;
+        assert!(remote_transport_zenoh.wait_for_sent(1).await);
+        assert!(remote_transport_someip.wait_for_sent(1).await);
+
+        assert_eq!(remote_transport_zenoh.sent(), vec![msg.clone()]);
+        assert_eq!(remote_transport_someip.sent(), vec![msg]);
+        // the repeated delivery is a replay to either rule
+        assert_eq!(ustreamer.metrics().replays_rejected, 2);
+    }
+
+    #[async_std::test]
+    async fn test_responses_are_correlated_with_forwarded_requests() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
         assert!(ustreamer
-            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_a.clone())
+            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint.clone())
             .await
             .is_ok());
         assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint_a.clone(), local_endpoint.clone())
+            .add_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
             .await
             .is_ok());
 
-        // Add forwarding rules to endpoint local<->remote_b
+        let request = UMessageBuilder::request(
+            UUri {
+                authority_name: "remote".to_string(),
+                ue_id: 0x4,
+                ue_version_major: 1,
+                resource_id: 0x1,
+                ..Default::default()
+            },
+            UUri {
+                authority_name: "local".to_string(),
+                ue_id: 0x1101,
+                ue_version_major: 1,
+                resource_id: 0x0,
+                ..Default::default()
+            },
+            1000,
+        )
+        .build()
+        .unwrap();
+        local_transport.deliver(request.clone()).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        let response = UMessageBuilder::response_for_request(request.attributes.as_ref().unwrap())
+            .build()
+            .unwrap();
+        remote_transport.deliver(response.clone()).await;
+        // answering it twice leaves the second response without a request
+        remote_transport.deliver(response).await;
+        assert!(local_transport.wait_for_sent(2).await);
+
+        assert_eq!(local_transport.sent_count(), 2);
+        assert_eq!(ustreamer.metrics().orphaned_responses, 1);
+        let rpc_latency_stats = ustreamer.rpc_latency_stats();
+        assert_eq!(rpc_latency_stats.len(), 1);
+        assert_eq!(rpc_latency_stats[0].authority_name, "remote");
+        assert_eq!(rpc_latency_stats[0].ue_id, 0x4);
+        assert_eq!(rpc_latency_stats[0].responses, 1);
+    }
+
+    #[async_std::test]
+    async fn test_expired_requests_are_answered_with_deadline_exceeded() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        ustreamer.set_request_tracking_config(RequestTrackingConfig {
+            expiry_check_interval: Duration::from_millis(10),
+            ..Default::default()
+        });
         assert!(ustreamer
-            .add_forwarding_rule(local_endpoint.clone(), remote_endpoint_b.clone())
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_deadline_exceeded_responses(),
+            )
             .await
             .is_ok());
         assert!(ustreamer
-            .add_forwarding_rule(remote_endpoint_b.clone(), local_endpoint.clone())
+            .add_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
+            .await
+            .is_ok());
+
+        let request = || {
+            UMessageBuilder::request(
+                UUri {
+                    authority_name: "remote".to_string(),
+                    ue_id: 0x4,
+                    ue_version_major: 1,
+                    resource_id: 0x1,
+                    ..Default::default()
+                },
+                UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: 0x1101,
+                    ue_version_major: 1,
+                    resource_id: 0x0,
+                    ..Default::default()
+                },
+                100,
+            )
+            .build()
+            .unwrap()
+        };
+        let answered_request = request();
+        let unanswered_request = request();
+        local_transport.deliver(answered_request.clone()).await;
+        local_transport.deliver(unanswered_request.clone()).await;
+        remote_transport
+            .deliver(
+                UMessageBuilder::response_for_request(
+                    answered_request.attributes.as_ref().unwrap(),
+                )
+                .build()
+                .unwrap(),
+            )
+            .await;
+        // the unanswered request is given up on once its TTL of 100ms has passed
+        assert!(local_transport.wait_for_sent(2).await);
+
+        let responses = local_transport.sent();
+        assert_eq!(responses.len(), 2);
+        let deadline_exceeded: Vec<_> = responses
+            .iter()
+            .filter(|response| {
+                response.attributes.as_ref().unwrap().commstatus
+                    == Some(UCode::DEADLINE_EXCEEDED.into())
+            })
+            .collect();
+        assert_eq!(deadline_exceeded.len(), 1);
+        assert_eq!(
+            deadline_exceeded[0].attributes.as_ref().unwrap().reqid,
+            unanswered_request.attributes.as_ref().unwrap().id
+        );
+        assert_eq!(ustreamer.metrics().deadline_exceeded_responses, 1);
+    }
+
+    #[async_std::test]
+    async fn test_requests_given_up_on_are_answered_with_deadline_exceeded() {
+        let (local_transport, local_endpoint, remote_transport, remote_endpoint) =
+            local_and_remote_endpoints();
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        ustreamer.set_request_tracking_config(RequestTrackingConfig {
+            max_outstanding_requests: 1,
+            ..Default::default()
+        });
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_deadline_exceeded_responses(),
+            )
+            .await
+            .is_ok());
+
+        let request = || {
+            UMessageBuilder::request(
+                UUri {
+                    authority_name: "remote".to_string(),
+                    ue_id: 0x4,
+                    ue_version_major: 1,
+                    resource_id: 0x1,
+                    ..Default::default()
+                },
+                UUri {
+                    authority_name: "local".to_string(),
+                    ue_id: 0x1101,
+                    ue_version_major: 1,
+                    resource_id: 0x0,
+                    ..Default::default()
+                },
+                60_000,
+            )
+            .build()
+            .unwrap()
+        };
+        let oldest_request = request();
+        local_transport.deliver(oldest_request.clone()).await;
+        local_transport.deliver(request()).await;
+        assert!(remote_transport.wait_for_sent(2).await);
+
+        let responses = local_transport.sent();
+        assert_eq!(responses.len(), 1);
+        let attributes = responses[0].attributes.as_ref().unwrap();
+        assert_eq!(attributes.commstatus, Some(UCode::DEADLINE_EXCEEDED.into()));
+        assert_eq!(
+            attributes.reqid,
+            oldest_request.attributes.as_ref().unwrap().id
+        );
+        assert_eq!(ustreamer.metrics().expired_requests, 1);
+        assert_eq!(ustreamer.metrics().deadline_exceeded_responses, 1);
+    }
+
+    #[async_std::test]
+    async fn test_retransmitted_requests_are_answered_from_the_response_cache() {
+        let local_transport = Arc::new(UPClientRecorder::default());
+        let remote_transport = Arc::new(UPClientRecorder::default());
+
+        // retransmissions reuse the id of the request, which replay protection mustn't get in
+        // the way of
+        let local_endpoint = Endpoint::new("local_endpoint", "local", local_transport.clone())
+            .with_replay_protection(ReplayProtectionConfig::default());
+        let remote_endpoint = Endpoint::new("remote_endpoint", "remote", remote_transport.clone());
+
+        let mut ustreamer = UStreamer::new("foo_bar_streamer", 100);
+        assert!(ustreamer
+            .add_forwarding_rule_with_options(
+                local_endpoint.clone(),
+                remote_endpoint.clone(),
+                ForwardingRuleOptions::new().with_response_cache(ResponseCacheConfig::default()),
+            )
+            .await
+            .is_ok());
+        assert!(ustreamer
+            .add_forwarding_rule(remote_endpoint.clone(), local_endpoint.clone())
             .await
             .is_ok());
+
+        let request = UMessageBuilder::request(
+            UUri {
+                authority_name: "remote".to_string(),
+                ue_id: 0x4,
+                ue_version_major: 1,
+                resource_id: 0x1,
+                ..Default::default()
+            },
+            UUri {
+                authority_name: "local".to_string(),
+                ue_id: 0x1101,
+                ue_version_major: 1,
+                resource_id: 0x0,
+                ..Default::default()
+            },
+            1000,
+        )
+        .build()
+        .unwrap();
+        local_transport.deliver(request.clone()).await;
+        // still awaiting its response
+        local_transport.deliver(request.clone()).await;
+        assert!(remote_transport.wait_for_sent(1).await);
+
+        let response = UMessageBuilder::response_for_request(request.attributes.as_ref().unwrap())
+            .build()
+            .unwrap();
+        remote_transport.deliver(response.clone()).await;
+        assert!(local_transport.wait_for_sent(1).await);
+        local_transport.deliver(request.clone()).await;
+        assert!(local_transport.wait_for_sent(2).await);
+
+        // the same request id from another requester doesn't get its response, but is taken for
+        // the replay it is
+        let mut other_request = request;
+        other_request
+            .attributes
+            .mut_or_insert_default()
+            .source
+            .mut_or_insert_default()
+            .ue_id = 0x1102;
+        local_transport.deliver(other_request).await;
+
+        assert_eq!(remote_transport.sent_count(), 1);
+        assert_eq!(local_transport.sent(), vec![response.clone(), response]);
+        let metrics = ustreamer.metrics();
+        assert_eq!(metrics.duplicate_requests_dropped, 1);
+        assert_eq!(metrics.cached_responses_sent, 1);
+        assert_eq!(metrics.replays_rejected, 1);
     }
 }
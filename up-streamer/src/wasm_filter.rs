@@ -0,0 +1,545 @@
+/********************************************************************************
+ * Copyright (c) 2024 Contributors to the Eclipse Foundation
+ *
+ * See the NOTICE file(s) distributed with this work for additional
+ * information regarding copyright ownership.
+ *
+ * This program and the accompanying materials are made available under the
+ * terms of the Apache License Version 2.0 which is available at
+ * https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ ********************************************************************************/
+
+use log::*;
+use protobuf::Message;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use up_rust::{UAttributes, UCode, UMessage, UStatus};
+use wasmi::core::TrapCode;
+use wasmi::{
+    Caller, Config, Engine, Error, Extern, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+const WASM_FILTER_TAG: &str = "WasmFilter:";
+const WASM_FILTER_FN_HOT_RELOAD_TAG: &str = "hot_reload():";
+
+const ABI_MODULE: &str = "up_streamer";
+const ENTRY_POINT: &str = "on_message";
+
+const VERDICT_FORWARD: i32 = 0;
+const VERDICT_DROP: i32 = 1;
+const VERDICT_REJECT: i32 = 2;
+
+/// Bounds a single run of a [`WasmFilter`]
+///
+/// # Examples
+///
+/// ```
+/// use up_streamer::WasmFilterLimits;
+///
+/// // a filter which decodes payloads needs a little more room
+/// let wasm_filter_limits = WasmFilterLimits {
+///     fuel: 10_000_000,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WasmFilterLimits {
+    /// Fuel a run may use up, about one unit per instruction executed
+    pub fuel: u64,
+    /// Most bytes of linear memory a module may grow to
+    pub max_memory_size: usize,
+}
+
+impl Default for WasmFilterLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 1_000_000,
+            max_memory_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// What a [`WasmFilter`] decided about a message
+#[derive(Debug, PartialEq)]
+pub(crate) enum WasmVerdict {
+    /// The message is forwarded, as the filter may have rewritten it
+    Forward(UMessage),
+    /// The message is dropped
+    Drop,
+    /// The message is dropped, a request is answered with PERMISSION_DENIED
+    Reject,
+}
+
+/// A WebAssembly module which filters and rewrites the messages a forwarding rule forwards, used
+/// with [`ForwardingRuleOptions::with_wasm_filter`][crate::ForwardingRuleOptions::with_wasm_filter]
+///
+/// The module is run in an interpreter, isolated from the streamer and the host, and can be
+/// written in any language compiling to `wasm32`. It exports its `memory` and
+/// `on_message() -> i32`, which returns `0` to forward the message, `1` to drop it or `2` to
+/// reject it. It may import from the `up_streamer` module:
+///
+/// | function                       | does                                                  |
+/// |--------------------------------|-------------------------------------------------------|
+/// | `attributes_len() -> i32`      | the size of the message's protobuf encoded attributes |
+/// | `read_attributes(ptr: i32)`    | copies the encoded attributes to `ptr`                |
+/// | `payload_len() -> i32`         | the size of the message's payload                     |
+/// | `read_payload(ptr: i32)`       | copies the payload to `ptr`                           |
+/// | `set_attributes(ptr, len: i32)`| replaces the attributes with those encoded at `ptr`   |
+/// | `set_payload(ptr, len: i32)`   | replaces the payload with the bytes at `ptr`          |
+///
+/// Each message is handled by a fresh instance of the module, bounded by [`WasmFilterLimits`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use up_streamer::{WasmFilter, WasmFilterLimits};
+///
+/// let wasm_filter = WasmFilter::from_file("filters/door_ecu.wasm", WasmFilterLimits::default())
+///     .unwrap()
+///     .with_hot_reload(Duration::from_secs(5));
+/// ```
+#[derive(Clone)]
+pub struct WasmFilter {
+    inner: Arc<WasmFilterInner>,
+}
+
+struct LoadedModule {
+    module: Module,
+    // when the file it was loaded from was last modified, if it was
+    modified: Option<SystemTime>,
+}
+
+struct WasmFilterInner {
+    engine: Engine,
+    limits: WasmFilterLimits,
+    path: Option<PathBuf>,
+    loaded: RwLock<LoadedModule>,
+}
+
+// what a single run of the module works on
+#[derive(Default)]
+struct Invocation {
+    attributes: Vec<u8>,
+    payload: Vec<u8>,
+    set_attributes: Option<Vec<u8>>,
+    set_payload: Option<Vec<u8>>,
+    store_limits: StoreLimits,
+}
+
+impl WasmFilter {
+    /// Loads the module from its binary format, failing with
+    /// [`UCode::INVALID_ARGUMENT`] if it isn't valid or doesn't keep to the ABI
+    pub fn new(wasm: &[u8], limits: WasmFilterLimits) -> Result<Self, UStatus> {
+        let engine = metered_engine();
+        let module = compile(&engine, &limits, wasm)?;
+        Ok(Self::from_module(engine, limits, None, module, None))
+    }
+
+    /// Loads the module from the file at `path`, failing with [`UCode::NOT_FOUND`] if it can't
+    /// be read
+    pub fn from_file(path: impl AsRef<Path>, limits: WasmFilterLimits) -> Result<Self, UStatus> {
+        let path = path.as_ref();
+        let (wasm, modified) = read_module_file(path)?;
+        let engine = metered_engine();
+        let module = compile(&engine, &limits, &wasm)?;
+        Ok(Self::from_module(
+            engine,
+            limits,
+            Some(path.to_path_buf()),
+            module,
+            modified,
+        ))
+    }
+
+    fn from_module(
+        engine: Engine,
+        limits: WasmFilterLimits,
+        path: Option<PathBuf>,
+        module: Module,
+        modified: Option<SystemTime>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(WasmFilterInner {
+                engine,
+                limits,
+                path,
+                loaded: RwLock::new(LoadedModule { module, modified }),
+            }),
+        }
+    }
+
+    /// Checks the file the module was loaded from every `interval` and reloads it once it has
+    /// changed, for as long as the filter is in use
+    ///
+    /// A changed file which can't be loaded is logged and the module loaded before kept.
+    /// Filters not loaded with [`WasmFilter::from_file`] aren't reloaded.
+    pub fn with_hot_reload(self, interval: Duration) -> Self {
+        if self.inner.path.is_some() {
+            Self::spawn_hot_reload(Arc::downgrade(&self.inner), interval);
+        }
+        self
+    }
+
+    /// Reloads the module from the file it was loaded from, keeping the module loaded before if
+    /// that fails
+    pub fn reload(&self) -> Result<(), UStatus> {
+        self.inner.reload(false).map(|_| ())
+    }
+
+    fn spawn_hot_reload(inner: Weak<WasmFilterInner>, interval: Duration) {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            match inner.reload(true) {
+                Ok(true) => info!(
+                    "{}:{} Reloaded WASM filter: {:?}",
+                    WASM_FILTER_TAG, WASM_FILTER_FN_HOT_RELOAD_TAG, inner.path
+                ),
+                Ok(false) => {}
+                Err(err) => warn!(
+                    "{}:{} Unable to reload WASM filter: {:?}, keeping the one loaded before: {err:?}",
+                    WASM_FILTER_TAG, WASM_FILTER_FN_HOT_RELOAD_TAG, inner.path
+                ),
+            }
+        });
+    }
+
+    /// Runs the module on `msg`, returning its verdict, or why it failed
+    pub(crate) fn run(&self, msg: &UMessage) -> Result<WasmVerdict, String> {
+        let attributes = msg
+            .attributes
+            .get_or_default()
+            .write_to_bytes()
+            .map_err(|err| format!("unable to serialize attributes: {err}"))?;
+        let invocation = Invocation {
+            attributes,
+            payload: msg.payload.as_deref().unwrap_or_default().to_vec(),
+            ..Default::default()
+        };
+        let module = self.inner.loaded.read().unwrap().module.clone();
+        let (verdict, invocation) =
+            run_module(&self.inner.engine, &self.inner.limits, &module, invocation).map_err(
+                |err| match err.as_trap_code() {
+                    Some(TrapCode::OutOfFuel) => "ran out of fuel".to_string(),
+                    _ => err.to_string(),
+                },
+            )?;
+
+        match verdict {
+            VERDICT_FORWARD => {}
+            VERDICT_DROP => return Ok(WasmVerdict::Drop),
+            VERDICT_REJECT => return Ok(WasmVerdict::Reject),
+            verdict => return Err(format!("returned an unknown verdict: {verdict}")),
+        }
+        let mut filtered_msg = msg.clone();
+        if let Some(attributes) = invocation.set_attributes {
+            filtered_msg.attributes = Some(
+                UAttributes::parse_from_bytes(&attributes)
+                    .map_err(|err| format!("set invalid attributes: {err}"))?,
+            )
+            .into();
+        }
+        if let Some(payload) = invocation.set_payload {
+            filtered_msg.payload = (!payload.is_empty()).then(|| payload.into());
+        }
+        Ok(WasmVerdict::Forward(filtered_msg))
+    }
+}
+
+impl WasmFilterInner {
+    // reloads the module, returning whether it did, only if the file has changed if asked to
+    fn reload(&self, only_if_modified: bool) -> Result<bool, UStatus> {
+        let Some(path) = &self.path else {
+            return Err(UStatus::fail_with_code(
+                UCode::FAILED_PRECONDITION,
+                "WASM filter wasn't loaded from a file",
+            ));
+        };
+        if only_if_modified {
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if modified == self.loaded.read().unwrap().modified {
+                return Ok(false);
+            }
+        }
+        let (wasm, modified) = read_module_file(path)?;
+        let module = compile(&self.engine, &self.limits, &wasm)?;
+        *self.loaded.write().unwrap() = LoadedModule { module, modified };
+        Ok(true)
+    }
+}
+
+fn metered_engine() -> Engine {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+fn read_module_file(path: &Path) -> Result<(Vec<u8>, Option<SystemTime>), UStatus> {
+    let wasm = std::fs::read(path).map_err(|err| {
+        UStatus::fail_with_code(
+            UCode::NOT_FOUND,
+            format!("Unable to read WASM filter: {path:?}: {err}"),
+        )
+    })?;
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    Ok((wasm, modified))
+}
+
+// compiles the module and makes sure it keeps to the ABI
+fn compile(engine: &Engine, limits: &WasmFilterLimits, wasm: &[u8]) -> Result<Module, UStatus> {
+    let invalid = |reason: String| {
+        UStatus::fail_with_code(
+            UCode::INVALID_ARGUMENT,
+            format!("Invalid WASM filter: {reason}"),
+        )
+    };
+    let module = Module::new(engine, wasm).map_err(|err| invalid(err.to_string()))?;
+    instantiate(engine, limits, &module, Invocation::default())
+        .map_err(|err| invalid(err.to_string()))?;
+    Ok(module)
+}
+
+fn instantiate(
+    engine: &Engine,
+    limits: &WasmFilterLimits,
+    module: &Module,
+    mut invocation: Invocation,
+) -> Result<(Store<Invocation>, TypedFunc<(), i32>), Error> {
+    invocation.store_limits = StoreLimitsBuilder::new()
+        .memory_size(limits.max_memory_size)
+        .build();
+    let mut store = Store::new(engine, invocation);
+    store.limiter(|invocation| &mut invocation.store_limits);
+    store.set_fuel(limits.fuel)?;
+    let instance = linker(engine)
+        .instantiate(&mut store, module)?
+        .start(&mut store)?;
+    let on_message = instance.get_typed_func::<(), i32>(&store, ENTRY_POINT)?;
+    Ok((store, on_message))
+}
+
+fn run_module(
+    engine: &Engine,
+    limits: &WasmFilterLimits,
+    module: &Module,
+    invocation: Invocation,
+) -> Result<(i32, Invocation), Error> {
+    let (mut store, on_message) = instantiate(engine, limits, module, invocation)?;
+    let verdict = on_message.call(&mut store, ())?;
+    Ok((verdict, store.into_data()))
+}
+
+fn linker(engine: &Engine) -> Linker<Invocation> {
+    let mut linker = Linker::new(engine);
+    // the names are all distinct, so defining them can't fail
+    linker
+        .func_wrap(
+            ABI_MODULE,
+            "attributes_len",
+            |caller: Caller<'_, Invocation>| -> i32 { caller.data().attributes.len() as i32 },
+        )
+        .unwrap()
+        .func_wrap(
+            ABI_MODULE,
+            "read_attributes",
+            |mut caller: Caller<'_, Invocation>, ptr: i32| -> Result<(), Error> {
+                let attributes = caller.data().attributes.clone();
+                write_guest(&mut caller, ptr, &attributes)
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            ABI_MODULE,
+            "payload_len",
+            |caller: Caller<'_, Invocation>| -> i32 { caller.data().payload.len() as i32 },
+        )
+        .unwrap()
+        .func_wrap(
+            ABI_MODULE,
+            "read_payload",
+            |mut caller: Caller<'_, Invocation>, ptr: i32| -> Result<(), Error> {
+                let payload = caller.data().payload.clone();
+                write_guest(&mut caller, ptr, &payload)
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            ABI_MODULE,
+            "set_attributes",
+            |mut caller: Caller<'_, Invocation>, ptr: i32, len: i32| -> Result<(), Error> {
+                let attributes = read_guest(&caller, ptr, len)?;
+                caller.data_mut().set_attributes = Some(attributes);
+                Ok(())
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            ABI_MODULE,
+            "set_payload",
+            |mut caller: Caller<'_, Invocation>, ptr: i32, len: i32| -> Result<(), Error> {
+                let payload = read_guest(&caller, ptr, len)?;
+                caller.data_mut().set_payload = Some(payload);
+                Ok(())
+            },
+        )
+        .unwrap();
+    linker
+}
+
+fn guest_memory(caller: &Caller<'_, Invocation>) -> Result<Memory, Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Error::new("module doesn't export its memory"))
+}
+
+fn write_guest(caller: &mut Caller<'_, Invocation>, ptr: i32, data: &[u8]) -> Result<(), Error> {
+    guest_memory(caller)?
+        .write(caller, ptr as u32 as usize, data)
+        .map_err(|err| Error::new(format!("unable to write to module memory: {err}")))
+}
+
+// copies straight out of the module's memory, so that a bogus len can't make us allocate more
+// than the module itself holds
+fn read_guest(caller: &Caller<'_, Invocation>, ptr: i32, len: i32) -> Result<Vec<u8>, Error> {
+    let memory = guest_memory(caller)?;
+    let start = ptr as u32 as usize;
+    start
+        .checked_add(len as u32 as usize)
+        .and_then(|end| memory.data(caller).get(start..end))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| Error::new("unable to read from module memory: out of bounds"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{WasmFilter, WasmFilterLimits, WasmVerdict};
+    use std::time::Duration;
+    use up_rust::{UAttributes, UMessage, UMessageType};
+
+    /// Assembles a module from its text format
+    pub(crate) fn wasm(wat: &str) -> Vec<u8> {
+        wat::parse_str(wat).unwrap()
+    }
+
+    /// Drops messages whose payload starts with `d`, rejects those starting with `r` and replaces
+    /// the payload of any other with `rewritten`
+    pub(crate) const REWRITING_FILTER: &str = r#"
+        (module
+            (import "up_streamer" "read_payload" (func $read_payload (param i32)))
+            (import "up_streamer" "set_payload" (func $set_payload (param i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 1024) "rewritten")
+            (func (export "on_message") (result i32)
+                (call $read_payload (i32.const 0))
+                (if (i32.eq (i32.load8_u (i32.const 0)) (i32.const 100))
+                    (then (return (i32.const 1))))
+                (if (i32.eq (i32.load8_u (i32.const 0)) (i32.const 114))
+                    (then (return (i32.const 2))))
+                (call $set_payload (i32.const 1024) (i32.const 9))
+                (i32.const 0)))
+    "#;
+
+    const SPINNING_FILTER: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "on_message") (result i32)
+                (loop $spin (br $spin))
+                (i32.const 0)))
+    "#;
+
+    pub(crate) fn message(payload: &str) -> UMessage {
+        UMessage {
+            attributes: Some(UAttributes {
+                type_: UMessageType::UMESSAGE_TYPE_PUBLISH.into(),
+                ..Default::default()
+            })
+            .into(),
+            payload: Some(payload.as_bytes().to_vec().into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_verdicts_and_rewrites_are_returned() {
+        let wasm_filter =
+            WasmFilter::new(&wasm(REWRITING_FILTER), WasmFilterLimits::default()).unwrap();
+
+        assert_eq!(
+            wasm_filter.run(&message("hello")),
+            Ok(WasmVerdict::Forward(message("rewritten")))
+        );
+        assert_eq!(wasm_filter.run(&message("drop")), Ok(WasmVerdict::Drop));
+        assert_eq!(wasm_filter.run(&message("reject")), Ok(WasmVerdict::Reject));
+    }
+
+    #[test]
+    fn test_modules_are_checked_and_metered() {
+        assert!(WasmFilter::new(b"not wasm", WasmFilterLimits::default()).is_err());
+        let unknown_import = r#"
+            (module
+                (import "env" "open" (func $open (param i32)))
+                (memory (export "memory") 1)
+                (func (export "on_message") (result i32) (i32.const 0)))
+        "#;
+        assert!(WasmFilter::new(&wasm(unknown_import), WasmFilterLimits::default()).is_err());
+
+        let wasm_filter = WasmFilter::new(
+            &wasm(SPINNING_FILTER),
+            WasmFilterLimits {
+                fuel: 10_000,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            wasm_filter.run(&message("hello")),
+            Err("ran out of fuel".to_string())
+        );
+
+        // a length beyond the module's memory is refused rather than allocated
+        let overreaching = r#"
+            (module
+                (import "up_streamer" "set_payload" (func $set_payload (param i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "on_message") (result i32)
+                    (call $set_payload (i32.const 0) (i32.const -1))
+                    (i32.const 0)))
+        "#;
+        let wasm_filter =
+            WasmFilter::new(&wasm(overreaching), WasmFilterLimits::default()).unwrap();
+        assert!(wasm_filter.run(&message("hello")).is_err());
+    }
+
+    #[test]
+    fn test_modules_are_reloaded_once_their_file_changes() {
+        let path = std::env::temp_dir().join(format!("wasm_filter_{}.wasm", std::process::id()));
+        std::fs::write(&path, wasm(SPINNING_FILTER)).unwrap();
+        let wasm_filter = WasmFilter::from_file(&path, WasmFilterLimits::default())
+            .unwrap()
+            .with_hot_reload(Duration::from_millis(10));
+        assert!(wasm_filter.run(&message("hello")).is_err());
+
+        // make sure the modification time differs on coarse clocks
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(&path, wasm(REWRITING_FILTER)).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            wasm_filter.run(&message("hello")),
+            Ok(WasmVerdict::Forward(message("rewritten")))
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}